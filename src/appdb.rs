@@ -1,19 +1,26 @@
 use anyhow::{Context, Result};
 use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::collections::BTreeSet;
 use std::path::Path;
+use std::sync::OnceLock;
 use uuid::Uuid;
-use crate::utils::{now_utc_micro, validate_id};
+use crate::cli::OutputFormat;
+use crate::db::DatabaseConfig;
+use crate::error::CwhError;
+use crate::utils::{json_escape, now_utc_micro, validate_id};
+use crate::output::say;
 
 /// Opens the app.db connection if a path is provided.
-pub(crate) fn open_appdb(path: Option<&Path>) -> Result<Option<Connection>> {
-    path.map(crate::db::open_appdb)
+pub(crate) fn open_appdb(path: Option<&Path>, config: &DatabaseConfig) -> Result<Option<Connection>> {
+    path.map(|p| crate::db::open_appdb(p, config))
         .transpose()
 }
 
-/// Lists all unique shelves from the Calibre-Web app.db.
+/// Lists all shelves from the Calibre-Web app.db with their owner, whether Kobo sync is
+/// enabled and how many books they hold.
 pub(crate) fn list_shelves(appdb_conn: Option<&Connection>) -> Result<()> {
     if let Some(conn) = appdb_conn {
-        println!("📖 Finding available shelves from Calibre-Web...");
+        say!("📖 Finding available shelves from Calibre-Web...");
 
         let mut stmt = conn.prepare(
             "SELECT s.id, s.name, s.kobo_sync, u.name as username, COUNT(bsl.book_id) as book_count
@@ -37,14 +44,14 @@ pub(crate) fn list_shelves(appdb_conn: Option<&Connection>) -> Result<()> {
         let shelves: Vec<(i64, String, i64, Option<String>, i64)> = shelves_iter.collect::<Result<Vec<_>, _>>()?;
 
         if shelves.is_empty() {
-            println!("\nNo shelves found in the Calibre-Web database.");
+            say!("\nNo shelves found in the Calibre-Web database.");
         } else {
-            println!("\nAvailable shelves:");
+            say!("\nAvailable shelves:");
             for (id, shelf_name, kobo_sync, username, book_count) in shelves {
                 let user_display = username.unwrap_or_else(|| "Unknown".to_string());
                 let kobo_indicator = if kobo_sync == 1 { " [Kobo]" } else { "" };
                 let book_text = if book_count == 1 { "book" } else { "books" };
-                println!("- {} (ID: {}) - User: {}{} - {} {}", 
+                say!("- {} (ID: {}) - User: {}{} - {} {}", 
                          shelf_name, id, user_display, kobo_indicator, book_count, book_text);
             }
         }
@@ -55,22 +62,64 @@ pub(crate) fn list_shelves(appdb_conn: Option<&Connection>) -> Result<()> {
     Ok(())
 }
 
-/// Resolves a username to user_id, defaulting to admin (id=1) if no username is provided
-fn resolve_user_id(tx: &rusqlite::Transaction, username: Option<&str>) -> Result<i64> {
+/// Set by `--default-user`: the user ID used when a command is given no `--username`
+static DEFAULT_USER_ID: OnceLock<i64> = OnceLock::new();
+
+/// Resolves the `--default-user` name once, so commands without `--username` use that user
+/// instead of the admin user
+pub(crate) fn set_default_user(conn: &Connection, username: &str) -> Result<()> {
+    let user_id = resolve_user_id(conn, Some(username))
+        .context("Failed to resolve the default user")?;
+    DEFAULT_USER_ID.set(user_id)
+        .map_err(|_| anyhow::anyhow!("The default user was already set"))
+}
+
+/// Resolves a username to user_id. Without a username, uses the `--default-user` if one was
+/// given, falling back to the admin user (id=1) if it exists.
+pub(crate) fn resolve_user_id(conn: &Connection, username: Option<&str>) -> Result<i64> {
     if let Some(uname) = username {
-        match tx.query_row(
+        match conn.query_row(
             "SELECT id FROM user WHERE name = ?1",
             params![uname],
             |row| row.get::<_, i64>(0),
         ).optional()? {
             Some(id) => Ok(id),
-            None => anyhow::bail!("User '{}' not found", uname),
+            None => anyhow::bail!("User '{}' not found. Valid usernames: {}", uname, list_usernames(conn)?.join(", ")),
         }
+    } else if let Some(&user_id) = DEFAULT_USER_ID.get() {
+        Ok(user_id)
     } else {
-        Ok(1) // Default admin user
+        let admin_exists = conn.query_row("SELECT 1 FROM user WHERE id = 1", [], |_| Ok(()))
+            .optional()?
+            .is_some();
+        if !admin_exists {
+            anyhow::bail!("No user with ID 1 (admin) exists; pass --username or --default-user. Valid usernames: {}", list_usernames(conn)?.join(", "));
+        }
+        Ok(1)
     }
 }
 
+/// Names a user in messages: the username if one was given, otherwise the resolved user ID
+fn user_label(username: Option<&str>, user_id: i64) -> String {
+    username.map_or_else(|| format!("ID {}", user_id), str::to_string)
+}
+
+/// Returns all Calibre-Web usernames, sorted by name
+fn list_usernames(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT name FROM user ORDER BY name")?;
+    let names = stmt.query_map([], |row| row.get(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+    Ok(names)
+}
+
+/// Checks up front that every given username exists, so long operations fail before doing any work
+pub(crate) fn validate_usernames(conn: &Connection, usernames: &[&str]) -> Result<()> {
+    for username in usernames {
+        resolve_user_id(conn, Some(username))?;
+    }
+    Ok(())
+}
+
 /// Finds or creates a shelf for the given user
 fn find_or_create_shelf(tx: &rusqlite::Transaction, shelf_name: &str, user_id: i64, username: Option<&str>) -> Result<i64> {
     match tx.query_row(
@@ -89,15 +138,14 @@ fn find_or_create_shelf(tx: &rusqlite::Transaction, shelf_name: &str, user_id: i
                 "INSERT INTO shelf (uuid, name, is_public, user_id, kobo_sync, created, last_modified) VALUES (?1, ?2, 0, ?3, 0, ?4, ?5)",
                 params![uuid, shelf_name, user_id, now_micro, now_micro],
             )?;
-            println!(" -> Created new shelf '{}' for user {}.", shelf_name, 
-                    username.unwrap_or("admin"));
+            say!(" -> Created new shelf '{}' for user {}.", shelf_name, user_label(username, user_id));
             Ok(tx.last_insert_rowid())
         }
     }
 }
 
 /// Ensures complete Kobo sync setup for a book: reading state, statistics, bookmark, and book_read_link.
-/// Called by `fix_kobo_sync_issues` to repair incomplete sync records.
+/// Called by `fix_kobo_sync_issues` to repair incomplete sync records and by `prepare_book_for_kobo`.
 fn ensure_kobo_sync_setup(tx: &Transaction, book_id: i64, user_id: i64, timestamp: &str) -> Result<()> {
     // Check if reading state already exists
     let reading_state_id: Option<i64> = tx.query_row(
@@ -178,13 +226,13 @@ fn ensure_kobo_sync_setup(tx: &Transaction, book_id: i64, user_id: i64, timestam
 }
 
 /// Synchronizes timestamps for all books on Kobo shelves to ensure consistent sync behavior.
-/// This function updates all books on Kobo shelves to have the same recent timestamp.
-fn sync_kobo_shelf_timestamps(tx: &Transaction, timestamp: &str) -> Result<usize> {
+/// This function updates all books on Kobo shelves (only `user_id`'s, if given) to have the same recent timestamp.
+fn sync_kobo_shelf_timestamps(tx: &Transaction, timestamp: &str, user_id: Option<i64>) -> Result<usize> {
     let updated_books = tx.execute(
         "UPDATE book_shelf_link 
          SET date_added = ?1 
-         WHERE shelf IN (SELECT id FROM shelf WHERE kobo_sync = 1)",
-        [timestamp],
+         WHERE shelf IN (SELECT id FROM shelf WHERE kobo_sync = 1 AND (?2 IS NULL OR user_id = ?2))",
+        params![timestamp, user_id],
     )?;
     
     Ok(updated_books)
@@ -193,20 +241,36 @@ fn sync_kobo_shelf_timestamps(tx: &Transaction, timestamp: &str) -> Result<usize
 /// Core function to add a book to a shelf with duplicate handling control.
 /// Matches Calibre-Web's `add_to_shelf()` behavior: insert BookShelf row,
 /// update shelf.last_modified. No proactive Kobo sync record creation.
-fn add_book_to_shelf_core(conn: &mut Connection, book_id: i64, shelf_name: &str, username: Option<&str>, allow_duplicates: bool) -> Result<bool> {
-    validate_id(book_id, "book")
-        .context("Invalid book ID for shelf operation")?;
+fn add_book_to_shelf_core(conn: &mut Connection, book_id: i64, shelf_name: &str, username: Option<&str>) -> Result<ShelfLink, CwhError> {
+    let tx = conn.transaction()
+        .context("Failed to start shelf operation transaction")?;
+    let link = link_book_to_shelf(&tx, book_id, shelf_name, username)?;
+    tx.commit()
+        .context("Failed to commit shelf link transaction")?;
+    Ok(link)
+}
+
+/// Whether `link_book_to_shelf` put the book on the shelf or found it there already
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShelfLink {
+    Added,
+    AlreadyPresent,
+}
+
+/// Links a book to a shelf (created if needed) within the caller's transaction.
+/// A book that is already on the shelf is left as it is.
+fn link_book_to_shelf(tx: &Transaction, book_id: i64, shelf_name: &str, username: Option<&str>) -> Result<ShelfLink, CwhError> {
+    if validate_id(book_id, "book").is_err() {
+        return Err(CwhError::InvalidInput(format!("Invalid book ID for shelf operation: {}", book_id)));
+    }
     
     if shelf_name.trim().is_empty() {
-        anyhow::bail!("Shelf name cannot be empty");
+        return Err(CwhError::InvalidInput("Shelf name cannot be empty".to_string()));
     }
-    
-    let tx = conn.transaction()
-        .context("Failed to start shelf operation transaction")?;
 
-    let user_id = resolve_user_id(&tx, username)
+    let user_id = resolve_user_id(tx, username)
         .context("Failed to resolve user ID for shelf operation")?;
-    let shelf_id = find_or_create_shelf(&tx, shelf_name, user_id, username)
+    let shelf_id = find_or_create_shelf(tx, shelf_name, user_id, username)
         .with_context(|| format!("Failed to find or create shelf '{}'", shelf_name))?;
 
     // Check if the link already exists to prevent duplicates
@@ -222,13 +286,7 @@ fn add_book_to_shelf_core(conn: &mut Connection, book_id: i64, shelf_name: &str,
         .is_some();
 
     if link_exists {
-        if allow_duplicates {
-            println!(" -> Book is already on shelf '{}'.", shelf_name);
-        } else {
-            println!(" -> Book {} is already on shelf '{}'.", book_id, shelf_name);
-        }
-        tx.commit()?;
-        return Ok(false);
+        return Ok(ShelfLink::AlreadyPresent);
     }
 
     // Get the next order value for this shelf (matches Calibre-Web's max(order) + 1 logic)
@@ -251,182 +309,290 @@ fn add_book_to_shelf_core(conn: &mut Connection, book_id: i64, shelf_name: &str,
         "UPDATE shelf SET last_modified = ?1 WHERE id = ?2",
         params![&now_micro, shelf_id],
     )?;
-
-    tx.commit()
-        .context("Failed to commit shelf link transaction")?;
-    Ok(true)
+    Ok(ShelfLink::Added)
 }
 
 /// Adds a book to a shelf in the Calibre-Web database. Creates the shelf if it doesn't exist.
 pub(crate) fn add_book_to_shelf_in_appdb(conn: &mut Connection, book_id: i64, shelf_name: &str, username: Option<&str>) -> Result<()> {
-    let was_added = add_book_to_shelf_core(conn, book_id, shelf_name, username, true)?;
-    
-    if was_added {
-        println!(" -> Added book to shelf '{}'.", shelf_name);
+    match add_book_to_shelf_core(conn, book_id, shelf_name, username)? {
+        ShelfLink::Added => say!(" -> Added book to shelf '{}'.", shelf_name),
+        ShelfLink::AlreadyPresent => say!(" -> Book is already on shelf '{}'.", shelf_name),
     }
     
     Ok(())
 }
 
-/// Inspects the database contents, showing relationships between books and shelves
-pub(crate) fn inspect_databases(appdb_conn: Option<&Connection>, calibre_conn: &Connection) -> Result<()> {
-    println!("\n📚 Database Inspection Report");
-    println!("═════════════════════════");
+/// Prepares a shelved book for Kobo sync (`add --for-kobo`): enables `kobo_sync` on the shelf and
+/// creates the reading state, statistics, bookmark and read link rows right away.
+pub(crate) fn prepare_book_for_kobo(conn: &mut Connection, book_id: i64, shelf_name: &str, username: Option<&str>) -> Result<()> {
+    let tx = conn.transaction()
+        .context("Failed to start Kobo setup transaction")?;
+    let user_id = resolve_user_id(&tx, username)?;
+    let shelf_id = find_or_create_shelf(&tx, shelf_name, user_id, username)?;
+    let now_micro = now_utc_micro();
 
-    // If we have an app.db connection, show shelf information
-    if let Some(conn) = appdb_conn {
-        println!("\n🔎 Shelves and Books:");
-        println!("──────────────────");
-        
-        // Get all shelves with their user information
-        let mut shelf_stmt = conn.prepare(
-            "SELECT s.id, s.name, u.name as username, s.is_public 
-             FROM shelf s 
-             LEFT JOIN user u ON s.user_id = u.id 
-             ORDER BY s.name"
-        )?;
-        
-        let shelf_rows = shelf_stmt.query_map(params![], |row| {
-            Ok((
-                row.get::<_, i64>("id")?,
-                row.get::<_, String>("name")?,
-                row.get::<_, Option<String>>("username")?,
-                row.get::<_, bool>("is_public")?,
-            ))
-        })?;
+    let enabled = tx.execute(
+        "UPDATE shelf SET kobo_sync = 1, last_modified = ?1 WHERE id = ?2 AND kobo_sync IS NOT 1",
+        params![&now_micro, shelf_id],
+    )?;
+    if enabled > 0 {
+        say!(" -> Enabled Kobo sync on shelf '{}'.", shelf_name);
+    }
 
-        for shelf_result in shelf_rows {
-            let (shelf_id, shelf_name, username, is_public) = shelf_result?;
-            println!("\nShelf: {} (ID: {})", shelf_name, shelf_id);
-            println!("  Owner: {}", username.unwrap_or_else(|| "Unknown".to_string()));
-            println!("  Public: {}", if is_public { "Yes" } else { "No" });
+    ensure_kobo_sync_setup(&tx, book_id, user_id, &now_micro)?;
+    tx.commit()
+        .context("Failed to commit Kobo setup transaction")?;
+    say!(" -> Created Kobo reading state for book {}.", book_id);
+    Ok(())
+}
 
-            // Get book IDs from this shelf
-            let mut book_stmt = conn.prepare(
-                "SELECT book_id FROM book_shelf_link WHERE shelf = ? ORDER BY book_id"
-            )?;
+/// A shelf and the books on it, as shown by `inspect-db`
+struct ShelfInspection {
+    id: i64,
+    name: String,
+    owner: Option<String>,
+    is_public: bool,
+    /// (id, title, author_sort) of each shelved book that exists in the library
+    books: Vec<(i64, String, String)>,
+}
 
-            let book_ids: Vec<i64> = book_stmt.query_map(params![shelf_id], |row| {
-                row.get::<_, i64>("book_id")
-            })?.collect::<Result<Vec<_>, _>>()?;
+/// Counts and recent additions from the Calibre library, as shown by `inspect-db`
+struct LibraryStats {
+    books: i64,
+    authors: i64,
+    series: i64,
+    /// (title, author_sort, timestamp) of the most recently added books
+    recent_books: Vec<(String, String, String)>,
+}
 
-            let mut book_count = 0;
-            println!("  Books:");
-            
-            // Look up book details in the Calibre database
-            if !book_ids.is_empty() {
-                let placeholders = book_ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-                let query = format!(
-                    "SELECT id, title, author_sort FROM books WHERE id IN ({}) ORDER BY title",
-                    placeholders
-                );
-                
-                let mut cal_stmt = calibre_conn.prepare(&query)?;
-                let params_vec: Vec<&dyn rusqlite::ToSql> = book_ids.iter()
-                    .map(|id| id as &dyn rusqlite::ToSql)
-                    .collect();
-                
-                let book_rows = cal_stmt.query_map(&params_vec[..], |row| {
-                    Ok((
-                        row.get::<_, i64>("id")?,
-                        row.get::<_, String>("title")?,
-                        row.get::<_, String>("author_sort")?,
-                    ))
-                })?;
-
-                for book_result in book_rows {
-                    let (book_id, title, author) = book_result?;
-                    println!("   - {} by {} (ID: {})", title, author, book_id);
-                    book_count += 1;
-                }
-            }
-            if book_count == 0 {
-                println!("   (No books on this shelf)");
-            }
-        }
+/// Inspects the database contents, showing relationships between books and shelves
+pub(crate) fn inspect_databases(appdb_conn: Option<&Connection>, calibre_conn: &Connection, format: OutputFormat) -> Result<()> {
+    let shelves = match appdb_conn {
+        Some(conn) => inspect_shelves(conn, calibre_conn)?,
+        None => Vec::new(),
+    };
+    let stats = inspect_library_stats(calibre_conn)?;
+    let orphaned_book_ids = match appdb_conn {
+        Some(conn) => find_orphaned_shelf_book_ids(conn, calibre_conn)?,
+        None => Vec::new(),
+    };
+
+    match format {
+        OutputFormat::Text => print_inspection_text(appdb_conn.is_some(), &shelves, &stats, &orphaned_book_ids),
+        OutputFormat::Json => println!("{}", inspection_to_json(&shelves, &stats, &orphaned_book_ids)),
     }
+    Ok(())
+}
 
-    // Show Calibre database information
-    println!("\n📚 Calibre Library Statistics:");
-    println!("─────────────────────────");
-
-    let book_count: i64 = calibre_conn.query_row("SELECT COUNT(*) FROM books", params![], |row| row.get(0))?;
-    let author_count: i64 = calibre_conn.query_row("SELECT COUNT(*) FROM authors", params![], |row| row.get(0))?;
-    let series_count: i64 = calibre_conn.query_row("SELECT COUNT(*) FROM series", params![], |row| row.get(0))?;
-
-    println!("Total Books: {}", book_count);
-    println!("Total Authors: {}", author_count);
-    println!("Total Series: {}", series_count);
-
-    if book_count > 0 {
-            println!("\nRecent Books:");
-        let mut recent_stmt = calibre_conn.prepare(
-            "SELECT title, author_sort, timestamp 
-             FROM books 
-             ORDER BY timestamp DESC 
-             LIMIT 5"
-        )?;
-        
-        let recent_rows = recent_stmt.query_map(params![], |row| {
-            Ok((
-                row.get::<_, String>("title")?,
-                row.get::<_, String>("author_sort")?,
-                row.get::<_, String>("timestamp")?,
-            ))
-        })?;
+/// Gets all shelves with their owner and the library books on them
+fn inspect_shelves(conn: &Connection, calibre_conn: &Connection) -> Result<Vec<ShelfInspection>> {
+    let mut shelf_stmt = conn.prepare(
+        "SELECT s.id, s.name, u.name as username, s.is_public 
+         FROM shelf s 
+         LEFT JOIN user u ON s.user_id = u.id 
+         ORDER BY s.name"
+    )?;
 
-        for recent_result in recent_rows {
-            let (title, author, timestamp) = recent_result?;
-            println!(" - {} by {} (Added: {})", title, author, timestamp);
-        }
-    }
+    let shelf_rows = shelf_stmt.query_map(params![], |row| {
+        Ok((
+            row.get::<_, i64>("id")?,
+            row.get::<_, String>("name")?,
+            row.get::<_, Option<String>>("username")?,
+            row.get::<_, bool>("is_public")?,
+        ))
+    })?.collect::<Result<Vec<_>, _>>()?;
 
-    // Check for any shelf links to non-existent books
-    if let Some(conn) = appdb_conn {
-        let mut orphaned_stmt = conn.prepare(
-            "SELECT DISTINCT book_id FROM book_shelf_link ORDER BY book_id"
+    let mut shelves = Vec::new();
+    for (id, name, owner, is_public) in shelf_rows {
+        // Get book IDs from this shelf
+        let mut book_stmt = conn.prepare(
+            "SELECT book_id FROM book_shelf_link WHERE shelf = ? ORDER BY book_id"
         )?;
-        
-        let orphaned_books: Vec<i64> = orphaned_stmt.query_map(params![], |row| {
+
+        let book_ids: Vec<i64> = book_stmt.query_map(params![id], |row| {
             row.get::<_, i64>("book_id")
         })?.collect::<Result<Vec<_>, _>>()?;
 
-        if !orphaned_books.is_empty() {
-            let placeholders = orphaned_books.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+        // Look up book details in the Calibre database
+        let mut books = Vec::new();
+        if !book_ids.is_empty() {
+            let placeholders = book_ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
             let query = format!(
-                "SELECT id FROM books WHERE id IN ({})",
+                "SELECT id, title, author_sort FROM books WHERE id IN ({}) ORDER BY title",
                 placeholders
             );
-            
+
             let mut cal_stmt = calibre_conn.prepare(&query)?;
-            let params_vec: Vec<&dyn rusqlite::ToSql> = orphaned_books.iter()
+            let params_vec: Vec<&dyn rusqlite::ToSql> = book_ids.iter()
                 .map(|id| id as &dyn rusqlite::ToSql)
                 .collect();
-            
-            let existing_books: std::collections::HashSet<i64> = cal_stmt.query_map(&params_vec[..], |row| {
-                row.get::<_, i64>("id")
-            })?.collect::<Result<_, _>>()?;
 
-            let missing_books: Vec<_> = orphaned_books.iter()
-                .filter(|id| !existing_books.contains(id))
-                .collect();
+            books = cal_stmt.query_map(&params_vec[..], |row| {
+                Ok((
+                    row.get::<_, i64>("id")?,
+                    row.get::<_, String>("title")?,
+                    row.get::<_, String>("author_sort")?,
+                ))
+            })?.collect::<Result<Vec<_>, _>>()?;
+        }
+
+        shelves.push(ShelfInspection { id, name, owner, is_public, books });
+    }
+    Ok(shelves)
+}
+
+/// Gets the library totals and the five most recently added books
+fn inspect_library_stats(calibre_conn: &Connection) -> Result<LibraryStats> {
+    let books: i64 = calibre_conn.query_row("SELECT COUNT(*) FROM books", params![], |row| row.get(0))?;
+    let authors: i64 = calibre_conn.query_row("SELECT COUNT(*) FROM authors", params![], |row| row.get(0))?;
+    let series: i64 = calibre_conn.query_row("SELECT COUNT(*) FROM series", params![], |row| row.get(0))?;
+
+    let mut recent_stmt = calibre_conn.prepare(
+        "SELECT title, author_sort, timestamp 
+         FROM books 
+         ORDER BY timestamp DESC 
+         LIMIT 5"
+    )?;
+
+    let recent_books = recent_stmt.query_map(params![], |row| {
+        Ok((
+            row.get::<_, String>("title")?,
+            row.get::<_, String>("author_sort")?,
+            row.get::<_, String>("timestamp")?,
+        ))
+    })?.collect::<Result<Vec<_>, _>>()?;
+
+    Ok(LibraryStats { books, authors, series, recent_books })
+}
+
+/// Finds book IDs linked to shelves that don't exist in the Calibre library
+fn find_orphaned_shelf_book_ids(conn: &Connection, calibre_conn: &Connection) -> Result<Vec<i64>> {
+    let mut orphaned_stmt = conn.prepare(
+        "SELECT DISTINCT book_id FROM book_shelf_link ORDER BY book_id"
+    )?;
+
+    let linked_books: Vec<i64> = orphaned_stmt.query_map(params![], |row| {
+        row.get::<_, i64>("book_id")
+    })?.collect::<Result<Vec<_>, _>>()?;
+
+    if linked_books.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let placeholders = linked_books.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+    let query = format!(
+        "SELECT id FROM books WHERE id IN ({})",
+        placeholders
+    );
+
+    let mut cal_stmt = calibre_conn.prepare(&query)?;
+    let params_vec: Vec<&dyn rusqlite::ToSql> = linked_books.iter()
+        .map(|id| id as &dyn rusqlite::ToSql)
+        .collect();
+
+    let existing_books: std::collections::HashSet<i64> = cal_stmt.query_map(&params_vec[..], |row| {
+        row.get::<_, i64>("id")
+    })?.collect::<Result<_, _>>()?;
 
-            if !missing_books.is_empty() {
-                println!("\n⚠️  Warning: Found shelf links to non-existent books:");
-                for book_id in missing_books {
-                    println!("   - Book ID: {}", book_id);
-                }
-                println!("\nYou can use the 'clean-shelves' command to remove these orphaned links.");
+    Ok(linked_books.into_iter()
+        .filter(|id| !existing_books.contains(id))
+        .collect())
+}
+
+fn print_inspection_text(has_appdb: bool, shelves: &[ShelfInspection], stats: &LibraryStats, orphaned_book_ids: &[i64]) {
+    say!("\n📚 Database Inspection Report");
+    say!("═════════════════════════");
+
+    // If we have an app.db connection, show shelf information
+    if has_appdb {
+        say!("\n🔎 Shelves and Books:");
+        say!("──────────────────");
+
+        for shelf in shelves {
+            say!("\nShelf: {} (ID: {})", shelf.name, shelf.id);
+            say!("  Owner: {}", shelf.owner.as_deref().unwrap_or("Unknown"));
+            say!("  Public: {}", if shelf.is_public { "Yes" } else { "No" });
+            say!("  Books:");
+            for (book_id, title, author) in &shelf.books {
+                say!("   - {} by {} (ID: {})", title, author, book_id);
+            }
+            if shelf.books.is_empty() {
+                say!("   (No books on this shelf)");
             }
         }
     }
 
-    println!("\n");
-    Ok(())
+    // Show Calibre database information
+    say!("\n📚 Calibre Library Statistics:");
+    say!("─────────────────────────");
+    say!("Total Books: {}", stats.books);
+    say!("Total Authors: {}", stats.authors);
+    say!("Total Series: {}", stats.series);
+
+    if !stats.recent_books.is_empty() {
+        say!("\nRecent Books:");
+        for (title, author, timestamp) in &stats.recent_books {
+            say!(" - {} by {} (Added: {})", title, author, timestamp);
+        }
+    }
+
+    if !orphaned_book_ids.is_empty() {
+        say!("\n⚠️  Warning: Found shelf links to non-existent books:");
+        for book_id in orphaned_book_ids {
+            say!("   - Book ID: {}", book_id);
+        }
+        say!("\nYou can use the 'clean-shelves' command to remove these orphaned links.");
+    }
+
+    say!("\n");
+}
+
+/// Serializes the inspection results as a single JSON document
+fn inspection_to_json(shelves: &[ShelfInspection], stats: &LibraryStats, orphaned_book_ids: &[i64]) -> String {
+    let shelves_json: Vec<String> = shelves.iter().map(|shelf| {
+        let books: Vec<String> = shelf.books.iter()
+            .map(|(id, title, author_sort)| format!(
+                "{{\"id\": {}, \"title\": \"{}\", \"author_sort\": \"{}\"}}",
+                id, json_escape(title), json_escape(author_sort)
+            ))
+            .collect();
+        format!(
+            "{{\"id\": {}, \"name\": \"{}\", \"owner\": {}, \"is_public\": {}, \"books\": [{}]}}",
+            shelf.id,
+            json_escape(&shelf.name),
+            shelf.owner.as_deref().map_or("null".to_string(), |owner| format!("\"{}\"", json_escape(owner))),
+            shelf.is_public,
+            books.join(", "),
+        )
+    }).collect();
+
+    let recent_json: Vec<String> = stats.recent_books.iter()
+        .map(|(title, author_sort, added)| format!(
+            "{{\"title\": \"{}\", \"author_sort\": \"{}\", \"added\": \"{}\"}}",
+            json_escape(title), json_escape(author_sort), json_escape(added)
+        ))
+        .collect();
+
+    let orphaned_json: Vec<String> = orphaned_book_ids.iter().map(i64::to_string).collect();
+
+    format!(
+        "{{\"shelves\": [{}], \"orphaned_book_ids\": [{}], \"library_stats\": {{\"books\": {}, \"authors\": {}, \"series\": {}, \"recent_books\": [{}]}}}}",
+        shelves_json.join(", "),
+        orphaned_json.join(", "),
+        stats.books,
+        stats.authors,
+        stats.series,
+        recent_json.join(", "),
+    )
 }
 
-pub(crate) fn clean_empty_shelves(appdb_conn: &mut Connection, calibre_conn: &Connection) -> Result<()> {
-    println!("🧹 Cleaning empty shelves from Calibre-Web...");
+/// With `report_only`, prints the orphaned links and the shelves that would be removed without deleting anything.
+pub(crate) fn clean_empty_shelves(appdb_conn: &mut Connection, calibre_conn: &Connection, report_only: bool) -> Result<()> {
+    if report_only {
+        say!("🔍 Checking Calibre-Web shelves for orphaned links and empty shelves (report only)...");
+    } else {
+        say!("🧹 Cleaning empty shelves from Calibre-Web...");
+    }
 
     let mut calibre_check_stmt = calibre_conn.prepare("SELECT 1 FROM books WHERE id = ?1")
         .context("Failed to prepare book existence check query")?;
@@ -443,6 +609,7 @@ pub(crate) fn clean_empty_shelves(appdb_conn: &mut Connection, calibre_conn: &Co
     // Collect all orphaned link IDs and empty shelf IDs before mutating
     let mut orphan_link_ids: Vec<(i64, String)> = Vec::new();
     let mut empty_shelf_ids: Vec<(i64, String)> = Vec::new();
+    let mut shelves_left_empty: Vec<&str> = Vec::new();
 
     for (shelf_id, shelf_name) in &shelves {
         let links: Vec<(i64, i64)> = {
@@ -453,6 +620,7 @@ pub(crate) fn clean_empty_shelves(appdb_conn: &mut Connection, calibre_conn: &Co
         };
 
         let mut orphaned_count = 0;
+        let link_count = links.len();
         for (link_id, book_id) in links {
             let exists: bool = calibre_check_stmt.query_row(params![book_id], |_| Ok(true)).optional()?.is_some();
             if !exists {
@@ -462,8 +630,22 @@ pub(crate) fn clean_empty_shelves(appdb_conn: &mut Connection, calibre_conn: &Co
         }
 
         if orphaned_count > 0 {
-            println!(" -> Found {} orphaned book links for shelf '{}'.", orphaned_count, shelf_name);
+            say!(" -> Found {} orphaned book links for shelf '{}'.", orphaned_count, shelf_name);
         }
+        if orphaned_count == link_count {
+            shelves_left_empty.push(shelf_name);
+        }
+    }
+
+    if report_only {
+        if !orphan_link_ids.is_empty() {
+            say!(" -> Would remove {} orphaned book links.", orphan_link_ids.len());
+        }
+        for name in &shelves_left_empty {
+            say!(" -> Would remove empty shelf '{}'.", name);
+        }
+        say!("✅ Report complete; nothing was changed.");
+        return Ok(());
     }
 
     // Now perform all deletes inside a single transaction
@@ -475,7 +657,7 @@ pub(crate) fn clean_empty_shelves(appdb_conn: &mut Connection, calibre_conn: &Co
     }
 
     if !orphan_link_ids.is_empty() {
-        println!(" -> Removed {} orphaned book links.", orphan_link_ids.len());
+        say!(" -> Removed {} orphaned book links.", orphan_link_ids.len());
     }
 
     for (shelf_id, shelf_name) in &shelves {
@@ -494,22 +676,66 @@ pub(crate) fn clean_empty_shelves(appdb_conn: &mut Connection, calibre_conn: &Co
         .context("Failed to commit shelf cleanup transaction")?;
 
     for (_id, name) in &empty_shelf_ids {
-        println!(" -> Removed empty shelf '{}'.", name);
+        say!(" -> Removed empty shelf '{}'.", name);
     }
 
-    println!("✅ Shelf cleaning complete.");
+    say!("✅ Shelf cleaning complete.");
     Ok(())
 }
 
-/// Diagnoses and fixes Kobo sync issues for existing shelf links
-pub(crate) fn fix_kobo_sync_issues(appdb_conn: &mut Connection) -> Result<()> {
-    println!("🔧 Diagnosing and fixing Kobo sync issues...");
-    
-    // Create backup before making changes
-    // Note: We can't directly get the path from Connection, so we'll document this requirement
-    
+/// Diagnoses and fixes Kobo sync issues for existing shelf links.
+///
+/// All fixes, including adding a missing `current_bookmark` column, run in one transaction, so a
+/// failure leaves app.db untouched. Foreign keys are off for the whole fix because SQLite can only
+/// toggle them outside a transaction and the reading state repairs delete and recreate linked rows;
+/// the risk is that a bad reference goes unnoticed, so `PRAGMA foreign_key_check` is reported before
+/// committing.
+///
+/// With `username`, only that user's Kobo shelves and reading states are touched; the foreign key
+/// check still covers every user's Kobo rows.
+pub(crate) fn fix_kobo_sync_issues(appdb_conn: &mut Connection, dry_run: bool, username: Option<&str>) -> Result<()> {
+    say!("🔧 Diagnosing and fixing Kobo sync issues...");
+    if dry_run {
+        say!("🧪 DRY RUN MODE: All fixes run in a transaction that is rolled back at the end\n");
+    }
+    let user_id = resolve_kobo_user_scope(appdb_conn, username)?;
+
+    appdb_conn.execute("PRAGMA foreign_keys = OFF", [])?;
+    let result = apply_kobo_sync_fixes(appdb_conn, dry_run, user_id);
+    appdb_conn.execute("PRAGMA foreign_keys = ON", [])?;
+    result
+}
+
+/// Returns the user ID the Kobo commands are limited to, or `None` for all users, in which case
+/// a warning is printed if more than one user has Kobo sync shelves.
+fn resolve_kobo_user_scope(conn: &Connection, username: Option<&str>) -> Result<Option<i64>> {
+    if let Some(uname) = username {
+        let user_id = resolve_user_id(conn, Some(uname))?;
+        say!("👤 Limiting to Kobo shelves and reading states of user '{}'.\n", uname);
+        return Ok(Some(user_id));
+    }
+
+    let kobo_users: i64 = conn.query_row(
+        "SELECT COUNT(DISTINCT user_id) FROM shelf WHERE kobo_sync = 1",
+        [],
+        |row| row.get(0),
+    )?;
+    if kobo_users > 1 {
+        say!("⚠️  {} users have Kobo sync shelves; all of them are included. Use --username to limit this to one user.\n", kobo_users);
+    }
+    Ok(None)
+}
+
+/// Runs the fixes of `fix_kobo_sync_issues` in a single transaction, committed unless `dry_run`
+fn apply_kobo_sync_fixes(appdb_conn: &mut Connection, dry_run: bool, user_id: Option<i64>) -> Result<()> {
     let tx = appdb_conn.transaction()
         .context("Failed to start Kobo sync fix transaction")?;
+
+    // Added first, inside the transaction, since the fixes below write to it
+    let added_bookmark_column = !crate::db::has_column(&tx, "kobo_reading_state", "current_bookmark")?;
+    if added_bookmark_column {
+        tx.execute("ALTER TABLE kobo_reading_state ADD COLUMN current_bookmark INTEGER", [])?;
+    }
     
     // Find all books on Kobo sync shelves that aren't properly set up for sync
     let mut stmt = tx.prepare(
@@ -517,10 +743,10 @@ pub(crate) fn fix_kobo_sync_issues(appdb_conn: &mut Connection) -> Result<()> {
          FROM book_shelf_link bsl
          JOIN shelf s ON bsl.shelf = s.id
          LEFT JOIN user u ON s.user_id = u.id
-         WHERE s.kobo_sync = 1"
+         WHERE s.kobo_sync = 1 AND (?1 IS NULL OR s.user_id = ?1)"
     )?;
     
-    let books_on_kobo_shelves = stmt.query_map([], |row| {
+    let books_on_kobo_shelves = stmt.query_map(params![user_id], |row| {
         Ok((
             row.get::<_, i64>("book_id")?,
             row.get::<_, i64>("shelf_id")?,
@@ -542,7 +768,7 @@ pub(crate) fn fix_kobo_sync_issues(appdb_conn: &mut Connection) -> Result<()> {
         // Use the shared function to ensure complete Kobo sync setup
         // This handles reading state, statistics, bookmark, and book_read_link creation/verification
         ensure_kobo_sync_setup(&tx, book_id, user_id, &now_micro)?;
-        println!(" -> Ensured complete Kobo sync setup for book {} (user {})", book_id, username);
+        say!(" -> Ensured complete Kobo sync setup for book {} (user {})", book_id, username);
         
         // Update the shelf's last_modified timestamp to trigger sync detection
         tx.execute(
@@ -555,34 +781,36 @@ pub(crate) fn fix_kobo_sync_issues(appdb_conn: &mut Connection) -> Result<()> {
     let orphaned_states = tx.execute(
         "UPDATE kobo_reading_state 
          SET last_modified = priority_timestamp 
-         WHERE last_modified IS NULL AND priority_timestamp IS NOT NULL",
-        [],
+         WHERE last_modified IS NULL AND priority_timestamp IS NOT NULL
+           AND (?1 IS NULL OR user_id = ?1)",
+        params![user_id],
     )?;
     
     if orphaned_states > 0 {
-        println!(" -> Fixed {} reading states with NULL last_modified", orphaned_states);
+        say!(" -> Fixed {} reading states with NULL last_modified", orphaned_states);
     }
     
     let orphaned_priorities = tx.execute(
         "UPDATE kobo_reading_state 
          SET priority_timestamp = last_modified 
-         WHERE priority_timestamp IS NULL AND last_modified IS NOT NULL",
-        [],
+         WHERE priority_timestamp IS NULL AND last_modified IS NOT NULL
+           AND (?1 IS NULL OR user_id = ?1)",
+        params![user_id],
     )?;
     
     if orphaned_priorities > 0 {
-        println!(" -> Fixed {} reading states with NULL priority_timestamp", orphaned_priorities);
+        say!(" -> Fixed {} reading states with NULL priority_timestamp", orphaned_priorities);
     }
 
     if book_count > 0 || orphaned_states > 0 || orphaned_priorities > 0 {
-        println!("✅ Processed {} books and fixed {} orphaned timestamps.", book_count, orphaned_states + orphaned_priorities);
-        println!("🔄 Books are now ready for proper Calibre-Web sync.");
+        say!("✅ Processed {} books and fixed {} orphaned timestamps.", book_count, orphaned_states + orphaned_priorities);
+        say!("🔄 Books are now ready for proper Calibre-Web sync.");
     } else {
-        println!("✅ No cleanup needed.");
+        say!("✅ No cleanup needed.");
     }
     
     // Step 3: Repair missing kobo_statistics entries
-    println!("\n📊 Repairing missing kobo_statistics entries...");
+    say!("\n📊 Repairing missing kobo_statistics entries...");
     let mut repaired_statistics = 0;
     
     // Collect missing statistics in a block to release the prepared statement
@@ -591,10 +819,10 @@ pub(crate) fn fix_kobo_sync_issues(appdb_conn: &mut Connection) -> Result<()> {
             "SELECT krs.id, krs.book_id, krs.last_modified 
              FROM kobo_reading_state krs 
              LEFT JOIN kobo_statistics ks ON krs.id = ks.kobo_reading_state_id 
-             WHERE ks.id IS NULL"
+             WHERE ks.id IS NULL AND (?1 IS NULL OR krs.user_id = ?1)"
         )?;
         
-        stats_stmt.query_map([], |row| {
+        stats_stmt.query_map(params![user_id], |row| {
             Ok((
                 row.get::<_, i64>("id")?,
                 row.get::<_, i64>("book_id")?,
@@ -610,78 +838,76 @@ pub(crate) fn fix_kobo_sync_issues(appdb_conn: &mut Connection) -> Result<()> {
             params![reading_state_id, timestamp],
         )?;
         
-        println!(" -> Created kobo_statistics entry for book {} (reading_state_id: {})", book_id, reading_state_id);
+        say!(" -> Created kobo_statistics entry for book {} (reading_state_id: {})", book_id, reading_state_id);
         repaired_statistics += 1;
     }
     
     // Step 4: Reset timestamps for books on Kobo shelves to ensure they sync
-    println!("\n⏰ Resetting sync timestamps to force inclusion in next sync...");
+    say!("\n⏰ Resetting sync timestamps to force inclusion in next sync...");
     
     // Get all books on Kobo shelves and reset their timestamps to current time
     let current_time = now_utc_micro();
-    let updated_books = sync_kobo_shelf_timestamps(&tx, &current_time)?;
+    let updated_books = sync_kobo_shelf_timestamps(&tx, &current_time, user_id)?;
     
     if updated_books > 0 {
-        println!(" -> Reset timestamps for {} books on Kobo shelves to {}", updated_books, current_time);
+        say!(" -> Reset timestamps for {} books on Kobo shelves to {}", updated_books, current_time);
     }
     
     // Final summary
     if repaired_statistics > 0 || updated_books > 0 {
-        println!("\n✅ Additional fixes applied:");
+        say!("\n✅ Additional fixes applied:");
         if repaired_statistics > 0 {
-            println!("   - Repaired {} missing statistics entries", repaired_statistics);
+            say!("   - Repaired {} missing statistics entries", repaired_statistics);
         }
         if updated_books > 0 {
-            println!("   - Reset timestamps for {} books to force sync", updated_books);
+            say!("   - Reset timestamps for {} books to force sync", updated_books);
         }
     }
     
-    // Commit all changes
-    tx.commit()?;
-    
-    println!("\n� Checking and fixing Kobo reading state schema...");
-    fix_kobo_reading_state_schema(appdb_conn)?;
+    say!("\n🔍 Checking Kobo reading state schema...");
+    match (added_bookmark_column, dry_run) {
+        (true, true) => say!(" -> Would add missing current_bookmark column to kobo_reading_state table"),
+        (true, false) => say!(" -> Adding missing current_bookmark column to kobo_reading_state table"),
+        (false, _) => say!(" -> current_bookmark column already exists"),
+    }
+    fix_kobo_reading_state_data(&tx, user_id)?;
+
+    let broken_references: i64 = tx.query_row(
+        "SELECT COUNT(*) FROM pragma_foreign_key_check WHERE \"table\" LIKE 'kobo_%'",
+        [],
+        |row| row.get(0),
+    )?;
+    if broken_references > 0 {
+        // The check can't be limited to one user, so say so when the fixes were
+        let scope = if user_id.is_some() { " across all users" } else { "" };
+        say!("⚠️  {} Kobo row(s){} reference missing rows (see PRAGMA foreign_key_check).", broken_references, scope);
+    }
+
+    if dry_run {
+        tx.rollback()?;
+        say!("\n🧪 [DRY RUN] Rolled back all changes; app.db was not modified.");
+        return Ok(());
+    }
 
-    println!("\n�🔄 All books on Kobo shelves are now ready for proper Calibre-Web sync!");
+    tx.commit()
+        .context("Failed to commit Kobo sync fixes")?;
+
+    say!("\n🔄 All books on Kobo shelves are now ready for proper Calibre-Web sync!");
     
     Ok(())
 }
 
-/// Fixes schema issues and data problems in kobo_reading_state and kobo_bookmark tables
-fn fix_kobo_reading_state_schema(conn: &mut Connection) -> Result<()> {
-    // Check if current_bookmark column exists
-    let has_current_bookmark: bool = conn.prepare("SELECT sql FROM sqlite_master WHERE type='table' AND name='kobo_reading_state'")?
-        .query_row([], |row| {
-            let sql: String = row.get(0)?;
-            Ok(sql.contains("current_bookmark"))
-        })
-        .unwrap_or(false);
-    
-    if !has_current_bookmark {
-        println!(" -> Adding missing current_bookmark column to kobo_reading_state table");
-        // First disable foreign keys, add column, then re-enable
-        conn.execute("PRAGMA foreign_keys = OFF", [])?;
-        conn.execute(
-            "ALTER TABLE kobo_reading_state ADD COLUMN current_bookmark INTEGER",
-            [],
-        )?;
-        conn.execute("PRAGMA foreign_keys = ON", [])?;
-    } else {
-        println!(" -> current_bookmark column already exists");
-    }
-    
-    // Now handle data fixes in a transaction with foreign keys disabled temporarily
-    conn.execute("PRAGMA foreign_keys = OFF", [])?;
-    let tx = conn.transaction()?;
-    
+/// Removes duplicate reading states and makes sure every reading state has a current bookmark.
+/// Only `user_id`'s reading states are touched, if given. Requires the `current_bookmark` column to exist.
+fn fix_kobo_reading_state_data(tx: &Transaction, user_id: Option<i64>) -> Result<()> {
     // Remove duplicate reading states (keep the most recent one for each book/user combination)
     // But first, handle any bookmarks that might be orphaned
     let duplicate_states: Vec<i64> = tx.prepare(
         "SELECT krs.id FROM kobo_reading_state krs 
-         WHERE krs.id NOT IN (
+         WHERE (?1 IS NULL OR krs.user_id = ?1) AND krs.id NOT IN (
              SELECT MAX(id) FROM kobo_reading_state GROUP BY user_id, book_id
          )"
-    )?.query_map([], |row| row.get::<_, i64>(0))?
+    )?.query_map(params![user_id], |row| row.get::<_, i64>(0))?
      .collect::<Result<Vec<_>, _>>()?;
     
     // Delete any bookmarks associated with duplicate reading states first
@@ -694,22 +920,22 @@ fn fix_kobo_reading_state_schema(conn: &mut Connection) -> Result<()> {
     
     // Now safely delete the duplicate reading states
     let removed_duplicates = tx.execute(
-        "DELETE FROM kobo_reading_state WHERE id NOT IN (
+        "DELETE FROM kobo_reading_state WHERE (?1 IS NULL OR user_id = ?1) AND id NOT IN (
             SELECT MAX(id) FROM kobo_reading_state GROUP BY user_id, book_id
         )",
-        [],
+        params![user_id],
     )?;
     
     if removed_duplicates > 0 {
-        println!(" -> Removed {} duplicate reading states", removed_duplicates);
+        say!(" -> Removed {} duplicate reading states", removed_duplicates);
     }
     
     // Ensure all reading states have bookmarks
     let missing_bookmarks: Vec<i64> = tx.prepare(
         "SELECT krs.id FROM kobo_reading_state krs 
          LEFT JOIN kobo_bookmark kb ON krs.id = kb.kobo_reading_state_id 
-         WHERE kb.id IS NULL"
-    )?.query_map([], |row| row.get::<_, i64>(0))?
+         WHERE kb.id IS NULL AND (?1 IS NULL OR krs.user_id = ?1)"
+    )?.query_map(params![user_id], |row| row.get::<_, i64>(0))?
      .collect::<Result<Vec<_>, _>>()?;
     
     let current_time = now_utc_micro();
@@ -729,45 +955,42 @@ fn fix_kobo_reading_state_schema(conn: &mut Connection) -> Result<()> {
             params![bookmark_id, reading_state_id],
         )?;
         
-        println!(" -> Created missing bookmark for reading state {}", reading_state_id);
+        say!(" -> Created missing bookmark for reading state {}", reading_state_id);
     }
     
     // Update current_bookmark references for existing reading states that have bookmarks but no current_bookmark set
     let updated_refs = tx.execute(
         "UPDATE kobo_reading_state SET current_bookmark = (
             SELECT kb.id FROM kobo_bookmark kb WHERE kb.kobo_reading_state_id = kobo_reading_state.id LIMIT 1
-         ) WHERE current_bookmark IS NULL AND EXISTS (
+         ) WHERE current_bookmark IS NULL AND (?1 IS NULL OR user_id = ?1) AND EXISTS (
             SELECT 1 FROM kobo_bookmark kb WHERE kb.kobo_reading_state_id = kobo_reading_state.id
          )",
-        [],
+        params![user_id],
     )?;
     
     if updated_refs > 0 {
-        println!(" -> Updated current_bookmark references for {} reading states", updated_refs);
+        say!(" -> Updated current_bookmark references for {} reading states", updated_refs);
     }
     
-    tx.commit()?;
-    
-    // Re-enable foreign keys
-    conn.execute("PRAGMA foreign_keys = ON", [])?;
-    
     Ok(())
 }
 
-/// Provides detailed diagnostics for Kobo sync setup
-pub(crate) fn diagnose_kobo_sync(appdb_path: &Path, metadata_path: &Path) -> Result<()> {
-    let appdb_conn = crate::db::open_appdb(appdb_path)?;
-    let calibre_conn = crate::db::open_calibre_db(metadata_path)?;
-    println!("🔍 Kobo Sync Diagnostic Report");
-    println!("═══════════════════════════════");
+/// Provides detailed diagnostics for Kobo sync setup, for one user's shelves if `username` is given
+pub(crate) fn diagnose_kobo_sync(appdb_path: &Path, metadata_path: &Path, config: &DatabaseConfig, username: Option<&str>) -> Result<()> {
+    let appdb_conn = crate::db::open_appdb(appdb_path, config)?;
+    let calibre_conn = crate::db::open_calibre_db(metadata_path, config)?;
+    say!("🔍 Kobo Sync Diagnostic Report");
+    say!("═══════════════════════════════");
+    let user_id = resolve_kobo_user_scope(&appdb_conn, username)?;
     
     // Check user Kobo settings
-    println!("\n👤 Users with Kobo sync enabled:");
+    say!("\n👤 Users with Kobo sync enabled:");
     let mut user_stmt = appdb_conn.prepare(
-        "SELECT id, name, kobo_only_shelves_sync FROM user WHERE id IN (SELECT DISTINCT user_id FROM shelf WHERE kobo_sync = 1)"
+        "SELECT id, name, kobo_only_shelves_sync FROM user
+         WHERE id IN (SELECT DISTINCT user_id FROM shelf WHERE kobo_sync = 1) AND (?1 IS NULL OR id = ?1)"
     )?;
     
-    let user_rows = user_stmt.query_map([], |row| {
+    let user_rows = user_stmt.query_map(params![user_id], |row| {
         Ok((
             row.get::<_, i64>("id")?,
             row.get::<_, String>("name")?,
@@ -777,23 +1000,23 @@ pub(crate) fn diagnose_kobo_sync(appdb_path: &Path, metadata_path: &Path) -> Res
     
     for user_result in user_rows {
         let (user_id, username, kobo_only) = user_result?;
-        println!("  - {} (ID: {}) - Kobo only shelves: {}", 
+        say!("  - {} (ID: {}) - Kobo only shelves: {}", 
                 username, user_id, kobo_only.unwrap_or(0) == 1);
     }
     
     // Check Kobo sync shelves
-    println!("\n📚 Kobo Sync Shelves:");
+    say!("\n📚 Kobo Sync Shelves:");
     let mut shelf_stmt = appdb_conn.prepare(
         "SELECT s.id, s.name, s.user_id, u.name as username, s.created, s.last_modified, 
                 COUNT(bsl.book_id) as book_count
          FROM shelf s 
          LEFT JOIN user u ON s.user_id = u.id
          LEFT JOIN book_shelf_link bsl ON s.id = bsl.shelf
-         WHERE s.kobo_sync = 1 
+         WHERE s.kobo_sync = 1 AND (?1 IS NULL OR s.user_id = ?1)
          GROUP BY s.id"
     )?;
     
-    let shelf_rows = shelf_stmt.query_map([], |row| {
+    let shelf_rows = shelf_stmt.query_map(params![user_id], |row| {
         Ok((
             row.get::<_, i64>("id")?,
             row.get::<_, String>("name")?,
@@ -807,8 +1030,8 @@ pub(crate) fn diagnose_kobo_sync(appdb_path: &Path, metadata_path: &Path) -> Res
     for shelf_result in shelf_rows {
         let (shelf_id, shelf_name, username, created, last_modified, book_count) = shelf_result?;
         let username = username.unwrap_or_else(|| "Unknown".to_string());
-        println!("  - {} (ID: {}) - Owner: {} - Books: {}", shelf_name, shelf_id, username, book_count);
-        println!("    Created: {} | Last Modified: {}", created, last_modified);
+        say!("  - {} (ID: {}) - Owner: {} - Books: {}", shelf_name, shelf_id, username, book_count);
+        say!("    Created: {} | Last Modified: {}", created, last_modified);
         
         // Show books on this shelf
         let mut book_stmt = appdb_conn.prepare(
@@ -838,14 +1061,14 @@ pub(crate) fn diagnose_kobo_sync(appdb_path: &Path, metadata_path: &Path) -> Res
             
             // Check sync status
             let in_sync_table: bool = appdb_conn.query_row(
-                "SELECT 1 FROM kobo_synced_books WHERE book_id = ?1",
-                [book_id],
+                "SELECT 1 FROM kobo_synced_books WHERE book_id = ?1 AND (?2 IS NULL OR user_id = ?2)",
+                params![book_id, user_id],
                 |_| Ok(true)
             ).optional()?.is_some();
             
             let has_reading_state: bool = appdb_conn.query_row(
-                "SELECT 1 FROM kobo_reading_state WHERE book_id = ?1",
-                [book_id],
+                "SELECT 1 FROM kobo_reading_state WHERE book_id = ?1 AND (?2 IS NULL OR user_id = ?2)",
+                params![book_id, user_id],
                 |_| Ok(true)
             ).optional()?.is_some();
             
@@ -856,16 +1079,16 @@ pub(crate) fn diagnose_kobo_sync(appdb_path: &Path, metadata_path: &Path) -> Res
                 (false, false) => "❌ No sync setup",
             };
             
-            println!("    [{}] {} - {} (Added: {})", order, book_title, sync_status, date_added);
+            say!("    [{}] {} - {} (Added: {})", order, book_title, sync_status, date_added);
         }
     }
     
-    println!("\n💡 Troubleshooting Tips:");
-    println!("  1. Ensure the Kobo device is properly connected to Calibre-Web");
-    println!("  2. Check that the user account on Kobo matches the shelf owner");
-    println!("  3. Verify the book file exists in the Calibre library directory");
-    println!("  4. Try disconnecting and reconnecting the Kobo device");
-    println!("  5. Check Calibre-Web logs for sync errors during the sync process");
+    say!("\n💡 Troubleshooting Tips:");
+    say!("  1. Ensure the Kobo device is properly connected to Calibre-Web");
+    say!("  2. Check that the user account on Kobo matches the shelf owner");
+    say!("  3. Verify the book file exists in the Calibre library directory");
+    say!("  4. Try disconnecting and reconnecting the Kobo device");
+    say!("  5. Check Calibre-Web logs for sync errors during the sync process");
     
     Ok(())
 }
@@ -880,13 +1103,225 @@ pub(crate) fn add_existing_book_to_shelf(conn: &mut Connection, book_id: i64, sh
     // Note: We can't validate against metadata.db here since we only have app.db connection
     // The caller should ensure the book exists in the Calibre database
     
-    let was_added = add_book_to_shelf_core(conn, book_id, shelf_name, username, false)?;
-    
-    if was_added {
-        println!("✅ Successfully added book {} to shelf '{}'.", book_id, shelf_name);
+    match add_book_to_shelf_core(conn, book_id, shelf_name, username)? {
+        ShelfLink::Added => say!("✅ Successfully added book {} to shelf '{}'.", book_id, shelf_name),
+        ShelfLink::AlreadyPresent => say!(" -> Book {} is already on shelf '{}'.", book_id, shelf_name),
     }
     
     Ok(())
 }
 
+/// Adds several existing books to a shelf in one transaction, reporting each book and a summary.
+/// Books already on the shelf are skipped; any other failure rolls the whole batch back.
+pub(crate) fn add_existing_books_to_shelf(conn: &mut Connection, book_ids: &[i64], shelf_name: &str, username: Option<&str>) -> Result<()> {
+    let tx = conn.transaction()
+        .context("Failed to start shelf operation transaction")?;
+    let mut added = 0;
+    let mut already_present = 0;
+    for &book_id in book_ids {
+        let link = link_book_to_shelf(&tx, book_id, shelf_name, username)
+            .map_err(|error| anyhow::Error::from(error).context(format!("Failed to add book {} to shelf '{}'", book_id, shelf_name)))?;
+        match link {
+            ShelfLink::Added => {
+                say!(" -> Added book {} to shelf '{}'.", book_id, shelf_name);
+                added += 1;
+            }
+            ShelfLink::AlreadyPresent => {
+                say!(" -> Book {} is already on shelf '{}'.", book_id, shelf_name);
+                already_present += 1;
+            }
+        }
+    }
+    tx.commit()
+        .context("Failed to commit shelf link transaction")?;
+
+    say!("✅ Added {} book(s) to shelf '{}' ({} already present).", added, shelf_name, already_present);
+    Ok(())
+}
+
+/// Looks up the ID of a shelf owned by the given user (admin if no username is given)
+fn find_shelf_id(conn: &Connection, shelf_name: &str, username: Option<&str>) -> Result<i64> {
+    let user_id = resolve_user_id(conn, username)?;
+    conn.query_row(
+        "SELECT id FROM shelf WHERE name = ?1 AND user_id = ?2",
+        params![shelf_name, user_id],
+        |row| row.get(0),
+    ).optional()?
+        .with_context(|| format!("Shelf '{}' not found for user {}", shelf_name, user_label(username, user_id)))
+}
+
+/// Returns the IDs of the books on a shelf in shelf order.
+pub(crate) fn shelf_book_ids(conn: &Connection, shelf_name: &str, username: Option<&str>) -> Result<Vec<i64>> {
+    let shelf_id = find_shelf_id(conn, shelf_name, username)?;
+    let mut stmt = conn.prepare("SELECT book_id FROM book_shelf_link WHERE shelf = ?1 ORDER BY \"order\"")?;
+    let ids = stmt.query_map(params![shelf_id], |row| row.get(0))?
+        .collect::<Result<Vec<i64>, _>>()?;
+    Ok(ids)
+}
 
+/// Compares the books on two shelves, listing those only on one of them and those on both.
+pub(crate) fn shelf_diff(appdb_conn: &Connection, calibre_conn: &Connection, shelf_a: &str, shelf_b: &str, username: Option<&str>) -> Result<()> {
+    let book_ids_on = |shelf_id: i64| -> Result<BTreeSet<i64>> {
+        let mut stmt = appdb_conn.prepare("SELECT book_id FROM book_shelf_link WHERE shelf = ?1")?;
+        let ids = stmt.query_map(params![shelf_id], |row| row.get(0))?
+            .collect::<Result<BTreeSet<i64>, _>>()?;
+        Ok(ids)
+    };
+
+    let books_a = book_ids_on(find_shelf_id(appdb_conn, shelf_a, username)?)?;
+    let books_b = book_ids_on(find_shelf_id(appdb_conn, shelf_b, username)?)?;
+
+    let mut title_stmt = calibre_conn.prepare("SELECT title FROM books WHERE id = ?1")?;
+    let mut print_section = |heading: String, ids: Vec<&i64>| -> Result<()> {
+        say!("\n{} ({}):", heading, ids.len());
+        if ids.is_empty() {
+            say!("   (none)");
+        }
+        for id in ids {
+            let title: Option<String> = title_stmt.query_row(params![id], |row| row.get(0)).optional()?;
+            say!("   - {} (ID: {})", title.unwrap_or_else(|| "Unknown (not in Calibre library)".to_string()), id);
+        }
+        Ok(())
+    };
+
+    say!("📚 Comparing shelves '{}' and '{}'...", shelf_a, shelf_b);
+    print_section(format!("Only in '{}'", shelf_a), books_a.difference(&books_b).collect())?;
+    print_section(format!("Only in '{}'", shelf_b), books_b.difference(&books_a).collect())?;
+    print_section("In both".to_string(), books_a.intersection(&books_b).collect())?;
+
+    Ok(())
+}
+
+/// Copies every book from one shelf onto another (created if needed), preserving the
+/// source order. Stale `kobo_synced_books` rows for the target user are cleared so the
+/// copied books are offered to the target user's Kobo on the next sync. Everything runs in
+/// one transaction, so a failure leaves the target shelf as it was.
+pub(crate) fn copy_shelf(conn: &mut Connection, source: &str, target: &str, source_user: Option<&str>, target_user: Option<&str>) -> Result<()> {
+    let source_id = find_shelf_id(conn, source, source_user)
+        .context("Source shelf does not exist")?;
+    let target_user_id = resolve_user_id(conn, target_user)
+        .context("Failed to resolve target user")?;
+
+    let tx = conn.transaction()
+        .context("Failed to start shelf copy transaction")?;
+    let book_ids: Vec<i64> = {
+        let mut stmt = tx.prepare(
+            "SELECT book_id FROM book_shelf_link WHERE shelf = ?1 ORDER BY \"order\", id"
+        )?;
+        stmt.query_map(params![source_id], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    say!("📚 Copying {} book(s) from shelf '{}' to '{}'...", book_ids.len(), source, target);
+
+    let mut copied = Vec::new();
+    let mut already_present = 0;
+    for book_id in book_ids {
+        let link = link_book_to_shelf(&tx, book_id, target, target_user)
+            .map_err(|error| anyhow::Error::from(error).context(format!("Failed to copy book {} to shelf '{}'", book_id, target)))?;
+        match link {
+            ShelfLink::Added => copied.push(book_id),
+            ShelfLink::AlreadyPresent => {
+                say!(" -> Book {} is already on shelf '{}'.", book_id, target);
+                already_present += 1;
+            }
+        }
+    }
+
+    let mut cleared_sync = 0;
+    for book_id in &copied {
+        cleared_sync += tx.execute(
+            "DELETE FROM kobo_synced_books WHERE book_id = ?1 AND user_id = ?2",
+            params![book_id, target_user_id],
+        )?;
+    }
+    tx.commit()
+        .context("Failed to commit shelf copy")?;
+
+    say!("✅ Copied {} book(s) to shelf '{}'.", copied.len(), target);
+    if already_present > 0 {
+        say!("   {} book(s) were already on the target shelf.", already_present);
+    }
+    if cleared_sync > 0 {
+        say!("   Cleared {} stale Kobo sync entries for the target user.", cleared_sync);
+    }
+
+    Ok(())
+}
+
+/// Bumps a shelf's `last_modified` to now and clears its owner's `kobo_synced_books` rows
+/// for the books on it, so the shelf is offered to the Kobo again on the next sync.
+pub(crate) fn touch_shelf(conn: &mut Connection, shelf_name: &str, username: Option<&str>) -> Result<()> {
+    let shelf_id = find_shelf_id(conn, shelf_name, username)?;
+    let user_id = resolve_user_id(conn, username)?;
+
+    let tx = conn.transaction()?;
+    tx.execute(
+        "UPDATE shelf SET last_modified = ?1 WHERE id = ?2",
+        params![now_utc_micro(), shelf_id],
+    )?;
+    let cleared_sync = tx.execute(
+        "DELETE FROM kobo_synced_books WHERE user_id = ?1
+         AND book_id IN (SELECT book_id FROM book_shelf_link WHERE shelf = ?2)",
+        params![user_id, shelf_id],
+    )?;
+    tx.commit()
+        .context("Failed to commit shelf touch")?;
+
+    say!("✅ Touched shelf '{}'.", shelf_name);
+    say!("   Cleared {} Kobo sync entries; the shelf's books will be sent on the next sync.", cleared_sync);
+    Ok(())
+}
+
+/// Moves a book from one shelf to another (created if needed) in a single transaction.
+/// Both shelves get a new `last_modified`, and the owner's `kobo_synced_books` row for the
+/// book is cleared so the Kobo picks up the change. A book that wasn't on the source shelf
+/// is still added to the target.
+pub(crate) fn move_book_to_shelf(conn: &mut Connection, book_id: i64, from_shelf: &str, to_shelf: &str, username: Option<&str>) -> Result<()> {
+    if from_shelf == to_shelf {
+        anyhow::bail!("The source and target shelves are the same: '{}'", from_shelf);
+    }
+    let source_id = find_shelf_id(conn, from_shelf, username)
+        .context("Source shelf does not exist")?;
+    let user_id = resolve_user_id(conn, username)?;
+
+    let tx = conn.transaction()
+        .context("Failed to start shelf move transaction")?;
+
+    let removed = tx.execute(
+        "DELETE FROM book_shelf_link WHERE book_id = ?1 AND shelf = ?2",
+        params![book_id, source_id],
+    )?;
+    if removed > 0 {
+        tx.execute(
+            "UPDATE shelf SET last_modified = ?1 WHERE id = ?2",
+            params![now_utc_micro(), source_id],
+        )?;
+        say!(" -> Removed book {} from shelf '{}'.", book_id, from_shelf);
+    } else {
+        say!("⚠️  Book {} was not on shelf '{}'; adding it to '{}' anyway.", book_id, from_shelf, to_shelf);
+    }
+
+    match link_book_to_shelf(&tx, book_id, to_shelf, username)? {
+        ShelfLink::Added => say!(" -> Added book {} to shelf '{}'.", book_id, to_shelf),
+        ShelfLink::AlreadyPresent => say!(" -> Book {} is already on shelf '{}'.", book_id, to_shelf),
+    }
+
+    let cleared_sync = tx.execute(
+        "DELETE FROM kobo_synced_books WHERE book_id = ?1 AND user_id = ?2",
+        params![book_id, user_id],
+    )?;
+
+    tx.commit()
+        .context("Failed to commit shelf move transaction")?;
+
+    if removed > 0 {
+        say!("✅ Moved book {} from shelf '{}' to '{}'.", book_id, from_shelf, to_shelf);
+    } else {
+        say!("✅ Book {} is on shelf '{}'.", book_id, to_shelf);
+    }
+    if cleared_sync > 0 {
+        say!("   Cleared {} stale Kobo sync entries.", cleared_sync);
+    }
+    Ok(())
+}
@@ -0,0 +1,193 @@
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use crate::output::say;
+
+/// Matches backup file names created by `backup_database`: `{stem}_backup_{operation}_{YYYYMMDD_HHMMSS}.db`
+static BACKUP_NAME_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(.+?)_backup_(.+)_(\d{8}_\d{6})\.db$").expect("invalid regex"));
+
+/// A database backup found next to the live database
+struct BackupFile {
+    path: PathBuf,
+    operation: String,
+    created: NaiveDateTime,
+    size: u64,
+}
+
+/// Splits a backup file name into the source database stem, the operation and the creation time
+fn parse_backup_name(file_name: &str) -> Option<(String, String, NaiveDateTime)> {
+    let caps = BACKUP_NAME_RE.captures(file_name)?;
+    let created = NaiveDateTime::parse_from_str(&caps[3], "%Y%m%d_%H%M%S").ok()?;
+    Some((caps[1].to_string(), caps[2].to_string(), created))
+}
+
+/// Finds the backups of a database in its directory, oldest first
+fn find_backups(db_path: &Path) -> Result<Vec<BackupFile>> {
+    let dir = db_path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    let stem = db_path.file_stem().and_then(|s| s.to_str()).unwrap_or("database");
+
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read directory {:?}", dir))? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some((source, operation, created)) = parse_backup_name(&file_name.to_string_lossy()) else {
+            continue;
+        };
+        if source == stem {
+            backups.push(BackupFile { path: entry.path(), operation, created, size: entry.metadata()?.len() });
+        }
+    }
+    backups.sort_by_key(|backup| backup.created);
+    Ok(backups)
+}
+
+/// Lists the backups of each given database, grouped by the operation that created them
+pub(crate) fn list_backups(db_paths: &[&Path]) -> Result<()> {
+    if db_paths.is_empty() {
+        anyhow::bail!("--metadata-file or --appdb-file is required to find backups");
+    }
+
+    for db_path in db_paths {
+        let backups = find_backups(db_path)?;
+        say!("\n📦 Backups of {:?}:", db_path);
+        if backups.is_empty() {
+            say!("   (No backups found)");
+            continue;
+        }
+
+        let mut by_operation: BTreeMap<&str, Vec<&BackupFile>> = BTreeMap::new();
+        for backup in &backups {
+            by_operation.entry(&backup.operation).or_default().push(backup);
+        }
+        for (operation, backups) in by_operation {
+            say!("  {}:", operation);
+            for backup in backups {
+                say!("   - {}  {:>8}KB  {}",
+                         backup.created.format("%Y-%m-%d %H:%M:%S"),
+                         backup.size / 1024,
+                         backup.path.display());
+            }
+        }
+    }
+    say!("\n💡 Use 'restore-backup <BACKUP>' to restore one of these.");
+    Ok(())
+}
+
+/// Fails unless each database has a backup made within the last `max_age_hours`: one of this
+/// tool's own backups next to it, or any file in `backup_dir` modified since then.
+pub(crate) fn require_recent_backup(db_paths: &[&Path], backup_dir: Option<&Path>, max_age_hours: u64) -> Result<()> {
+    let cutoff = i64::try_from(max_age_hours).ok()
+        .and_then(chrono::Duration::try_hours)
+        .and_then(|max_age| chrono::Local::now().naive_local().checked_sub_signed(max_age))
+        .unwrap_or(NaiveDateTime::MIN);
+
+    if let Some(dir) = backup_dir {
+        let external_cutoff = std::time::SystemTime::now()
+            .checked_sub(std::time::Duration::from_secs(max_age_hours.saturating_mul(3600)))
+            .unwrap_or(std::time::UNIX_EPOCH);
+        let newest = walkdir::WalkDir::new(dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter_map(|e| e.metadata().ok()?.modified().ok())
+            .max();
+        if newest.is_some_and(|modified| modified >= external_cutoff) {
+            say!("📦 Found a backup in {:?} from the last {} hour(s).", dir, max_age_hours);
+            return Ok(());
+        }
+    }
+
+    for db_path in db_paths {
+        let newest = find_backups(db_path)?.pop();
+        match newest {
+            Some(backup) if backup.created >= cutoff => {
+                say!("📦 Found a backup of {:?} from {}.", db_path, backup.created.format("%Y-%m-%d %H:%M:%S"));
+            }
+            Some(backup) => anyhow::bail!(
+                "The newest backup of {:?} is from {}, more than {} hour(s) ago. Back it up (e.g. copy the file) and try again.",
+                db_path, backup.created.format("%Y-%m-%d %H:%M:%S"), max_age_hours,
+            ),
+            None => anyhow::bail!(
+                "{:?} has no backup from the last {} hour(s). Back it up (e.g. copy the file) and try again.",
+                db_path, max_age_hours,
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Copies a backup over the live database, after confirmation and a safety copy of the current file.
+/// Without `target`, the database is the one the backup was made from, in the same directory.
+pub(crate) fn restore_backup(backup: &Path, target: Option<&Path>, yes: bool) -> Result<()> {
+    if !backup.is_file() {
+        anyhow::bail!("Backup file does not exist: {:?}", backup);
+    }
+    let target = match target {
+        Some(target) => target.to_path_buf(),
+        None => {
+            let file_name = backup.file_name().unwrap_or_default().to_string_lossy();
+            let (stem, _, _) = parse_backup_name(&file_name)
+                .with_context(|| format!("{:?} isn't named like a backup; pass the database to restore with --target", backup))?;
+            backup.with_file_name(format!("{}.db", stem))
+        }
+    };
+
+    // Make sure the backup is a readable SQLite database before replacing anything
+    let check: String = rusqlite::Connection::open_with_flags(backup, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .and_then(|conn| conn.query_row("PRAGMA quick_check", [], |row| row.get(0)))
+        .with_context(|| format!("{:?} is not a valid SQLite database", backup))?;
+    if check != "ok" {
+        anyhow::bail!("Backup {:?} failed its integrity check: {}", backup, check);
+    }
+
+    // A live write-ahead log would be replayed on top of the restored file
+    if crate::db::wal_path(&target).exists() {
+        anyhow::bail!("{:?} is in use (its -wal file exists); stop Calibre-Web and try again", target);
+    }
+
+    say!("⚠️  This will replace {:?} with {:?}.", target, backup);
+    if !yes && !confirm()? {
+        say!("Restore cancelled.");
+        return Ok(());
+    }
+
+    if target.exists() {
+        say!("📦 Saving a copy of the current database first...");
+        crate::utils::backup_database(&target, "pre_restore")
+            .context("Failed to back up the current database")?;
+    }
+
+    fs::copy(backup, &target)
+        .with_context(|| format!("Failed to copy {:?} to {:?}", backup, target))?;
+    say!("✅ Restored {:?} from {:?}", target, backup);
+    Ok(())
+}
+
+/// Asks the user to type "yes" on stdin
+fn confirm() -> Result<bool> {
+    say!("Type 'yes' to continue:");
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)
+        .context("Failed to read confirmation")?;
+    Ok(answer.trim().eq_ignore_ascii_case("yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_backup_name() {
+        let (stem, operation, created) = parse_backup_name("metadata_backup_clean_db_20260102_030405.db").unwrap();
+        assert_eq!(stem, "metadata");
+        assert_eq!(operation, "clean_db");
+        assert_eq!(created.format("%Y-%m-%d %H:%M:%S").to_string(), "2026-01-02 03:04:05");
+        assert!(parse_backup_name("metadata.db").is_none());
+        assert!(parse_backup_name("metadata_backup_clean_db_2026.db").is_none());
+    }
+}
@@ -1,12 +1,15 @@
 use anyhow::{Context, Result};
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use rusqlite::{params, Connection, Transaction, OptionalExtension};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 use uuid::Uuid;
-use crate::models::{BookMetadata, ExistingBookData, UpdateChanges, UpsertResult};
-use crate::utils::{now_utc_micro, format_timestamp_micro, find_or_create_by_name, find_or_create_by_name_and_sort, find_or_create_language, calculate_file_hash, validate_id, validate_table_name, validate_column_name, get_valid_filename, title_sort as compute_title_sort, get_sorted_author, set_metadata_dirty, detect_book_format};
+use crate::cli::{ChecksumAlgorithm, CommentsStrategy, OutputFormat};
+use crate::error::CwhError;
+use crate::models::{AuthorSortMethod, BookMetadata, BookWriteOptions, DeleteScope, ExistingBookData, ListOptions, UpdateChanges, UpsertResult};
+use crate::output::say;
+use crate::utils::{now_utc_micro, format_timestamp_micro, find_or_create_by_name, find_or_create_by_name_and_sort, find_or_create_language, calculate_file_hash, same_size_and_mtime, validate_id, validate_table_name, validate_column_name, get_valid_filename, title_sort as compute_title_sort, set_metadata_dirty, detect_book_format, has_book_extension, render_book_path, format_series_index, json_escape, parse_db_timestamp};
 
 /// Retrieves existing book metadata for comparison
 fn get_existing_book_data(tx: &Connection, book_id: i64) -> Result<ExistingBookData> {
@@ -17,18 +20,7 @@ fn get_existing_book_data(tx: &Connection, book_id: i64) -> Result<ExistingBookD
         |row| Ok((row.get(0)?, row.get(1)?))
     )?;
     
-    // Parse pubdate if it exists
-    let pubdate = pubdate_str.and_then(|s| {
-        // Try parsing with timezone first
-        if let Ok(dt) = DateTime::parse_from_str(&s, "%Y-%m-%d %H:%M:%S%.6f%z") {
-            Some(dt.with_timezone(&Utc))
-        } else if let Ok(naive) = chrono::NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M:%S%.6f") {
-            // If that fails, try parsing as naive datetime and assume UTC
-            Some(DateTime::from_naive_utc_and_offset(naive, Utc))
-        } else {
-            None
-        }
-    });
+    let pubdate = pubdate_str.as_deref().and_then(parse_db_timestamp);
     
     // Get publisher name
     let publisher: Option<String> = tx.query_row(
@@ -47,35 +39,90 @@ fn get_existing_book_data(tx: &Connection, book_id: i64) -> Result<ExistingBookD
         params![book_id],
         |row| row.get(0)
     ).optional()?;
+
+    // Get comment (description) text
+    let comments: Option<String> = tx.query_row(
+        "SELECT text FROM comments WHERE book = ?1",
+        params![book_id],
+        |row| row.get(0)
+    ).optional()?;
     
     Ok(ExistingBookData {
         pubdate,
         series_index,
         publisher,
         series,
+        comments,
     })
 }
 
+/// Builds the `comments` text for a book from its subtitle, description and rights.
+fn build_comment_text(metadata: &BookMetadata) -> Option<String> {
+    let mut comment_parts = Vec::new();
+    if let Some(subtitle) = &metadata.subtitle {
+        comment_parts.push(format!("<h3>{}</h3>", subtitle));
+    }
+    if let Some(description) = &metadata.description {
+        comment_parts.push(description.to_string());
+    }
+    if let Some(rights) = &metadata.rights {
+        comment_parts.push(format!("<p>Rights: {}</p>", rights));
+    }
+
+    if comment_parts.is_empty() {
+        None
+    } else {
+        Some(comment_parts.join("\n"))
+    }
+}
+
+/// Decides the comments to store for an existing book, given its current comments and the
+/// comments built from the re-added file
+fn merge_comments(strategy: CommentsStrategy, existing: Option<&str>, new: Option<String>) -> Option<String> {
+    let Some(existing) = existing else { return new };
+    let Some(new) = new else { return Some(existing.to_string()) };
+    match strategy {
+        CommentsStrategy::Overwrite => Some(new),
+        CommentsStrategy::Preserve => Some(existing.to_string()),
+        // Re-adding the same file again must not append the same text a second time
+        CommentsStrategy::Append if existing.contains(new.as_str()) => Some(existing.to_string()),
+        CommentsStrategy::Append => Some(format!("{}\n{}", existing, new)),
+        CommentsStrategy::PreferLonger if new.chars().count() > existing.chars().count() => Some(new),
+        CommentsStrategy::PreferLonger => Some(existing.to_string()),
+    }
+}
+
 /// Get the file path of an existing book in the library
-fn get_existing_book_file_path(library_dir: &Path, book_path: &str) -> Result<Option<PathBuf>> {
+fn get_existing_book_file_path(library_dir: &Path, book_path: &str, incoming_file: &Path, prefer_format: &[String]) -> Result<Option<PathBuf>> {
     let book_dir = library_dir.join(book_path);
     if !book_dir.exists() {
         return Ok(None);
     }
-    
-    // Look for EPUB or KEPUB files in the book directory
+
+    // Pick deterministically: the incoming file's format, then the preferred formats in order,
+    // then anything else by file name
+    let incoming_format = detect_book_format(incoming_file).map(|(format, _)| format).ok();
+    let rank = |path: &Path| -> usize {
+        let Ok((format, extension)) = detect_book_format(path) else {
+            return usize::MAX;
+        };
+        if Some(format) == incoming_format {
+            return 0;
+        }
+        prefer_format.iter()
+            .position(|preferred| preferred.trim_start_matches('.').eq_ignore_ascii_case(&extension[1..]))
+            .map_or(usize::MAX - 1, |position| position + 1)
+    };
+
+    let mut candidates = Vec::new();
     for entry in fs::read_dir(&book_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            let path_str = path.to_string_lossy().to_lowercase();
-            if path_str.ends_with(".epub") || path_str.ends_with(".kepub") {
-                return Ok(Some(path));
-            }
+        let path = entry?.path();
+        if path.is_file() && has_book_extension(&path) {
+            candidates.push(path);
         }
     }
-    
-    Ok(None)
+    candidates.sort_by(|a, b| rank(a).cmp(&rank(b)).then_with(|| a.cmp(b)));
+    Ok(candidates.into_iter().next())
 }
 
 /// Compares new metadata with existing book data to determine what needs updating
@@ -102,10 +149,67 @@ fn determine_changes(existing: &ExistingBookData, new_metadata: &BookMetadata) -
     if existing.series != new_metadata.series {
         changes.series_changed = true;
     }
+
+    // Compare comments (subtitle + description + rights)
+    if existing.comments != build_comment_text(new_metadata) {
+        changes.comments_changed = true;
+    }
     
     changes
 }
 
+/// Returns the ID of the book with this title and author sort, if it's in the library.
+pub(crate) fn find_book_id(conn: &Connection, title: &str, author_sort: &str) -> Result<Option<i64>> {
+    conn.query_row(
+        "SELECT id FROM books WHERE title = ?1 AND author_sort = ?2",
+        params![title, author_sort],
+        |row| row.get(0)
+    ).optional()
+    .context("Failed to look up existing book")
+}
+
+/// Returns the sort name a book by `author` gets: the stored `authors.sort` if the author
+/// already exists, otherwise the one from `author_sort_map` or computed with `method`.
+pub(crate) fn author_sort_for(conn: &Connection, author: &str, method: AuthorSortMethod, author_sort_map: Option<&HashMap<String, String>>) -> Result<String> {
+    let stored: Option<String> = conn.query_row(
+        "SELECT sort FROM authors WHERE name = ?1 AND sort IS NOT NULL AND sort != ''",
+        params![author],
+        |row| row.get(0),
+    ).optional()?;
+    Ok(stored.unwrap_or_else(|| new_author_sort(author, method, author_sort_map)))
+}
+
+/// Returns the sort name for an author not yet in the library: the one `author_sort_map` gives
+/// for the name, ignoring case, otherwise the one computed with `method`.
+fn new_author_sort(author: &str, method: AuthorSortMethod, author_sort_map: Option<&HashMap<String, String>>) -> String {
+    author_sort_map
+        .and_then(|map| map.get(&author.trim().to_lowercase()))
+        .cloned()
+        .unwrap_or_else(|| method.author_sort(author))
+}
+
+/// Sets `books.author_sort` to the linked authors' `sort` values joined with " & " in link order,
+/// the way Calibre maintains it. Call this whenever a book's author links change.
+pub(crate) fn refresh_book_author_sort(conn: &Connection, book_id: i64) -> Result<String> {
+    let author_sort = linked_author_sort(conn, book_id)?;
+    conn.execute("UPDATE books SET author_sort = ?1 WHERE id = ?2", params![&author_sort, book_id])
+        .with_context(|| format!("Failed to update author sort for book {}", book_id))?;
+    Ok(author_sort)
+}
+
+/// Returns the linked authors' `sort` values joined with " & " in link order
+pub(crate) fn linked_author_sort(conn: &Connection, book_id: i64) -> Result<String> {
+    let sorts: Vec<String> = conn.prepare(
+        "SELECT COALESCE(a.sort, a.name) FROM books_authors_link bal
+         JOIN authors a ON a.id = bal.author
+         WHERE bal.book = ?1
+         ORDER BY bal.id",
+    )?
+    .query_map(params![book_id], |row| row.get(0))?
+    .collect::<Result<Vec<_>, _>>()?;
+    Ok(sorts.join(" & "))
+}
+
 /// Handles the database transaction for adding or updating a book.
 /// If a book with the same title and author exists, it updates it. Otherwise, it creates a new one.
 pub(crate) fn add_book_to_db(
@@ -113,22 +217,22 @@ pub(crate) fn add_book_to_db(
     metadata: &BookMetadata, 
     library_dir: &Path, 
     new_epub_file: &Path,
-    dry_run: bool
-) -> Result<UpsertResult> {
+    options: &BookWriteOptions,
+) -> Result<UpsertResult, CwhError> {
     if metadata.title.trim().is_empty() {
-        anyhow::bail!("Book title cannot be empty");
+        return Err(CwhError::InvalidEpub("Book title cannot be empty".to_string()));
     }
     if metadata.author.trim().is_empty() {
-        anyhow::bail!("Book author cannot be empty");
+        return Err(CwhError::InvalidEpub("Book author cannot be empty".to_string()));
     }
     if !new_epub_file.exists() {
-        anyhow::bail!("EPUB file does not exist: {:?}", new_epub_file);
+        return Err(CwhError::NotFound(format!("EPUB file does not exist: {:?}", new_epub_file)));
     }
 
     let tx = conn.transaction()
         .context("Failed to start database transaction")?;
 
-    let author_sort_name = get_sorted_author(&metadata.author);
+    let author_sort_name = author_sort_for(&tx, &metadata.author, options.author_sort, options.author_sort_map)?;
     let existing_book: Option<(i64, String)> = tx.query_row(
         "SELECT id, path FROM books WHERE title = ?1 AND author_sort = ?2",
         params![&metadata.title, &author_sort_name],
@@ -136,9 +240,9 @@ pub(crate) fn add_book_to_db(
     ).optional()?;
 
     let result = if let Some((book_id, book_path)) = existing_book {
-        update_book(&tx, book_id, &book_path, metadata, library_dir, new_epub_file, dry_run)?
+        update_book(&tx, book_id, &book_path, metadata, library_dir, new_epub_file, options)?
     } else {
-        create_book(&tx, metadata, dry_run)?
+        create_book(&tx, metadata, options)?
     };
 
     tx.commit()
@@ -147,6 +251,27 @@ pub(crate) fn add_book_to_db(
     Ok(result)
 }
 
+/// Removes a just-created book whose files couldn't be written, so no row points at a missing file.
+pub(crate) fn remove_created_book(conn: &Connection, library_dir: &Path, book_id: i64, book_path: &str) -> Result<()> {
+    // Remove the partial files first so the database has room for its journal on a full disk
+    let book_dir = library_dir.join(book_path);
+    if book_dir.exists() {
+        fs::remove_dir_all(&book_dir)
+            .with_context(|| format!("Failed to remove partially written directory: {:?}", book_dir))?;
+    }
+    // Only succeeds while the author (and --shard-by-id bucket) directories are now empty
+    for parent_dir in book_dir.ancestors().skip(1).take_while(|dir| *dir != library_dir && dir.starts_with(library_dir)) {
+        if fs::remove_dir(parent_dir).is_err() {
+            break;
+        }
+    }
+
+    // Triggers will handle linked tables.
+    conn.execute("DELETE FROM books WHERE id = ?1", params![book_id])
+        .with_context(|| format!("Failed to remove book {} after its files couldn't be written", book_id))?;
+    Ok(())
+}
+
 /// Updates an existing book's metadata when the EPUB file or metadata has changed.
 fn update_book(
     tx: &Transaction,
@@ -155,54 +280,70 @@ fn update_book(
     metadata: &BookMetadata,
     library_dir: &Path,
     new_epub_file: &Path,
-    dry_run: bool,
+    options: &BookWriteOptions,
 ) -> Result<UpsertResult> {
-    println!(" -> Found existing book with ID: {}. Checking file hash...", book_id);
-
-    let new_file_hash = calculate_file_hash(new_epub_file)?;
+    let dry_run = options.dry_run;
+    let (check, same, different) = if options.skip_hash_check {
+        ("size and mtime", "same size and mtime", "different size or mtime")
+    } else {
+        ("file hash", "same hash", "different hash")
+    };
+    say!(" -> Found existing book with ID: {}. Checking {}...", book_id, check);
 
-    if let Some(existing_file_path) = get_existing_book_file_path(library_dir, book_path)? {
-        if let Ok(existing_file_hash) = calculate_file_hash(&existing_file_path) {
-            if new_file_hash == existing_file_hash {
-                println!(" -> Files are identical (same hash). No changes needed.");
+    if let Some(existing_file_path) = get_existing_book_file_path(library_dir, book_path, new_epub_file, options.prefer_format)? {
+        let identical = if options.skip_hash_check {
+            same_size_and_mtime(new_epub_file, &existing_file_path)
+        } else {
+            let new_file_hash = calculate_file_hash(new_epub_file, options.checksum)?;
+            calculate_file_hash(&existing_file_path, options.checksum).map(|hash| hash == new_file_hash)
+        };
+        match identical {
+            Ok(true) => {
+                say!(" -> Files are identical ({}). No changes needed.", same);
                 if dry_run {
-                    println!("   [DRY RUN] Would skip all operations");
+                    say!("   [DRY RUN] Would skip all operations");
                 }
                 return Ok(UpsertResult::NoChanges { book_id, book_path: book_path.to_string() });
-            } else if dry_run {
-                println!(" -> Files differ (different hash). Would check metadata changes...");
-            } else {
-                println!(" -> Files differ (different hash). Checking metadata changes...");
             }
-        } else {
-            println!(" -> Could not hash existing file. Proceeding with metadata comparison...");
+            Ok(false) if dry_run => say!(" -> Files differ ({}). Would check metadata changes...", different),
+            Ok(false) => say!(" -> Files differ ({}). Checking metadata changes...", different),
+            Err(_) => say!(" -> Could not compare with the existing file. Proceeding with metadata comparison..."),
         }
     } else {
-        println!(" -> Existing file not found. Proceeding with update...");
+        say!(" -> Existing file not found. Proceeding with update...");
     }
 
     let existing_data = get_existing_book_data(tx, book_id)?;
-    let changes = determine_changes(&existing_data, metadata);
+    let mut changes = determine_changes(&existing_data, metadata);
+    let mut comment_text = build_comment_text(metadata);
+    if changes.comments_changed {
+        comment_text = merge_comments(options.comments_strategy, existing_data.comments.as_deref(), comment_text);
+        if comment_text == existing_data.comments {
+            say!(" -> Description differs, but keeping the existing comments (--comments-strategy {}).",
+                options.comments_strategy.name());
+            changes.comments_changed = false;
+        }
+    }
 
     if !changes.has_any_changes() {
         if dry_run {
-            println!(" -> No metadata changes detected. Would skip database update.");
-            println!("   [DRY RUN] Would skip all operations");
+            say!(" -> No metadata changes detected. Would skip database update.");
+            say!("   [DRY RUN] Would skip all operations");
         } else {
-            println!(" -> No metadata changes detected. Skipping database update.");
+            say!(" -> No metadata changes detected. Skipping database update.");
         }
         return Ok(UpsertResult::NoChanges { book_id, book_path: book_path.to_string() });
     }
 
     if dry_run {
-        println!(" -> Metadata changes detected. Would update database...");
-        println!("   [DRY RUN] Would update: pubdate={}, series_index={}, publisher={}, series={}",
+        say!(" -> Metadata changes detected. Would update database...");
+        say!("   [DRY RUN] Would update: pubdate={}, series_index={}, publisher={}, series={}, comments={}",
             changes.pubdate_changed, changes.series_index_changed,
-            changes.publisher_changed, changes.series_changed);
+            changes.publisher_changed, changes.series_changed, changes.comments_changed);
         return Ok(UpsertResult::Updated { book_id, book_path: book_path.to_string() });
     }
 
-    println!(" -> Metadata changes detected. Updating database...");
+    say!(" -> Metadata changes detected. Updating database...");
     let now_str = now_utc_micro();
 
     let mut set_clauses: Vec<String> = vec!["last_modified = ?".to_string()];
@@ -265,38 +406,54 @@ fn update_book(
         }
     }
 
+    if changes.comments_changed {
+        tx.execute(
+            "DELETE FROM comments WHERE book = ?1",
+            params![book_id],
+        ).with_context(|| format!("Failed to delete old comments for book {}", book_id))?;
+
+        if let Some(comment_text) = comment_text {
+            tx.execute(
+                "INSERT INTO comments (book, text) VALUES (?1, ?2)",
+                params![book_id, comment_text],
+            ).with_context(|| format!("Failed to update comments for book {}", book_id))?;
+        }
+    }
+
     set_metadata_dirty(tx, book_id)?;
 
     Ok(UpsertResult::Updated { book_id, book_path: book_path.to_string() })
 }
 
 /// Creates a brand new book record with all associated metadata.
-fn create_book(
-    tx: &Transaction,
-    metadata: &BookMetadata,
-    dry_run: bool,
-) -> Result<UpsertResult> {
+/// `added_date` overrides the `timestamp` (date added) column, which otherwise defaults to now.
+/// The book directory is built from `path_pattern` once the book ID is known; later updates
+/// and deletes use the stored `books.path`, so they always find the files.
+fn create_book(tx: &Transaction, metadata: &BookMetadata, options: &BookWriteOptions) -> Result<UpsertResult> {
+    let BookWriteOptions { added_date, path_pattern, author_sort, author_sort_map, dry_run, .. } = *options;
     if dry_run {
-        println!(" -> Would create new book with title: '{}'", metadata.title);
-        println!(" -> Would assign author: '{}'", metadata.author);
+        say!(" -> Would create new book with title: '{}'", metadata.title);
+        say!(" -> Would assign author: '{}'", metadata.author);
         if let Some(publisher) = &metadata.publisher {
-            println!(" -> Would set publisher: '{}'", publisher);
+            say!(" -> Would set publisher: '{}'", publisher);
         }
         if let Some(series) = &metadata.series {
-            println!(" -> Would add to series: '{}'", series);
+            say!(" -> Would add to series: '{}'", series);
         }
-        println!("   [DRY RUN] Would create new database entry and copy files");
-        let dry_author = get_valid_filename(&metadata.author, 96);
-        let dry_title = get_valid_filename(&metadata.title, 96);
-        return Ok(UpsertResult::Created { book_id: 0, book_path: format!("{}/{} (NEW)", dry_author, dry_title) });
+        if let Some(added) = added_date {
+            say!(" -> Would set date added: {}", added.format("%Y-%m-%d"));
+        }
+        say!("   [DRY RUN] Would create new database entry and copy files");
+        return Ok(UpsertResult::Created { book_id: 0, book_path: render_book_path(path_pattern, metadata, "NEW") });
     }
 
-    let author_sort_name = get_sorted_author(&metadata.author);
+    let author_sort_name = new_author_sort(&metadata.author, author_sort, author_sort_map);
     let author_id = find_or_create_by_name_and_sort(tx, "authors", &metadata.author, &author_sort_name)
         .with_context(|| format!("Failed to find or create author '{}'", metadata.author))?;
 
     let now = Utc::now();
-    let now_str = format_timestamp_micro(&now);
+    let timestamp_str = format_timestamp_micro(&added_date.unwrap_or(now));
+    let last_modified_str = format_timestamp_micro(&metadata.modified.unwrap_or(now));
     let pubdate_str = format_timestamp_micro(&metadata.pubdate.unwrap_or(now));
     let book_uuid = Uuid::new_v4().to_string();
     let title_sort = compute_title_sort(&metadata.title);
@@ -308,18 +465,16 @@ fn create_book(
             &metadata.title,
             &title_sort,
             &author_sort_name,
-            &now_str,
+            &timestamp_str,
             &pubdate_str,
-            &now_str,
+            &last_modified_str,
             metadata.series_index.unwrap_or(1.0),
             &book_uuid,
         ],
     ).with_context(|| format!("Failed to insert book '{}' into database", metadata.title))?;
     let book_id = tx.last_insert_rowid();
 
-    let author_dir = get_valid_filename(&metadata.author, 96);
-    let title_dir = get_valid_filename(&metadata.title, 96);
-    let book_path = format!("{}/{} ({})", author_dir, title_dir, book_id);
+    let book_path = render_book_path(path_pattern, metadata, &book_id.to_string());
 
     tx.execute(
         "UPDATE books SET path = ?1 WHERE id = ?2",
@@ -330,37 +485,27 @@ fn create_book(
         "INSERT INTO books_authors_link (book, author) VALUES (?1, ?2)",
         params![book_id, author_id],
     ).with_context(|| format!("Failed to link book {} to author {}", book_id, author_id))?;
+    // An existing author keeps its stored sort, which may differ from the computed one
+    refresh_book_author_sort(tx, book_id)?;
 
-    let (book_format, _extension) = detect_book_format(&metadata.path)?;
+    let (book_format, _extension) = metadata.format()?;
     let data_name = format!("{} - {}", get_valid_filename(&metadata.title, 42), get_valid_filename(&metadata.author, 42));
     tx.execute(
         "INSERT INTO data (book, format, uncompressed_size, name) VALUES (?1, ?2, ?3, ?4)",
         params![book_id, book_format, metadata.file_size as i64, data_name],
     )?;
 
-    let mut comment_parts = Vec::new();
-    if let Some(subtitle) = &metadata.subtitle {
-        comment_parts.push(format!("<h3>{}</h3>", subtitle));
-    }
-    if let Some(description) = &metadata.description {
-        comment_parts.push(description.to_string());
-    }
-    if let Some(rights) = &metadata.rights {
-        comment_parts.push(format!("<p>Rights: {}</p>", rights));
-    }
-
-    if !comment_parts.is_empty() {
-        let comment_text = comment_parts.join("\n");
+    if let Some(comment_text) = build_comment_text(metadata) {
         tx.execute(
             "INSERT INTO comments (book, text) VALUES (?1, ?2)",
             params![book_id, comment_text],
         )?;
     }
-    if let Some(language) = &metadata.language {
+    for (item_order, language) in metadata.languages.iter().enumerate() {
         let lang_id = find_or_create_language(tx, language)?;
         tx.execute(
-            "INSERT INTO books_languages_link (book, lang_code) VALUES (?1, ?2)",
-            params![book_id, lang_id],
+            "INSERT INTO books_languages_link (book, lang_code, item_order) VALUES (?1, ?2, ?3)",
+            params![book_id, lang_id, item_order as i64],
         )?;
     }
     if let Some(isbn) = &metadata.isbn {
@@ -369,6 +514,12 @@ fn create_book(
             params![book_id, isbn],
         )?;
     }
+    if let Some(isbn10) = &metadata.isbn10 {
+        tx.execute(
+            "INSERT INTO identifiers (book, type, val) VALUES (?1, 'ISBN10', ?2)",
+            params![book_id, isbn10],
+        )?;
+    }
 
     if let Some(publisher_name) = &metadata.publisher {
         let publisher_id = find_or_create_by_name(tx, "publishers", publisher_name)?;
@@ -400,28 +551,67 @@ fn create_book(
 }
 
 
+/// Formats a timestamp column for `list`. Values that don't parse as a timestamp are shown as
+/// stored, so one malformed row doesn't fail the whole listing.
+fn display_timestamp(row: &rusqlite::Row, column: &str, format: &str) -> Result<String> {
+    use rusqlite::types::ValueRef;
+    let raw = match row.get_ref(column)? {
+        ValueRef::Null => return Ok("(none)".to_string()),
+        ValueRef::Text(text) | ValueRef::Blob(text) => String::from_utf8_lossy(text).into_owned(),
+        ValueRef::Integer(value) => value.to_string(),
+        ValueRef::Real(value) => value.to_string(),
+    };
+    Ok(match parse_db_timestamp(&raw) {
+        Some(timestamp) => timestamp.format(format).to_string(),
+        None => format!("{} (unrecognized format)", raw),
+    })
+}
+
 /// Lists all books with their attributes.
-pub(crate) fn list_books(
-    conn: &Connection,
-    appdb_conn: Option<&Connection>,
-    shelf_name: Option<&str>,
-    unshelved: bool,
-    verbose: bool,
-) -> Result<()> {
+pub(crate) fn list_books(conn: &Connection, appdb_conn: Option<&Connection>, library_dir: &Path, options: &ListOptions) -> Result<()> {
+    let ListOptions {
+        shelf_name, unshelved, username, include_archived, since_id, series, min_series_index, max_series_index,
+        latest_per_series, verbose, count_only, full_paths, compact, group_by_shelf,
+    } = *options;
+
+    // Books archived in Calibre-Web are hidden by default, like in its UI
+    let archived_ids: HashSet<i64> = if let Some(appdb) = appdb_conn {
+        let mut stmt = appdb.prepare("SELECT DISTINCT book_id FROM archived_book WHERE is_archived = 1")?;
+        stmt.query_map([], |row| row.get(0))?
+            .collect::<Result<HashSet<i64>, _>>()?
+    } else {
+        HashSet::new()
+    };
+
+    if group_by_shelf {
+        let appdb = appdb_conn.context("--group-by-shelf requires Calibre-Web's app.db (--appdb-file)")?;
+        return list_books_by_shelf(conn, appdb, &archived_ids, include_archived);
+    }
+
     let book_ids_on_shelf = if unshelved {
         // Find books NOT on any shelf
-        let appdb = appdb_conn.context("app.db connection is required to find unshelved books")?;
+        let appdb = appdb_conn.context("--unshelved requires Calibre-Web's app.db (--appdb-file)")?;
         
         // First get all book IDs from metadata.db
         let mut all_books_stmt = conn.prepare("SELECT id FROM books")?;
         let all_book_ids: Vec<i64> = all_books_stmt.query_map([], |row| row.get(0))?
             .collect::<Result<Vec<i64>, _>>()?;
         
-        // Then get book IDs that ARE on shelves from app.db
-        let mut shelved_stmt = appdb.prepare("SELECT DISTINCT book_id FROM book_shelf_link")?;
-        let shelved_ids: HashSet<i64> = shelved_stmt.query_map([], |row| row.get(0))?
-            .collect::<Result<Vec<i64>, _>>()?
-            .into_iter().collect();
+        // Then get book IDs that ARE on shelves from app.db, optionally only the given user's shelves
+        let shelved_ids: HashSet<i64> = if let Some(uname) = username {
+            let user_id = crate::appdb::resolve_user_id(appdb, Some(uname))?;
+            let mut shelved_stmt = appdb.prepare(
+                "SELECT DISTINCT bsl.book_id FROM book_shelf_link bsl
+                 JOIN shelf s ON s.id = bsl.shelf
+                 WHERE s.user_id = ?1",
+            )?;
+            shelved_stmt.query_map(params![user_id], |row| row.get(0))?
+                .collect::<Result<HashSet<i64>, _>>()?
+        } else {
+            let mut shelved_stmt = appdb.prepare("SELECT DISTINCT book_id FROM book_shelf_link")?;
+            shelved_stmt.query_map([], |row| row.get(0))?
+                .collect::<Result<HashSet<i64>, _>>()?
+        };
         
         // Find books that are NOT on any shelf
         let unshelved_ids: Vec<i64> = all_book_ids.into_iter()
@@ -429,12 +619,18 @@ pub(crate) fn list_books(
             .collect();
 
         if unshelved_ids.is_empty() {
-            println!("No unshelved books found. All books are on at least one shelf.");
+            if count_only {
+                println!("0");
+            } else if let Some(uname) = username {
+                say!("No unshelved books found. All books are on at least one of {}'s shelves.", uname);
+            } else {
+                say!("No unshelved books found. All books are on at least one shelf.");
+            }
             return Ok(());
         }
         Some(unshelved_ids)
     } else if let Some(shelf) = shelf_name {
-        let appdb = appdb_conn.context("app.db connection is required to filter by shelf")?;
+        let appdb = appdb_conn.context("--shelf requires Calibre-Web's app.db (--appdb-file)")?;
         let mut stmt = appdb.prepare(
             "SELECT bsl.book_id FROM book_shelf_link bsl
              JOIN shelf s ON s.id = bsl.shelf
@@ -444,7 +640,11 @@ pub(crate) fn list_books(
         let ids = ids_iter.collect::<Result<Vec<i64>, _>>()?;
 
         if ids.is_empty() {
-            println!("No books found on shelf '{}'.", shelf);
+            if count_only {
+                println!("0");
+            } else {
+                say!("No books found on shelf '{}'.", shelf);
+            }
             return Ok(());
         }
         Some(ids)
@@ -452,32 +652,80 @@ pub(crate) fn list_books(
         None
     };
 
-    let sql = if let Some(ids) = &book_ids_on_shelf {
+    let mut conditions = Vec::new();
+    let mut params_vec: Vec<&dyn rusqlite::ToSql> = Vec::new();
+    if let Some(ids) = &book_ids_on_shelf {
         let placeholders = ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-        format!(
-            "SELECT * FROM books WHERE id IN ({}) ORDER BY title",
-            placeholders
-        )
+        conditions.push(format!("id IN ({})", placeholders));
+        params_vec.extend(ids.iter().map(|id| id as &dyn rusqlite::ToSql));
+    }
+    if let Some(since) = &since_id {
+        conditions.push("id > ?".to_string());
+        params_vec.push(since);
+    }
+    if let Some(series_name) = &series {
+        conditions.push(
+            "id IN (SELECT bsl.book FROM books_series_link bsl
+                    JOIN series s ON s.id = bsl.series
+                    WHERE s.name = ? COLLATE NOCASE)"
+                .to_string(),
+        );
+        params_vec.push(series_name);
+    }
+    if let Some(min) = &min_series_index {
+        conditions.push("series_index >= ?".to_string());
+        params_vec.push(min);
+    }
+    if let Some(max) = &max_series_index {
+        conditions.push("series_index <= ?".to_string());
+        params_vec.push(max);
+    }
+    if latest_per_series {
+        // Ties on the index go to the most recently added book
+        conditions.push(
+            "id IN (SELECT book FROM (
+                        SELECT bsl.book, ROW_NUMBER() OVER (
+                            PARTITION BY bsl.series ORDER BY b.series_index DESC, b.id DESC
+                        ) AS rank
+                        FROM books_series_link bsl
+                        JOIN books b ON b.id = bsl.book)
+                    WHERE rank = 1)"
+                .to_string(),
+        );
+    }
+    let where_clause = if conditions.is_empty() {
+        String::new()
     } else {
-        "SELECT * FROM books ORDER BY title".to_string()
+        format!(" WHERE {}", conditions.join(" AND "))
     };
-
-    let mut stmt = conn.prepare(&sql)?;
-
-    let params_vec: Vec<&dyn rusqlite::ToSql> = if let Some(ids) = &book_ids_on_shelf {
-        ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect()
+    let order_by = if latest_per_series {
+        "(SELECT COALESCE(s.sort, s.name) FROM books_series_link bsl JOIN series s ON s.id = bsl.series
+          WHERE bsl.book = books.id) COLLATE NOCASE, title"
+    } else if series.is_some() {
+        "series_index, title"
     } else {
-        vec![]
+        "title"
     };
+    let sql = format!("SELECT * FROM books{} ORDER BY {}", where_clause, order_by);
+
+    let mut stmt = conn.prepare(&sql)?;
 
     let mut rows = stmt.query(&params_vec[..])?;
 
-    if unshelved {
-        println!("📚 Listing books not on any shelf...\n");
+    if count_only {
+        // Only the total is printed, after the loop
+    } else if compact {
+        // Only the book lines go to stdout
+    } else if let (true, Some(uname)) = (unshelved, username) {
+        say!("📚 Listing books not on any of {}'s shelves...\n", uname);
+    } else if unshelved {
+        say!("📚 Listing books not on any shelf...\n");
     } else if let Some(shelf) = shelf_name {
-        println!("📚 Listing books on shelf '{}'...\n", shelf);
+        say!("📚 Listing books on shelf '{}'...\n", shelf);
+    } else if let Some(series_name) = series {
+        say!("📚 Listing books in series '{}'...\n", series_name);
     } else {
-        println!("📚 Listing all books in the library...\n");
+        say!("📚 Listing all books in the library...\n");
     }
 
     let mut shelf_stmt = appdb_conn
@@ -493,15 +741,31 @@ pub(crate) fn list_books(
         .transpose()?;
 
     let mut count = 0;
+    let mut hidden_archived = 0;
+    let mut highest_id = None;
     while let Some(row) = rows.next()? {
-        count += 1;
-        println!("{}", "─".repeat(80));
         let id: i64 = row.get("id")?;
-        println!("ID:          {}", id);
-        println!("Title:       {}", row.get::<_, String>("title")?);
+        highest_id = highest_id.max(Some(id));
+        let is_archived = archived_ids.contains(&id);
+        if is_archived && !include_archived {
+            hidden_archived += 1;
+            continue;
+        }
+        count += 1;
+        if count_only {
+            continue;
+        }
+        if compact {
+            println!("{}", compact_book_line(conn, row, is_archived)?);
+            continue;
+        }
+        say!("{}", "─".repeat(80));
+        say!("ID:          {}", id);
+        let archived_tag = if is_archived { " [archived]" } else { "" };
+        say!("Title:       {}{}", row.get::<_, String>("title")?, archived_tag);
 
         let authors = get_linked_items(conn, "authors", "books_authors_link", "author", id)?;
-        println!("Authors:     {}", authors.join(" & "));
+        say!("Authors:     {}", authors.join(" & "));
 
         if let Some(stmt) = &mut shelf_stmt {
             let shelves_iter = stmt.query_map(params![id], |row| {
@@ -512,153 +776,549 @@ pub(crate) fn list_books(
             })?;
             let shelves: Vec<(String, Option<String>)> = shelves_iter.collect::<Result<Vec<_>, _>>()?;
             if !shelves.is_empty() {
-                println!("Shelves:");
+                say!("Shelves:");
                 for (shelf_name, username) in shelves {
                     let user_display = username.unwrap_or_else(|| "admin".to_string());
-                    println!("            - {} (User: {})", shelf_name, user_display);
+                    say!("            - {} (User: {})", shelf_name, user_display);
                 }
             }
         }
 
         let series = get_linked_items(conn, "series", "books_series_link", "series", id)?;
         if !series.is_empty() {
-            println!("Series:      {} (#{})", series.join(", "), row.get::<_, f64>("series_index")?);
+            say!("Series:      {} (#{})", series.join(", "), format_series_index(row.get::<_, f64>("series_index")?));
         }
 
         let tags = get_linked_items(conn, "tags", "books_tags_link", "tag", id)?;
         if !tags.is_empty() {
-            println!("Tags:        {}", tags.join(", "));
+            say!("Tags:        {}", tags.join(", "));
         }
 
         let publisher =
             get_linked_items(conn, "publishers", "books_publishers_link", "publisher", id)?;
         if !publisher.is_empty() {
-            println!("Publisher:   {}", publisher.join(", "));
+            say!("Publisher:   {}", publisher.join(", "));
         }
 
-        println!("Published:   {}", row.get::<_, DateTime<Utc>>("pubdate")?.format("%Y-%m-%d"));
-        println!("Path:        {}", row.get::<_, String>("path")?);
+        say!("Published:   {}", display_timestamp(row, "pubdate", "%Y-%m-%d")?);
+        let book_path: String = row.get("path")?;
+        say!("Path:        {}", book_path);
+        if full_paths {
+            for file in get_book_file_paths(conn, library_dir, id, &book_path)? {
+                say!("File:        {}", file.display());
+            }
+        }
 
         if verbose {
-            println!("Sort:        {}", row.get::<_, String>("sort")?);
-            println!("Author Sort: {}", row.get::<_, String>("author_sort")?);
-            println!("Timestamp:   {}", row.get::<_, DateTime<Utc>>("timestamp")?);
-            println!("Last Mod:    {}", row.get::<_, DateTime<Utc>>("last_modified")?);
-            println!("UUID:        {}", row.get::<_, String>("uuid")?);
-            println!("Has Cover:   {}", row.get::<_, bool>("has_cover")?);
-
-            if let Some(language) = get_book_language(conn, id)? {
-                println!("Language:    {}", language);
+            say!("Sort:        {}", row.get::<_, String>("sort")?);
+            say!("Author Sort: {}", row.get::<_, String>("author_sort")?);
+            say!("Timestamp:   {}", display_timestamp(row, "timestamp", "%Y-%m-%d %H:%M:%S%.f UTC")?);
+            say!("Last Mod:    {}", display_timestamp(row, "last_modified", "%Y-%m-%d %H:%M:%S%.f UTC")?);
+            say!("UUID:        {}", row.get::<_, String>("uuid")?);
+            say!("Has Cover:   {}", row.get::<_, bool>("has_cover")?);
+
+            let languages = get_book_languages(conn, id)?;
+            if !languages.is_empty() {
+                say!("Languages:   {}", languages.join(", "));
             }
 
             let identifiers = get_book_identifiers(conn, id)?;
             if !identifiers.is_empty() {
-                println!("Identifiers:");
+                say!("Identifiers:");
                 for (id_type, id_val) in identifiers {
-                    println!("  {}: {}", id_type, id_val);
+                    say!("  {}: {}", id_type, id_val);
                 }
             }
         }
     }
     
+    if count_only {
+        println!("{}", count);
+        return Ok(());
+    }
     if count > 0 {
-        println!("{}", "─".repeat(80));
+        if !compact {
+            say!("{}", "─".repeat(80));
+        }
+    } else if let Some(series_name) = series {
+        say!("No books found in series '{}'.", series_name);
+    }
+    if hidden_archived > 0 {
+        say!("({} archived book(s) hidden; use --include-archived to show them)", hidden_archived);
+    }
+    if let (Some(since), Some(highest)) = (since_id, highest_id) {
+        say!("Books with ID > {} listed. Highest ID: {} (use --since-id {} next time)", since, highest, highest);
+    }
+
+    Ok(())
+}
+
+
+/// Prints every shelf in app.db with its books in shelf order, then the books on no shelf, for
+/// `list --group-by-shelf`. A book on several shelves is listed under each of them.
+fn list_books_by_shelf(conn: &Connection, appdb: &Connection, archived_ids: &HashSet<i64>, include_archived: bool) -> Result<()> {
+    say!("📚 Listing books by shelf...\n");
+
+    let mut books_stmt = conn.prepare("SELECT id, title FROM books ORDER BY title")?;
+    let books = books_stmt
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let titles: HashMap<i64, &str> = books.iter().map(|(id, title)| (*id, title.as_str())).collect();
+
+    let mut shelf_stmt = appdb.prepare(
+        "SELECT s.id, s.name, u.name FROM shelf s
+         LEFT JOIN user u ON s.user_id = u.id
+         ORDER BY s.name COLLATE NOCASE, u.name",
+    )?;
+    let shelves = shelf_stmt
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, Option<String>>(2)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut member_stmt = appdb.prepare("SELECT book_id FROM book_shelf_link WHERE shelf = ?1 ORDER BY \"order\", id")?;
+
+    let mut hidden_archived = HashSet::new();
+    let mut print_book = |id: i64, title: &str| -> Result<bool> {
+        let is_archived = archived_ids.contains(&id);
+        if is_archived && !include_archived {
+            hidden_archived.insert(id);
+            return Ok(false);
+        }
+        let authors = get_linked_items(conn, "authors", "books_authors_link", "author", id)?;
+        let archived_tag = if is_archived { " [archived]" } else { "" };
+        say!("  {:>5}  {}{} — {}", id, title, archived_tag, authors.join(" & "));
+        Ok(true)
+    };
+
+    let mut shelved = HashSet::new();
+    for (shelf_id, shelf_name, username) in &shelves {
+        let members = member_stmt
+            .query_map(params![shelf_id], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        say!("{}", "─".repeat(80));
+        say!("Shelf: {} (User: {})", shelf_name, username.as_deref().unwrap_or("Unknown"));
+        let mut listed = 0;
+        for book_id in members {
+            shelved.insert(book_id);
+            // Links to books that are no longer in metadata.db are left to `cleanup`
+            if let Some(title) = titles.get(&book_id)
+                && print_book(book_id, title)?
+            {
+                listed += 1;
+            }
+        }
+        if listed == 0 {
+            say!("  (no books)");
+        }
+    }
+
+    say!("{}", "─".repeat(80));
+    say!("Unshelved");
+    let mut listed = 0;
+    for (id, title) in books.iter().filter(|(id, _)| !shelved.contains(id)) {
+        if print_book(*id, title)? {
+            listed += 1;
+        }
+    }
+    if listed == 0 {
+        say!("  (no books)");
     }
+    say!("{}", "─".repeat(80));
 
+    if !hidden_archived.is_empty() {
+        say!("({} archived book(s) hidden; use --include-archived to show them)", hidden_archived.len());
+    }
     Ok(())
 }
 
+/// Formats a book as `ID<TAB>Title<TAB>Authors<TAB>Series #n` for `list --compact`. Tabs and
+/// line breaks inside values become spaces so every book stays on one line with four columns.
+fn compact_book_line(conn: &Connection, row: &rusqlite::Row, is_archived: bool) -> Result<String> {
+    let clean = |value: &str| value.replace(['\t', '\n', '\r'], " ");
+    let id: i64 = row.get("id")?;
+    let archived_tag = if is_archived { " [archived]" } else { "" };
+    let title = format!("{}{}", row.get::<_, String>("title")?, archived_tag);
+    let authors = get_linked_items(conn, "authors", "books_authors_link", "author", id)?.join(" & ");
+    let series = match get_linked_items(conn, "series", "books_series_link", "series", id)?.first() {
+        Some(name) => format!("{} #{}", name, format_series_index(row.get::<_, f64>("series_index")?)),
+        None => String::new(),
+    };
+    Ok(format!("{}\t{}\t{}\t{}", id, clean(&title), clean(&authors), clean(&series)))
+}
 
-/// Deletes a book from the database and filesystem.
-pub(crate) fn delete_book(calibre_conn: &mut Connection, appdb_conn: Option<&Connection>, library_db_path: &Path, book_id: i64) -> Result<()> {
-    // Validate book ID
+/// Copies a book's `cover.jpg` to `output`, scaled down to `width` pixels wide if given.
+pub(crate) fn dump_cover(conn: &Connection, library_dir: &Path, book_id: i64, output: &Path, width: Option<u32>) -> Result<(), CwhError> {
     validate_id(book_id, "book")?;
-    
-    // Create backup before destructive operation
-    crate::utils::backup_database(library_db_path, "delete_book")
-        .context("Failed to create database backup before deletion")?;
-    
-    let book_info: Option<(String, String)> = calibre_conn.query_row(
-            "SELECT title, path FROM books WHERE id = ?1",
-            params![book_id],
-            |row| Ok((row.get(0)?, row.get(1)?)),
-        )
-        .optional()
-        .with_context(|| format!("Failed to query book with ID {}", book_id))?;
-
-    let book_path_str = if let Some((title, path)) = book_info.as_ref() {
-        println!("You are about to delete:");
-        println!("  ID:    {}", book_id);
-        println!("  Title: {}", title);
-        path.clone()
-    } else {
-        println!("Warning: Book with ID {} not found in Calibre database. Attempting to clean up Calibre-Web shelves and filesystem.", book_id);
-        String::new()
+    let book_path: String = conn.query_row(
+        "SELECT path FROM books WHERE id = ?1",
+        params![book_id],
+        |row| row.get(0),
+    )
+    .optional()?
+    .ok_or_else(|| CwhError::NotFound(format!("No book found with ID {}", book_id)))?;
+
+    let cover_path = library_dir.join(&book_path).join("cover.jpg");
+    if !cover_path.is_file() {
+        return Err(CwhError::NotFound(format!("Book {} has no cover: {:?} does not exist", book_id, cover_path)));
+    }
+
+    let cover_data = fs::read(&cover_path)
+        .with_context(|| format!("Failed to read {:?}", cover_path))?;
+    let cover_data = match width {
+        Some(width) => crate::epub::scale_cover_to_width(&cover_data, width)
+            .with_context(|| format!("Failed to scale {:?}", cover_path))?,
+        None => cover_data,
     };
+    fs::write(output, &cover_data)
+        .with_context(|| format!("Failed to write the cover to {:?}", output))?;
+
+    say!("✅ Wrote the cover of book {} to {:?}", book_id, output);
+    Ok(())
+}
+
+/// Deletes books from the database and filesystem. The metadata.db rows are removed in a single
+/// transaction after one backup, then the app.db shelf links in another, then each book's
+/// directory. Directories that can't be removed don't stop the rest; the final summary lists
+/// them for manual cleanup and the command fails with a file system error.
+pub(crate) fn delete_books(calibre_conn: &mut Connection, appdb_conn: Option<&Connection>, library_db_path: &Path, book_ids: &[i64], scope: DeleteScope) -> Result<(), CwhError> {
+    // Validate book IDs
+    for &book_id in book_ids {
+        validate_id(book_id, "book")?;
+    }
+    let delete_rows = scope != DeleteScope::FilesOnly;
+
+    // Create backup before destructive operation
+    if delete_rows {
+        crate::utils::backup_database(library_db_path, "delete_book")
+            .context("Failed to create database backup before deletion")?;
+    }
 
     // Delete from DB. Triggers will handle linked tables.
     let tx = calibre_conn.transaction()
         .context("Failed to start deletion transaction")?;
-    let affected = tx.execute("DELETE FROM books WHERE id = ?1", params![book_id])
-        .with_context(|| format!("Failed to delete book {} from database", book_id))?;
+    let mut book_paths = Vec::with_capacity(book_ids.len());
+    for &book_id in book_ids {
+        let book_info: Option<(String, String)> = tx.query_row(
+                "SELECT title, path FROM books WHERE id = ?1",
+                params![book_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .with_context(|| format!("Failed to query book with ID {}", book_id))?;
+
+        let book_path_str = if let Some((title, path)) = book_info.as_ref() {
+            say!("You are about to delete:");
+            say!("  ID:    {}", book_id);
+            say!("  Title: {}", title);
+            path.clone()
+        } else if delete_rows {
+            say!("Warning: Book with ID {} not found in Calibre database. Attempting to clean up Calibre-Web shelves and filesystem.", book_id);
+            String::new()
+        } else {
+            say!("Warning: Book with ID {} not found in Calibre database, so its directory is unknown; skipping.", book_id);
+            String::new()
+        };
+
+        if delete_rows {
+            let affected = tx.execute("DELETE FROM books WHERE id = ?1", params![book_id])
+                .with_context(|| format!("Failed to delete book {} from database", book_id))?;
+            if affected == 0 && book_info.is_some() {
+                return Err(CwhError::NotFound(format!("No book found with ID {} to delete.", book_id)));
+            }
+        }
+        book_paths.push((book_id, book_path_str));
+    }
     tx.commit()
         .context("Failed to commit deletion transaction")?;
 
-    if affected == 0 && book_info.is_some() {
-         anyhow::bail!("No book found with ID {} to delete.", book_id);
+    let mut summary = DeleteSummary {
+        rows_deleted: if delete_rows { book_paths.iter().filter(|(_, path)| !path.is_empty()).count() } else { 0 },
+        ..Default::default()
+    };
+    if delete_rows
+        && let Some(conn) = appdb_conn
+        && let Err(e) = remove_from_shelves(conn, book_ids, &mut summary) {
+            summary.print(scope, true);
+            return Err(CwhError::Other(e.context(
+                "The books were deleted from metadata.db but removing them from Calibre-Web's shelves failed; run clean-db to remove the leftover shelf links",
+            )));
+        }
+
+    let library_dir = library_db_path.parent().unwrap_or_else(|| Path::new("."));
+    for (book_id, book_path_str) in &book_paths {
+        if book_path_str.is_empty() {
+            continue;
+        }
+        let book_dir = library_dir.join(book_path_str);
+        if scope == DeleteScope::DatabaseOnly {
+            if book_dir.exists() {
+                summary.kept.push(book_dir);
+            }
+            continue;
+        }
+        match remove_book_directory(library_dir, &book_dir) {
+            Ok(true) => summary.dirs_removed += 1,
+            Ok(false) => say!(" -> Book directory for ID {} not found, skipping filesystem delete: {:?}", book_id, book_dir),
+            Err(e) => {
+                say!(" -> ⚠️  Could not remove the directory of book ID {}: {:#}", book_id, e);
+                summary.failed.push((book_dir, format!("{:#}", e)));
+            }
+        }
+    }
+
+    summary.print(scope, appdb_conn.is_some());
+    if !summary.failed.is_empty() {
+        return Err(CwhError::FileSystem(anyhow::anyhow!(
+            "{} book directory(ies) could not be removed; delete them by hand", summary.failed.len(),
+        )));
     }
-    
-    // Also delete from Calibre-Web shelves if app.db is provided
-    if let Some(conn) = appdb_conn {
-        let mut stmt = conn.prepare("SELECT shelf FROM book_shelf_link WHERE book_id = ?1")?;
-        let shelf_ids: Vec<i64> = stmt.query_map(params![book_id], |row| row.get(0))?.collect::<Result<Vec<_>, _>>()?;
-
-        conn.execute("DELETE FROM book_shelf_link WHERE book_id = ?1", params![book_id])?;
-        println!(" -> Removed book from all Calibre-Web shelves.");
-
-        for shelf_id in shelf_ids {
-            let count: i64 = conn.query_row("SELECT COUNT(*) FROM book_shelf_link WHERE shelf = ?1", params![shelf_id], |row| row.get(0))?;
-            if count == 0 {
-                let shelf_name: String = conn.query_row("SELECT name FROM shelf WHERE id = ?1", params![shelf_id], |row| row.get(0))?;
-                conn.execute("DELETE FROM shelf WHERE id = ?1", params![shelf_id])?;
-                println!(" -> Removed empty shelf '{}'.", shelf_name);
+    Ok(())
+}
+
+/// What `delete_books` removed, for its final report
+#[derive(Debug, Default)]
+struct DeleteSummary {
+    rows_deleted: usize,
+    shelf_links_removed: usize,
+    shelves_removed: Vec<String>,
+    dirs_removed: usize,
+    /// Book directories left on disk by `--db-only`
+    kept: Vec<PathBuf>,
+    /// Book directories that couldn't be removed, with the error
+    failed: Vec<(PathBuf, String)>,
+}
+
+impl DeleteSummary {
+    fn print(&self, scope: DeleteScope, has_appdb: bool) {
+        say!("\n📊 Deletion summary:");
+        if scope == DeleteScope::FilesOnly {
+            say!("   metadata.db: unchanged (--files-only); run check-formats --fix to update the format records");
+        } else {
+            say!("   metadata.db: deleted {} book(s)", self.rows_deleted);
+            if has_appdb {
+                say!("   app.db:      removed {} shelf link(s)", self.shelf_links_removed);
+                if !self.shelves_removed.is_empty() {
+                    say!("                removed now-empty shelf(s): {}", self.shelves_removed.join(", "));
+                }
+            }
+        }
+        if scope != DeleteScope::DatabaseOnly {
+            say!("   Files:       removed {} book directory(ies)", self.dirs_removed);
+        }
+        if !self.kept.is_empty() {
+            say!("   Files kept on disk (--db-only):");
+            for dir in &self.kept {
+                say!("      - {:?}", dir);
+            }
+        }
+        if !self.failed.is_empty() {
+            say!("   ⚠️  Left on disk for manual cleanup:");
+            for (dir, error) in &self.failed {
+                say!("      - {:?} ({})", dir, error);
             }
         }
     }
-    
-    println!(" -> Successfully deleted database entry for book ID {}", book_id);
-
-    // Delete cover image and directory from filesystem
-    if !book_path_str.is_empty() {
-        let book_dir = library_db_path.parent().unwrap_or_else(|| Path::new(".")).join(book_path_str);
-        // Delete cover image if it exists
-        let cover_path = book_dir.join("cover.jpg");
-        if cover_path.exists() {
-            fs::remove_file(&cover_path)
-                .with_context(|| format!("Failed to remove cover image: {:?}", cover_path))?;
-            println!(" -> Cover image deleted.");
-        }
-        if book_dir.exists() {
-            fs::remove_dir_all(&book_dir)
-                .with_context(|| format!("Failed to delete book directory: {:?}", book_dir))?;
-            println!(" -> Successfully deleted book directory: {:?}", book_dir);
-
-            // Check if the parent author directory is now empty
-            if let Some(author_dir) = book_dir.parent()
-                && let Ok(mut entries) = fs::read_dir(author_dir)
-                    && entries.next().is_none()
-                        && fs::remove_dir(author_dir).is_ok() {
-                            println!(" -> Successfully deleted empty author directory: {:?}", author_dir);
-                        }
+}
+
+/// Removes the books from every Calibre-Web shelf, and shelves left empty, in one app.db transaction
+fn remove_from_shelves(conn: &Connection, book_ids: &[i64], summary: &mut DeleteSummary) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    let mut shelf_ids = Vec::new();
+    for &book_id in book_ids {
+        let mut stmt = tx.prepare("SELECT shelf FROM book_shelf_link WHERE book_id = ?1")?;
+        shelf_ids.extend(stmt.query_map(params![book_id], |row| row.get::<_, i64>(0))?.collect::<Result<Vec<_>, _>>()?);
+        summary.shelf_links_removed += tx.execute("DELETE FROM book_shelf_link WHERE book_id = ?1", params![book_id])?;
+    }
+    shelf_ids.sort_unstable();
+    shelf_ids.dedup();
+
+    let mut shelves_removed = Vec::new();
+    for shelf_id in shelf_ids {
+        let count: i64 = tx.query_row("SELECT COUNT(*) FROM book_shelf_link WHERE shelf = ?1", params![shelf_id], |row| row.get(0))?;
+        if count == 0 {
+            let shelf_name: String = tx.query_row("SELECT name FROM shelf WHERE id = ?1", params![shelf_id], |row| row.get(0))?;
+            tx.execute("DELETE FROM shelf WHERE id = ?1", params![shelf_id])?;
+            say!(" -> Removed empty shelf '{}'.", shelf_name);
+            shelves_removed.push(shelf_name);
+        }
+    }
+    tx.commit()?;
+    say!(" -> Removed the book(s) from all Calibre-Web shelves.");
+    summary.shelves_removed = shelves_removed;
+    Ok(())
+}
+
+/// Deletes a book's directory and then its author directory (and `--shard-by-id` bucket) once
+/// empty. Returns false if the directory didn't exist.
+fn remove_book_directory(library_dir: &Path, book_dir: &Path) -> Result<bool> {
+    if !book_dir.exists() {
+        return Ok(false);
+    }
+    fs::remove_dir_all(book_dir)
+        .with_context(|| format!("Failed to delete book directory: {:?}", book_dir))?;
+    say!(" -> Successfully deleted book directory: {:?}", book_dir);
+
+    // A flat --path-pattern puts books directly in the library, which must never be removed.
+    for parent_dir in book_dir.ancestors().skip(1).take_while(|dir| *dir != library_dir && dir.starts_with(library_dir)) {
+        let is_empty = fs::read_dir(parent_dir).is_ok_and(|mut entries| entries.next().is_none());
+        if !is_empty || fs::remove_dir(parent_dir).is_err() {
+            break;
+        }
+        say!(" -> Successfully deleted empty directory: {:?}", parent_dir);
+    }
+    Ok(true)
+}
+
+/// Returns the absolute paths of all of a book's files, resolved from `books.path` and the `data` table
+fn get_book_file_paths(conn: &Connection, library_dir: &Path, book_id: i64, book_path: &str) -> Result<Vec<PathBuf>> {
+    let book_dir = std::path::absolute(library_dir.join(book_path))
+        .with_context(|| format!("Failed to resolve the directory of book {}", book_id))?;
+    let mut stmt = conn.prepare("SELECT name, format FROM data WHERE book = ?1 ORDER BY format")?;
+    let files = stmt.query_map(params![book_id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+        .map(|file| file.map(|(name, format)| book_dir.join(format!("{}.{}", name, format.to_lowercase()))))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(files)
+}
+
+/// Returns the path of a book's file in the given format, resolved from `books.path`
+/// and the `data` table, or `None` if the book has no file in that format.
+fn get_book_format_path(conn: &Connection, library_dir: &Path, book_id: i64, format: &str) -> Result<Option<PathBuf>> {
+    let file: Option<(String, String)> = conn.query_row(
+        "SELECT b.path, d.name FROM data d JOIN books b ON d.book = b.id WHERE b.id = ?1 AND d.format = ?2",
+        params![book_id, format],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?;
+
+    Ok(file.map(|(book_path, data_name)| {
+        library_dir.join(book_path).join(format!("{}.{}", data_name, format.to_lowercase()))
+    }))
+}
+
+/// Returns true if both files have the same size and SHA-1 hash
+fn same_file_contents(a: &Path, b: &Path) -> Result<bool> {
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+    Ok(calculate_file_hash(a, ChecksumAlgorithm::Sha1)? == calculate_file_hash(b, ChecksumAlgorithm::Sha1)?)
+}
+
+/// Copies the EPUB file of each book into a flat directory as `{author} - {title}.epub`, adding
+/// ` ({id})` only when another exported book already took that name, so re-running an export
+/// into the same directory refreshes the same files. Files that are already identical are left
+/// alone. Books without an EPUB file on disk are skipped and reported. The library is only read.
+pub(crate) fn export_books(conn: &Connection, library_dir: &Path, book_ids: &[i64], output_dir: &Path) -> Result<()> {
+    fs::create_dir_all(output_dir)
+        .with_context(|| format!("Failed to create output directory {:?}", output_dir))?;
+
+    let mut exported = 0;
+    let mut up_to_date = 0;
+    let mut skipped = Vec::new();
+    let mut claimed_names = HashSet::new();
+
+    for &book_id in book_ids {
+        let title: Option<String> = conn.query_row(
+            "SELECT title FROM books WHERE id = ?1",
+            params![book_id],
+            |row| row.get(0),
+        ).optional()?;
+        let Some(title) = title else {
+            skipped.push(format!("ID {}: not in Calibre library", book_id));
+            continue;
+        };
+
+        let source = match get_book_format_path(conn, library_dir, book_id, "EPUB")? {
+            Some(path) if path.exists() => path,
+            Some(path) => {
+                skipped.push(format!("ID {} — '{}': file not found ({:?})", book_id, title, path));
+                continue;
+            }
+            None => {
+                skipped.push(format!("ID {} — '{}': no EPUB format", book_id, title));
+                continue;
+            }
+        };
+
+        let authors = get_linked_items(conn, "authors", "books_authors_link", "author", book_id)?;
+        let base_name = format!("{} - {}", get_valid_filename(&authors.join(" & "), 96), get_valid_filename(&title, 96));
+        // Two books can share an author and title; keep both by adding the ID to the later one
+        let file_name = if claimed_names.insert(base_name.clone()) {
+            format!("{}.epub", base_name)
         } else {
-            println!(
-                " -> Book directory not found, skipping filesystem delete: {:?}",
-                book_dir
-            );
+            format!("{} ({}).epub", base_name, book_id)
+        };
+        let dest = output_dir.join(file_name);
+        if dest.exists() && same_file_contents(&source, &dest)? {
+            say!(" -> '{}' is already exported to {:?}", title, dest);
+            up_to_date += 1;
+            continue;
         }
+
+        fs::copy(&source, &dest)
+            .with_context(|| format!("Failed to copy {:?} to {:?}", source, dest))?;
+        say!(" -> Exported '{}' to {:?}", title, dest);
+        exported += 1;
+    }
+
+    say!("\n📊 Export complete:");
+    say!("   ✅ Exported: {}", exported);
+    if up_to_date > 0 {
+        say!("   ⏭️  Already up to date: {}", up_to_date);
+    }
+    if !skipped.is_empty() {
+        say!("   ⚠️  Skipped: {}", skipped.len());
+        for reason in &skipped {
+            say!("      - {}", reason);
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists the books linked to the undetermined language "und" with the language codes their
+/// book files actually list, then totals each unmapped code so the language map can be extended.
+pub(crate) fn audit_languages(conn: &Connection, library_dir: &Path) -> Result<()> {
+    say!("🔍 Looking for books stored with an undetermined language...");
+
+    let mut stmt = conn.prepare(
+        "SELECT b.id, b.title, b.path FROM books b
+         JOIN books_languages_link bll ON bll.book = b.id
+         JOIN languages l ON l.id = bll.lang_code
+         WHERE l.lang_code = 'und'
+         ORDER BY b.title",
+    )?;
+    let books: Vec<(i64, String, String)> = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if books.is_empty() {
+        say!(" -> No books have the language \"und\".");
+        return Ok(());
+    }
+
+    let mut unmapped: BTreeMap<String, usize> = BTreeMap::new();
+    let metadata_options = crate::models::MetadataOptions::default();
+    for (book_id, title, book_path) in &books {
+        let book_file = get_book_file_paths(conn, library_dir, *book_id, book_path)?
+            .into_iter()
+            .find(|path| path.is_file());
+        let source_codes = match book_file.map(|path| crate::epub::get_book_metadata(&path, &metadata_options)) {
+            Some(Ok(metadata)) if metadata.unmapped_languages.is_empty() => "no unmapped codes in the file".to_string(),
+            Some(Ok(metadata)) => {
+                for code in &metadata.unmapped_languages {
+                    *unmapped.entry(code.clone()).or_default() += 1;
+                }
+                metadata.unmapped_languages.join(", ")
+            }
+            Some(Err(e)) => format!("file unreadable: {}", e),
+            None => "no book file found".to_string(),
+        };
+        say!("    ID {} — '{}': {}", book_id, title, source_codes);
     }
 
-    println!("\n✅ Success! Book ID {} has been deleted.", book_id);
+    say!("\n📊 {} book(s) have the language \"und\".", books.len());
+    if !unmapped.is_empty() {
+        say!("   Source codes to add to --lang-map-file:");
+        for (code, count) in &unmapped {
+            say!("   '{}' — {} book(s)", code, count);
+        }
+    }
     Ok(())
 }
 
@@ -689,13 +1349,65 @@ fn get_linked_items(
     items_iter.collect::<Result<Vec<_>, _>>().map_err(Into::into)
 }
 
-/// Helper function to get the language of a book.
-fn get_book_language(conn: &Connection, book_id: i64) -> Result<Option<String>> {
-    conn.query_row(
-        "SELECT l.lang_code FROM languages l JOIN books_languages_link bll ON l.id = bll.lang_code WHERE bll.book = ?1",
-        params![book_id],
-        |row| row.get(0),
-    ).optional().map_err(Into::into)
+/// Helper function to get the languages of a book, in link order.
+fn get_book_languages(conn: &Connection, book_id: i64) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT l.lang_code FROM languages l JOIN books_languages_link bll ON l.id = bll.lang_code
+         WHERE bll.book = ?1 ORDER BY bll.item_order, bll.id",
+    )?;
+    let languages = stmt.query_map(params![book_id], |row| row.get(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+    Ok(languages)
+}
+
+/// Prints the books that have an identifier of the given type and value, as text or JSON.
+/// ISBNs compare without hyphens or spaces; types and values compare case-insensitively.
+pub(crate) fn find_by_identifier(conn: &Connection, id_type: &str, value: &str, format: OutputFormat) -> Result<(), CwhError> {
+    let is_isbn = id_type.eq_ignore_ascii_case("isbn");
+    let normalize = |text: &str| -> String {
+        if is_isbn {
+            text.chars().filter(|c| !matches!(c, '-' | ' ')).collect()
+        } else {
+            text.trim().to_string()
+        }
+    };
+    let wanted = normalize(value);
+
+    let mut stmt = conn.prepare(
+        "SELECT b.id, b.title, COALESCE(b.author_sort, ''), i.val FROM identifiers i
+         JOIN books b ON b.id = i.book
+         WHERE i.type = ?1 COLLATE NOCASE
+         ORDER BY b.id",
+    )?;
+    let matches: Vec<(i64, String, String, String)> = stmt
+        .query_map(params![id_type], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get::<_, String>(3)?)))?
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|(_, _, _, val)| normalize(val).eq_ignore_ascii_case(&wanted))
+        .collect();
+
+    if matches.is_empty() {
+        return Err(CwhError::NotFound(format!("No book has identifier {}:{}", id_type, value)));
+    }
+
+    match format {
+        OutputFormat::Json => {
+            let books: Vec<String> = matches.iter()
+                .map(|(id, title, author_sort, val)| format!(
+                    "{{\"id\": {}, \"title\": \"{}\", \"author_sort\": \"{}\", \"identifier\": \"{}\"}}",
+                    id, json_escape(title), json_escape(author_sort), json_escape(val)
+                ))
+                .collect();
+            println!("[{}]", books.join(", "));
+        }
+        OutputFormat::Text => {
+            say!("🔎 Books with identifier {}:{}", id_type, value);
+            for (id, title, author_sort, val) in &matches {
+                say!("   [{}] {} by {} ({})", id, title, author_sort, val);
+            }
+        }
+    }
+    Ok(())
 }
 
 /// Helper function to get the identifiers of a book.
@@ -708,3 +1420,197 @@ fn get_book_identifiers(conn: &Connection, book_id: i64) -> Result<Vec<(String,
     })?;
     identifiers_iter.collect::<Result<Vec<_>, _>>().map_err(Into::into)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an in-memory database with the parts of the Calibre schema used when adding books
+    fn test_calibre_db() -> Connection {
+        let conn = Connection::open_in_memory().expect("failed to open in-memory database");
+        conn.execute_batch(
+            "CREATE TABLE books (id INTEGER PRIMARY KEY AUTOINCREMENT, title TEXT NOT NULL, sort TEXT, timestamp TIMESTAMP,
+                pubdate TIMESTAMP, series_index REAL NOT NULL DEFAULT 1.0, author_sort TEXT, path TEXT NOT NULL DEFAULT '',
+                uuid TEXT, has_cover BOOL DEFAULT 0, last_modified TIMESTAMP);
+             CREATE TABLE authors (id INTEGER PRIMARY KEY, name TEXT NOT NULL, sort TEXT, link TEXT NOT NULL DEFAULT '', UNIQUE(name));
+             CREATE TABLE books_authors_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, author INTEGER NOT NULL);
+             CREATE TABLE data (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, format TEXT NOT NULL, uncompressed_size INTEGER NOT NULL, name TEXT NOT NULL);
+             CREATE TABLE comments (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, text TEXT NOT NULL, UNIQUE(book));
+             CREATE TABLE languages (id INTEGER PRIMARY KEY, lang_code TEXT NOT NULL, link TEXT NOT NULL DEFAULT '');
+             CREATE TABLE books_languages_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, lang_code INTEGER NOT NULL, item_order INTEGER NOT NULL DEFAULT 0);
+             CREATE TABLE identifiers (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, type TEXT NOT NULL, val TEXT NOT NULL);
+             CREATE TABLE publishers (id INTEGER PRIMARY KEY, name TEXT NOT NULL, sort TEXT, link TEXT NOT NULL DEFAULT '');
+             CREATE TABLE books_publishers_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, publisher INTEGER NOT NULL);
+             CREATE TABLE series (id INTEGER PRIMARY KEY, name TEXT NOT NULL, sort TEXT, link TEXT NOT NULL DEFAULT '');
+             CREATE TABLE books_series_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, series INTEGER NOT NULL);
+             CREATE TABLE metadata_dirtied (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, UNIQUE(book));",
+        ).expect("failed to create test schema");
+        conn
+    }
+
+    /// Write options matching the `add` defaults
+    fn test_write_options() -> BookWriteOptions<'static> {
+        BookWriteOptions {
+            added_date: None,
+            path_pattern: crate::utils::DEFAULT_PATH_PATTERN,
+            author_sort: AuthorSortMethod::default(),
+            author_sort_map: None,
+            comments_strategy: CommentsStrategy::Preserve,
+            prefer_format: &[],
+            checksum: crate::cli::ChecksumAlgorithm::Sha1,
+            skip_hash_check: false,
+            dry_run: false,
+        }
+    }
+
+    /// Creates an empty temporary library directory holding a placeholder book file.
+    /// Returns the library directory and the path of the book file.
+    fn test_library(name: &str, book_file_name: &str) -> (PathBuf, PathBuf) {
+        let library_dir = std::env::temp_dir().join(format!("cwh-test-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&library_dir).unwrap();
+        let book_file = library_dir.join(book_file_name);
+        fs::write(&book_file, b"not really an epub").unwrap();
+        (library_dir, book_file)
+    }
+
+    #[test]
+    fn test_list_tolerates_nonstandard_timestamps() {
+        let conn = test_calibre_db();
+        conn.execute_batch(
+            "CREATE TABLE tags (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+             CREATE TABLE books_tags_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, tag INTEGER NOT NULL);
+             INSERT INTO books (id, title, sort, author_sort, uuid, timestamp, pubdate, last_modified) VALUES
+                (1, 'Calibre', 'Calibre', 'Doe, John', 'u1', '2024-01-02 03:04:05.123456+00:00', '2001-05-03 00:00:00+00:00', '2024-01-02 03:04:05.123456+00:00'),
+                (2, 'Imported', 'Imported', 'Doe, John', 'u2', '2024-01-02T03:04:05Z', 'May 2001', 1704164645);",
+        ).unwrap();
+
+        let options = ListOptions { verbose: true, ..Default::default() };
+        list_books(&conn, None, Path::new("."), &options).unwrap();
+    }
+
+    #[test]
+    fn test_display_timestamp_normalizes_or_shows_raw() {
+        let conn = test_calibre_db();
+        conn.execute_batch(
+            "INSERT INTO books (id, title, timestamp, pubdate, last_modified) VALUES
+                (2, 'Imported', '2024-01-02T03:04:05Z', 'May 2001', 1704164645);",
+        ).unwrap();
+
+        let mut stmt = conn.prepare("SELECT timestamp, pubdate, last_modified FROM books WHERE id = 2").unwrap();
+        let mut rows = stmt.query([]).unwrap();
+        let row = rows.next().unwrap().unwrap();
+        assert_eq!(display_timestamp(row, "timestamp", "%Y-%m-%d %H:%M:%S%.f UTC").unwrap(), "2024-01-02 03:04:05 UTC");
+        assert_eq!(display_timestamp(row, "pubdate", "%Y-%m-%d").unwrap(), "May 2001 (unrecognized format)");
+        assert_eq!(display_timestamp(row, "last_modified", "%Y-%m-%d").unwrap(), "1704164645 (unrecognized format)");
+    }
+
+    #[test]
+    fn test_refresh_book_author_sort_uses_link_order() {
+        let conn = test_calibre_db();
+        conn.execute_batch(
+            "INSERT INTO books (id, title, author_sort) VALUES (1, 'Solo', 'stale'), (2, 'Together', 'stale');
+             INSERT INTO authors (id, name, sort) VALUES (1, 'John Doe', 'Doe, John'), (2, 'Jane Roe', 'Roe, Jane');
+             INSERT INTO books_authors_link (book, author) VALUES (1, 1), (2, 2), (2, 1);",
+        ).unwrap();
+
+        assert_eq!(refresh_book_author_sort(&conn, 1).unwrap(), "Doe, John");
+        assert_eq!(refresh_book_author_sort(&conn, 2).unwrap(), "Roe, Jane & Doe, John");
+        let stored: String = conn.query_row("SELECT author_sort FROM books WHERE id = 2", [], |row| row.get(0)).unwrap();
+        assert_eq!(stored, "Roe, Jane & Doe, John");
+    }
+
+    #[test]
+    fn test_readding_with_changed_description_updates_comments() {
+        let mut conn = test_calibre_db();
+        let (library_dir, book_file) = test_library("comments", "book.epub");
+
+        let mut metadata = BookMetadata {
+            title: "A Title".to_string(),
+            author: "Jane Doe".to_string(),
+            path: book_file.clone(),
+            description: Some("<p>Old description</p>".to_string()),
+            ..Default::default()
+        };
+        let write_options = BookWriteOptions { comments_strategy: CommentsStrategy::Overwrite, ..test_write_options() };
+        let comment_text = |conn: &Connection| -> String {
+            conn.query_row("SELECT text FROM comments", [], |row| row.get(0)).unwrap()
+        };
+
+        let created = add_book_to_db(&mut conn, &metadata, &library_dir, &book_file, &write_options).unwrap();
+        assert!(matches!(created, UpsertResult::Created { .. }));
+        assert_eq!(comment_text(&conn), "<p>Old description</p>");
+
+        metadata.description = Some("<p>New description</p>".to_string());
+        let updated = add_book_to_db(&mut conn, &metadata, &library_dir, &book_file, &write_options).unwrap();
+        assert!(matches!(updated, UpsertResult::Updated { .. }));
+        assert_eq!(comment_text(&conn), "<p>New description</p>");
+
+        metadata.description = Some("<p>Imported again</p>".to_string());
+        let preserving = BookWriteOptions { comments_strategy: CommentsStrategy::Preserve, ..write_options };
+        add_book_to_db(&mut conn, &metadata, &library_dir, &book_file, &preserving).unwrap();
+        assert_eq!(comment_text(&conn), "<p>New description</p>");
+
+        let appending = BookWriteOptions { comments_strategy: CommentsStrategy::Append, ..write_options };
+        add_book_to_db(&mut conn, &metadata, &library_dir, &book_file, &appending).unwrap();
+        add_book_to_db(&mut conn, &metadata, &library_dir, &book_file, &appending).unwrap();
+        assert_eq!(comment_text(&conn), "<p>New description</p>\n<p>Imported again</p>");
+
+        metadata.description = Some("<p>Short</p>".to_string());
+        let prefer_longer = BookWriteOptions { comments_strategy: CommentsStrategy::PreferLonger, ..write_options };
+        add_book_to_db(&mut conn, &metadata, &library_dir, &book_file, &prefer_longer).unwrap();
+        assert_eq!(comment_text(&conn), "<p>New description</p>\n<p>Imported again</p>");
+
+        fs::remove_dir_all(&library_dir).ok();
+    }
+
+    #[test]
+    fn test_series_sort_moves_leading_article() {
+        let mut conn = test_calibre_db();
+        let (library_dir, book_file) = test_library("series-sort", "book.epub");
+
+        let metadata = BookMetadata {
+            title: "Leviathan Wakes".to_string(),
+            author: "James S. A. Corey".to_string(),
+            path: book_file.clone(),
+            series: Some("The Expanse".to_string()),
+            ..Default::default()
+        };
+        let write_options = test_write_options();
+        let series_sort = |conn: &Connection| -> String {
+            conn.query_row("SELECT sort FROM series", [], |row| row.get(0)).unwrap()
+        };
+
+        add_book_to_db(&mut conn, &metadata, &library_dir, &book_file, &write_options).unwrap();
+        assert_eq!(series_sort(&conn), "Expanse, The");
+
+        conn.execute("UPDATE series SET sort = name", []).unwrap();
+        crate::cleanup::normalize_library(&mut conn, &library_dir, false).unwrap();
+        assert_eq!(series_sort(&conn), "Expanse, The");
+
+        fs::remove_dir_all(&library_dir).ok();
+    }
+
+    #[test]
+    fn test_uppercase_extension_is_added() {
+        let mut conn = test_calibre_db();
+        let (library_dir, book_file) = test_library("uppercase", "BOOK.EPUB");
+
+        let metadata = BookMetadata {
+            title: "Loud Title".to_string(),
+            author: "Jane Doe".to_string(),
+            path: book_file.clone(),
+            ..Default::default()
+        };
+        let write_options = test_write_options();
+        let cover_options = crate::models::CoverOptions { quality: 82, max_pixels: 40_000_000, max_width: None, max_height: None, ignore_errors: true, placeholder_background: None };
+
+        let created = add_book_to_db(&mut conn, &metadata, &library_dir, &book_file, &write_options).unwrap();
+        let format: String = conn.query_row("SELECT format FROM data", [], |row| row.get(0)).unwrap();
+        assert_eq!(format, "EPUB");
+        let file_options = crate::models::BookFileOptions { cover: &cover_options, preserve_timestamps: false, temp_dir: None };
+        crate::epub::update_book_files(&library_dir, &book_file, created.book_path(), false, &metadata, &file_options).unwrap();
+        assert!(library_dir.join(created.book_path()).join("Loud Title - Jane Doe.epub").exists());
+
+        fs::remove_dir_all(&library_dir).ok();
+    }
+}
@@ -1,11 +1,78 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use rusqlite::{Connection, params};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use crate::utils::{now_utc_micro, get_valid_filename};
+use crate::calibre::{linked_author_sort, refresh_book_author_sort};
+use crate::models::AuthorSortMethod;
+use crate::utils::{now_utc_micro, get_valid_filename, detect_book_format, format_series_index, series_index_candidates, set_metadata_dirty, title_sort};
+use crate::output::say;
 
-/// Cleans up orphaned data in both Calibre and Calibre-Web databases
-pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Option<&mut Connection>, calibre_library_path: &PathBuf) -> Result<()> {
-    println!("🧹 Starting database cleanup...");
+/// Metadata entity tables pruned when no book links to them: (entity name, DELETE statement)
+const ORPHANED_METADATA_QUERIES: &[(&str, &str)] = &[
+    ("author", "DELETE FROM authors WHERE NOT EXISTS (SELECT 1 FROM books_authors_link WHERE author = authors.id)"),
+    ("publisher", "DELETE FROM publishers WHERE NOT EXISTS (SELECT 1 FROM books_publishers_link WHERE publisher = publishers.id)"),
+    ("series", "DELETE FROM series WHERE NOT EXISTS (SELECT 1 FROM books_series_link WHERE series = series.id)"),
+    ("tag", "DELETE FROM tags WHERE NOT EXISTS (SELECT 1 FROM books_tags_link WHERE tag = tags.id)"),
+];
+
+/// Deletes authors, publishers, series and tags that no book links to.
+/// Returns the number of rows removed per entity type.
+fn prune_orphaned_metadata(tx: &rusqlite::Transaction) -> Result<Vec<(&'static str, usize)>> {
+    ORPHANED_METADATA_QUERIES.iter()
+        .map(|(entity, sql)| Ok((*entity, tx.execute(sql, [])?)))
+        .collect()
+}
+
+/// Deletes identifiers whose book no longer exists, then collapses duplicate (book, type)
+/// rows down to the most recently written one. Types compare case-insensitively, as in Calibre.
+/// Returns (orphaned rows removed, duplicate rows removed).
+fn prune_identifier_rows(tx: &rusqlite::Transaction) -> Result<(usize, usize)> {
+    let orphaned = tx.execute(
+        "DELETE FROM identifiers WHERE NOT EXISTS (SELECT 1 FROM books WHERE books.id = identifiers.book)",
+        [],
+    )?;
+    let duplicates = tx.execute(
+        "DELETE FROM identifiers WHERE id NOT IN (SELECT MAX(id) FROM identifiers GROUP BY book, LOWER(type))",
+        [],
+    )?;
+    Ok((orphaned, duplicates))
+}
+
+/// Removes orphaned and duplicate identifier rows from metadata.db
+pub(crate) fn prune_identifiers(metadata_conn: &mut Connection) -> Result<()> {
+    say!("🧹 Pruning orphaned and duplicate identifiers...");
+
+    let tx = metadata_conn.transaction()?;
+    let (orphaned, duplicates) = prune_identifier_rows(&tx)?;
+    tx.commit()?;
+
+    say!(" -> Removed {} identifiers of missing books", orphaned);
+    say!(" -> Removed {} duplicate identifiers", duplicates);
+    say!("\n✅ Pruning complete: {} identifiers removed.", orphaned + duplicates);
+    Ok(())
+}
+
+/// Removes only the orphaned metadata entities from metadata.db, without the
+/// library walk and integrity checks of `cleanup_databases`
+pub(crate) fn prune_metadata(metadata_conn: &mut Connection) -> Result<()> {
+    say!("🧹 Pruning metadata entries with no books...");
+
+    let tx = metadata_conn.transaction()?;
+    let results = prune_orphaned_metadata(&tx)?;
+    tx.commit()?;
+
+    for (entity, deleted) in &results {
+        say!(" -> Removed {} orphaned {} entries", deleted, entity);
+    }
+    let total: usize = results.iter().map(|(_, deleted)| deleted).sum();
+    say!("\n✅ Pruning complete: {} entries removed.", total);
+    Ok(())
+}
+
+/// Cleans up orphaned data in both Calibre and Calibre-Web databases. With `trim_data_rows`,
+/// format rows whose file is gone from an existing book directory are removed as well.
+pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Option<&mut Connection>, calibre_library_path: &PathBuf, trim_data_rows: bool) -> Result<()> {
+    say!("🧹 Starting database cleanup...");
     
     // Get list of actual files in the Calibre library
     let mut existing_files = std::collections::HashSet::new();
@@ -53,7 +120,7 @@ pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Opti
 
     // Clean up orphaned books and their related data
     if !orphaned_books.is_empty() {
-        println!("\n📚 Cleaning up orphaned books...");
+        say!("\n📚 Cleaning up orphaned books...");
         for book_id in &orphaned_books {
             // Delete from related tables
             for table in &[
@@ -75,47 +142,29 @@ pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Opti
             
             // Delete the book itself
             tx.execute("DELETE FROM books WHERE id = ?1", params![book_id])?;
-            println!(" -> Removed orphaned book (ID: {})", book_id);
+            say!(" -> Removed orphaned book (ID: {})", book_id);
         }
     }
 
     // Drop the statement before committing
     drop(stmt);
 
-    // Clean up authors with no books
-    let deleted = tx.execute(
-        "DELETE FROM authors WHERE NOT EXISTS (SELECT 1 FROM books_authors_link WHERE author = authors.id)",
-        [],
-    )?;
-    if deleted > 0 {
-        println!(" -> Removed {} orphaned author entries", deleted);
+    if trim_data_rows {
+        trim_orphan_data_rows(&tx, calibre_library_path)?;
     }
 
-    // Clean up publishers with no books
-    let deleted = tx.execute(
-        "DELETE FROM publishers WHERE NOT EXISTS (SELECT 1 FROM books_publishers_link WHERE publisher = publishers.id)",
-        [],
-    )?;
-    if deleted > 0 {
-        println!(" -> Removed {} orphaned publisher entries", deleted);
+    for (entity, deleted) in prune_orphaned_metadata(&tx)? {
+        if deleted > 0 {
+            say!(" -> Removed {} orphaned {} entries", deleted, entity);
+        }
     }
 
-    // Clean up series with no books
-    let deleted = tx.execute(
-        "DELETE FROM series WHERE NOT EXISTS (SELECT 1 FROM books_series_link WHERE series = series.id)",
-        [],
-    )?;
-    if deleted > 0 {
-        println!(" -> Removed {} orphaned series entries", deleted);
+    let (orphaned_identifiers, duplicate_identifiers) = prune_identifier_rows(&tx)?;
+    if orphaned_identifiers > 0 {
+        say!(" -> Removed {} identifiers of missing books", orphaned_identifiers);
     }
-
-    // Clean up tags with no books
-    let deleted = tx.execute(
-        "DELETE FROM tags WHERE NOT EXISTS (SELECT 1 FROM books_tags_link WHERE tag = tags.id)",
-        [],
-    )?;
-    if deleted > 0 {
-        println!(" -> Removed {} orphaned tag entries", deleted);
+    if duplicate_identifiers > 0 {
+        say!(" -> Removed {} duplicate identifiers", duplicate_identifiers);
     }
 
     // --- Integrity checks ---
@@ -130,7 +179,7 @@ pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Opti
 
         // Clean up Calibre-Web database if provided
     if let Some(conn) = appdb_conn {
-        println!("
+        say!("
 🌐 Cleaning up Calibre-Web database...");
         let tx = conn.transaction()?;
 
@@ -141,7 +190,7 @@ pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Opti
             [],
         )?;
         if fixed > 0 {
-            println!(" -> Fixed {} shelf records with missing created timestamp", fixed);
+            say!(" -> Fixed {} shelf records with missing created timestamp", fixed);
         }
 
         // Fix NULL last_modified values in shelf records
@@ -150,7 +199,7 @@ pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Opti
             [],
         )?;
         if fixed > 0 {
-            println!(" -> Fixed {} shelf records with missing last_modified timestamp", fixed);
+            say!(" -> Fixed {} shelf records with missing last_modified timestamp", fixed);
         }
 
         // Set both timestamps to current time if both are NULL
@@ -160,7 +209,7 @@ pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Opti
             params![now_micro, now_micro],
         )?;
         if fixed > 0 {
-            println!(" -> Fixed {} shelf records with no timestamps", fixed);
+            say!(" -> Fixed {} shelf records with no timestamps", fixed);
         }
 
         // Fix NULL timestamps in book_shelf_link
@@ -169,7 +218,7 @@ pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Opti
             params![now_micro],
         )?;
         if fixed > 0 {
-            println!(" -> Fixed {} book shelf links with missing timestamp", fixed);
+            say!(" -> Fixed {} book shelf links with missing timestamp", fixed);
         }
 
         // Get valid book IDs from Calibre database
@@ -206,7 +255,7 @@ pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Opti
             [],
         )?;
         if deleted > 0 {
-            println!(" -> Removed {} orphaned download entries", deleted);
+            say!(" -> Removed {} orphaned download entries", deleted);
         }
 
         // Clean up archived books
@@ -215,7 +264,7 @@ pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Opti
             [],
         )?;
         if deleted > 0 {
-            println!(" -> Removed {} orphaned archived book entries", deleted);
+            say!(" -> Removed {} orphaned archived book entries", deleted);
         }
 
         // Clean up Kobo bookmarks before reading state
@@ -226,7 +275,7 @@ pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Opti
             [],
         )?;
         if deleted > 0 {
-            println!(" -> Removed {} orphaned Kobo bookmark entries", deleted);
+            say!(" -> Removed {} orphaned Kobo bookmark entries", deleted);
         }
 
         // Clean up Kobo statistics before reading state
@@ -237,7 +286,7 @@ pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Opti
             [],
         )?;
         if deleted > 0 {
-            println!(" -> Removed {} orphaned Kobo statistics entries", deleted);
+            say!(" -> Removed {} orphaned Kobo statistics entries", deleted);
         }
 
         // Clean up Kobo reading state after its dependents
@@ -246,7 +295,7 @@ pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Opti
             [],
         )?;
         if deleted > 0 {
-            println!(" -> Removed {} orphaned Kobo reading state entries", deleted);
+            say!(" -> Removed {} orphaned Kobo reading state entries", deleted);
         }
 
         // Clean up Kobo synced books
@@ -255,7 +304,7 @@ pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Opti
             [],
         )?;
         if deleted > 0 {
-            println!(" -> Removed {} orphaned Kobo sync entries", deleted);
+            say!(" -> Removed {} orphaned Kobo sync entries", deleted);
         }
 
         // Finally book shelf links and empty shelves
@@ -264,7 +313,7 @@ pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Opti
             [],
         )?;
         if deleted > 0 {
-            println!(" -> Removed {} orphaned shelf links", deleted);
+            say!(" -> Removed {} orphaned shelf links", deleted);
         }
 
         // Clean up empty shelves last
@@ -273,20 +322,20 @@ pub(crate) fn cleanup_databases(metadata_conn: &mut Connection, appdb_conn: Opti
             [],
         )?;
         if deleted > 0 {
-            println!(" -> Removed {} empty shelves", deleted);
+            say!(" -> Removed {} empty shelves", deleted);
         }
 
         // Commit app DB changes
         tx.commit()?;
     }
 
-    println!("\n✨ Database cleanup complete!");
+    say!("\n✨ Database cleanup complete!");
     Ok(())
 }
 
 /// Reports duplicate books (same title + author_sort) with different IDs.
 fn check_duplicate_books(tx: &rusqlite::Transaction) -> Result<()> {
-    println!("\n🔍 Checking for duplicate books...");
+    say!("\n🔍 Checking for duplicate books...");
 
     let mut stmt = tx.prepare(
         "SELECT title, author_sort, GROUP_CONCAT(id) as ids, COUNT(*) as cnt
@@ -306,21 +355,60 @@ fn check_duplicate_books(tx: &rusqlite::Transaction) -> Result<()> {
     })?.collect::<Result<Vec<_>, _>>()?;
 
     if dupes.is_empty() {
-        println!(" -> No duplicate books found.");
+        say!(" -> No duplicate books found.");
     } else {
-        println!(" ⚠️  Found {} sets of duplicate books:", dupes.len());
+        say!(" ⚠️  Found {} sets of duplicate books:", dupes.len());
         for (title, author_sort, ids, count) in &dupes {
-            println!("    '{}' by {} — {} copies (IDs: {})", title, author_sort, count, ids);
+            say!("    '{}' by {} — {} copies (IDs: {})", title, author_sort, count, ids);
         }
-        println!("    These are not automatically removed; review and delete manually with the 'delete' command.");
+        say!("    These are not automatically removed; review and delete manually with the 'delete' command.");
     }
 
     Ok(())
 }
 
+/// Removes `data` rows of books whose directory exists but holds no file of the row's format,
+/// looked up as `{name}.{format}` like `check_formats` does. Rows whose format is present under
+/// another name are left for `check_data_name_mismatches`.
+fn trim_orphan_data_rows(tx: &rusqlite::Transaction, library_dir: &Path) -> Result<()> {
+    say!("\n🔍 Checking format data rows against the files on disk...");
+
+    let rows: Vec<(i64, i64, String, String, String, String)> = tx.prepare(
+        "SELECT d.id, b.id, b.title, b.path, d.format, d.name
+         FROM data d
+         JOIN books b ON b.id = d.book
+         ORDER BY b.title, d.format"
+    )?
+    .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?)))?
+    .collect::<Result<Vec<_>, _>>()?;
+
+    let mut dir_files: HashMap<String, Vec<String>> = HashMap::new();
+    let mut removed = 0;
+    for (data_id, book_id, title, book_path, format, name) in &rows {
+        let book_dir = library_dir.join(book_path);
+        if !book_dir.is_dir() {
+            continue;
+        }
+        if !dir_files.contains_key(book_path) {
+            dir_files.insert(book_path.clone(), file_names_in_dir(&book_dir)?);
+        }
+        if data_file_status(format, name, &dir_files[book_path]) == DataFileStatus::Missing {
+            report_missing_data_row(tx, *data_id, (*book_id, title, book_path), format, true)?;
+            removed += 1;
+        }
+    }
+
+    if removed == 0 {
+        say!(" -> Every format data row has its file.");
+    } else {
+        say!(" -> Removed {} data row(s) whose file is missing.", removed);
+    }
+    Ok(())
+}
+
 /// Reports books that have no entry in the `data` table (no format/file record).
 fn check_missing_data_entries(tx: &rusqlite::Transaction) -> Result<()> {
-    println!("\n🔍 Checking for books with missing format data...");
+    say!("\n🔍 Checking for books with missing format data...");
 
     let mut stmt = tx.prepare(
         "SELECT b.id, b.title, b.author_sort, b.path
@@ -340,14 +428,14 @@ fn check_missing_data_entries(tx: &rusqlite::Transaction) -> Result<()> {
     })?.collect::<Result<Vec<_>, _>>()?;
 
     if missing.is_empty() {
-        println!(" -> All books have format data entries.");
+        say!(" -> All books have format data entries.");
     } else {
-        println!(" ⚠️  Found {} book(s) with no format data:", missing.len());
+        say!(" ⚠️  Found {} book(s) with no format data:", missing.len());
         for (id, title, author, path) in &missing {
-            println!("    ID {} — '{}' by {} (path: {})", id, title, author, path);
+            say!("    ID {} — '{}' by {} (path: {})", id, title, author, path);
         }
-        println!("    These books exist in the database but have no associated file format.");
-        println!("    Consider deleting them with the 'delete' command or re-adding the EPUB.");
+        say!("    These books exist in the database but have no associated file format.");
+        say!("    Consider deleting them with the 'delete' command or re-adding the EPUB.");
     }
 
     Ok(())
@@ -355,7 +443,7 @@ fn check_missing_data_entries(tx: &rusqlite::Transaction) -> Result<()> {
 
 /// Reports mismatches between `data.name` and the actual filename on disk.
 fn check_data_name_mismatches(tx: &rusqlite::Transaction, library_dir: &Path) -> Result<()> {
-    println!("\n🔍 Checking for data.name vs filename mismatches...");
+    say!("\n🔍 Checking for data.name vs filename mismatches...");
 
     let mut stmt = tx.prepare(
         "SELECT d.id, d.book, d.name, d.format, b.path, b.title, b.author_sort
@@ -411,12 +499,12 @@ fn check_data_name_mismatches(tx: &rusqlite::Transaction, library_dir: &Path) ->
 
             if actual_files.is_empty() {
                 missing_file_count += 1;
-                println!("    ⚠️  ID {} — '{}' by {}: no book file found in {}", book_id, title, author, book_path);
+                say!("    ⚠️  ID {} — '{}' by {}: no book file found in {}", book_id, title, author, book_path);
             } else {
                 mismatch_count += 1;
-                println!("    ⚠️  ID {} — '{}' by {} (data.id {}):", book_id, title, author, data_id);
-                println!("       Expected: {}", expected_filename);
-                println!("       Found:    {}", actual_files.join(", "));
+                say!("    ⚠️  ID {} — '{}' by {} (data.id {}):", book_id, title, author, data_id);
+                say!("       Expected: {}", expected_filename);
+                say!("       Found:    {}", actual_files.join(", "));
 
                 // Auto-fix: update data.name to match the actual file on disk
                 if actual_files.len() == 1 {
@@ -428,7 +516,7 @@ fn check_data_name_mismatches(tx: &rusqlite::Transaction, library_dir: &Path) ->
                         .or_else(|| actual.strip_suffix(".kepub"))
                         .unwrap_or(actual);
                     tx.execute("UPDATE data SET name = ?1 WHERE id = ?2", params![stem, data_id])?;
-                    println!("       ✅ Fixed: updated data.name to '{}'", stem);
+                    say!("       ✅ Fixed: updated data.name to '{}'", stem);
                 }
             }
         } else {
@@ -447,13 +535,13 @@ fn check_data_name_mismatches(tx: &rusqlite::Transaction, library_dir: &Path) ->
     }
 
     if mismatch_count == 0 && missing_file_count == 0 {
-        println!(" -> All data.name entries match their files on disk.");
+        say!(" -> All data.name entries match their files on disk.");
     } else {
         if mismatch_count > 0 {
-            println!(" -> Fixed {} filename mismatch(es).", mismatch_count);
+            say!(" -> Fixed {} filename mismatch(es).", mismatch_count);
         }
         if missing_file_count > 0 {
-            println!(" -> {} book(s) have a data record but no file on disk.", missing_file_count);
+            say!(" -> {} book(s) have a data record but no file on disk.", missing_file_count);
         }
     }
 
@@ -462,7 +550,7 @@ fn check_data_name_mismatches(tx: &rusqlite::Transaction, library_dir: &Path) ->
 
 /// Reports books where has_cover=1 but cover.jpg is missing, and fixes the flag.
 fn check_missing_covers(tx: &rusqlite::Transaction, library_dir: &Path) -> Result<()> {
-    println!("\n🔍 Checking for missing cover images...");
+    say!("\n🔍 Checking for missing cover images...");
 
     let mut stmt = tx.prepare(
         "SELECT id, title, author_sort, path FROM books WHERE has_cover = 1 ORDER BY title"
@@ -482,15 +570,15 @@ fn check_missing_covers(tx: &rusqlite::Transaction, library_dir: &Path) -> Resul
         let cover_path = library_dir.join(book_path).join("cover.jpg");
         if !cover_path.exists() {
             missing_count += 1;
-            println!("    ⚠️  ID {} — '{}' by {}: has_cover=1 but cover.jpg missing", book_id, title, author);
+            say!("    ⚠️  ID {} — '{}' by {}: has_cover=1 but cover.jpg missing", book_id, title, author);
             tx.execute("UPDATE books SET has_cover = 0 WHERE id = ?1", params![book_id])?;
         }
     }
 
     if missing_count == 0 {
-        println!(" -> All books with has_cover=1 have their cover.jpg file.");
+        say!(" -> All books with has_cover=1 have their cover.jpg file.");
     } else {
-        println!(" -> Fixed {} book(s): set has_cover=0 where cover.jpg was missing.", missing_count);
+        say!(" -> Fixed {} book(s): set has_cover=0 where cover.jpg was missing.", missing_count);
     }
 
     // Also check the reverse: has_cover=0 but cover.jpg exists
@@ -512,14 +600,687 @@ fn check_missing_covers(tx: &rusqlite::Transaction, library_dir: &Path) -> Resul
         let cover_path = library_dir.join(book_path).join("cover.jpg");
         if cover_path.exists() {
             found_count += 1;
-            println!("    ✅ ID {} — '{}' by {}: has_cover=0 but cover.jpg exists, fixing", book_id, title, author);
+            say!("    ✅ ID {} — '{}' by {}: has_cover=0 but cover.jpg exists, fixing", book_id, title, author);
             tx.execute("UPDATE books SET has_cover = 1 WHERE id = ?1", params![book_id])?;
         }
     }
 
     if found_count > 0 {
-        println!(" -> Fixed {} book(s): set has_cover=1 where cover.jpg was found.", found_count);
+        say!(" -> Fixed {} book(s): set has_cover=1 where cover.jpg was found.", found_count);
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// A book file found in a book directory
+struct BookFile {
+    format: &'static str,
+    /// File name without the format extension, as stored in `data.name`
+    name: String,
+    /// The file's name on disk, whose extension may differ in case from `expected_file_name`
+    file_name: String,
+    /// `{name}.{format in lowercase}`, the file name Calibre-Web opens for a `data` row
+    expected_file_name: String,
+    size: u64,
+}
+
+/// Lists the book files in a book directory, ignoring covers, OPF files and anything else
+fn book_files_in_dir(book_dir: &Path) -> Result<Vec<BookFile>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(book_dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let Ok((format, extension)) = detect_book_format(&path) else {
+            continue;
+        };
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        // detect_book_format ignores case, so "Book.EPUB" is stripped to "Book" as well
+        let (name, suffix) = match strip_suffix_ignore_case(&file_name, ".kepub.epub") {
+            Some(name) => (name, ".kepub.epub"),
+            None => (strip_suffix_ignore_case(&file_name, extension).unwrap_or(&file_name), extension),
+        };
+        let (name, expected_file_name) = (name.to_string(), format!("{}{}", name, suffix));
+        files.push(BookFile { format, name, file_name, expected_file_name, size: path.metadata()?.len() });
+    }
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(files)
+}
+
+/// Lists the names of the files directly inside `dir`
+fn file_names_in_dir(dir: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.path().is_file() {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    Ok(names)
+}
+
+/// Where a `data` row's file stands among the files of its book directory
+#[derive(Debug, PartialEq)]
+enum DataFileStatus {
+    /// `{name}.{format}` is there
+    Present,
+    /// A file with the format's extension is there, but its extension's letter case differs from
+    /// `{format}` in lowercase, or its whole name matches `{name}.{format}` only when case is
+    /// ignored. Holds the file's name and the name Calibre-Web opens.
+    WrongCase { file: String, expected: String },
+    /// No file by that name, but one with the format's extension is; holds its name without it
+    Renamed(String),
+    /// No file with the format's extension at all
+    Missing,
+}
+
+/// Finds a `data` row's file the way Calibre-Web opens it, `{name}.{format in lowercase}`, so
+/// that every format is recognized, not just the ones this tool imports. Names are compared
+/// case-sensitively, as they are on the file systems Calibre-Web usually runs on; a file that
+/// only matches when case is ignored is reported as `WrongCase`.
+fn data_file_status(format: &str, name: &str, files: &[String]) -> DataFileStatus {
+    let extension = format.to_lowercase();
+    // Kobo tools also write KEPUBs as .kepub.epub, which must not count as a plain EPUB
+    let suffixes = if extension == "kepub" { vec![".kepub".to_string(), ".kepub.epub".to_string()] } else { vec![format!(".{}", extension)] };
+    let is_plain_epub = |file: &str| !(extension == "epub" && file.to_lowercase().ends_with(".kepub.epub"));
+
+    if suffixes.iter().any(|suffix| files.contains(&format!("{}{}", name, suffix))) {
+        return DataFileStatus::Present;
+    }
+    for suffix in &suffixes {
+        let expected = format!("{}{}", name, suffix);
+        if let Some(file) = files.iter().find(|file| file.eq_ignore_ascii_case(&expected) && is_plain_epub(file)) {
+            return DataFileStatus::WrongCase { file: file.clone(), expected };
+        }
+    }
+    for suffix in &suffixes {
+        if let Some(file) = files.iter().find(|file| file.ends_with(suffix.as_str()) && is_plain_epub(file)) {
+            return DataFileStatus::Renamed(file[..file.len() - suffix.len()].to_string());
+        }
+    }
+    for suffix in &suffixes {
+        if let Some(file) = files.iter().find(|file| strip_suffix_ignore_case(file, suffix).is_some() && is_plain_epub(file)) {
+            return DataFileStatus::WrongCase { file: file.clone(), expected: format!("{}{}", name, suffix) };
+        }
+    }
+    DataFileStatus::Missing
+}
+
+/// `str::strip_suffix` with the suffix compared case-insensitively (ASCII only)
+fn strip_suffix_ignore_case<'a>(text: &'a str, suffix: &str) -> Option<&'a str> {
+    let split = text.len().checked_sub(suffix.len())?;
+    (text.is_char_boundary(split) && text[split..].eq_ignore_ascii_case(suffix)).then(|| &text[..split])
+}
+
+/// Reports a `data` row whose file is gone from the book's directory, and deletes the row if `remove`
+fn report_missing_data_row(tx: &rusqlite::Transaction, data_id: i64, book: (i64, &str, &str), format: &str, remove: bool) -> Result<()> {
+    let (book_id, title, book_path) = book;
+    say!("    ⚠️  ID {} — '{}': {} format recorded but no {} file in {}", book_id, title, format, format, book_path);
+    if remove {
+        tx.execute("DELETE FROM data WHERE id = ?1", params![data_id])?;
+        say!("       ✅ Fixed: removed the {} data row", format);
+    }
+    Ok(())
+}
+
+/// Checks that every `data` row points at a file with the right name and extension in the
+/// book's directory, and that every book file there has a `data` row. With `fix`, the `data`
+/// rows are reconciled to the files actually present, and files with a wrongly cased extension
+/// or name are renamed to what Calibre-Web opens; otherwise nothing is changed.
+pub(crate) fn check_formats(metadata_conn: &mut Connection, library_dir: &Path, fix: bool) -> Result<()> {
+    say!("🔍 Checking book formats against the files on disk...");
+
+    let tx = metadata_conn.transaction()?;
+    let books: Vec<(i64, String, String)> = tx.prepare("SELECT id, title, path FROM books ORDER BY title")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut problem_count = 0;
+    let mut missing_dirs = 0;
+    for (book_id, title, book_path) in &books {
+        let book_dir = library_dir.join(book_path);
+        if !book_dir.is_dir() {
+            missing_dirs += 1;
+            continue;
+        }
+        let files = book_files_in_dir(&book_dir)?;
+        let file_names = file_names_in_dir(&book_dir)?;
+        let rows: Vec<(i64, String, String)> = tx.prepare("SELECT id, format, name FROM data WHERE book = ?1")?
+            .query_map(params![book_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (data_id, format, name) in &rows {
+            match data_file_status(format, name, &file_names) {
+                DataFileStatus::Present => {}
+                DataFileStatus::WrongCase { file, expected } => {
+                    problem_count += 1;
+                    say!("    ⚠️  ID {} — '{}': {} file is '{}' but Calibre-Web opens '{}'", book_id, title, format, file, expected);
+                    if fix {
+                        std::fs::rename(book_dir.join(&file), book_dir.join(&expected))
+                            .with_context(|| format!("Failed to rename {:?} to {:?}", book_dir.join(&file), expected))?;
+                        say!("       ✅ Fixed: renamed the file");
+                    }
+                }
+                DataFileStatus::Renamed(file_name) => {
+                    problem_count += 1;
+                    say!("    ⚠️  ID {} — '{}': {} data.name is '{}' but the file is '{}'", book_id, title, format, name, file_name);
+                    if fix {
+                        tx.execute("UPDATE data SET name = ?1 WHERE id = ?2", params![file_name, data_id])?;
+                        say!("       ✅ Fixed: updated data.name");
+                    }
+                }
+                DataFileStatus::Missing => {
+                    problem_count += 1;
+                    report_missing_data_row(&tx, *data_id, (*book_id, title, book_path), format, fix)?;
+                }
+            }
+        }
+
+        for file in &files {
+            if rows.iter().any(|(_, format, _)| file.format.eq_ignore_ascii_case(format)) {
+                continue;
+            }
+            problem_count += 1;
+            say!("    ⚠️  ID {} — '{}': file '{}' ({}) has no data row", book_id, title, file.name, file.format);
+            if fix {
+                if file.file_name != file.expected_file_name {
+                    std::fs::rename(book_dir.join(&file.file_name), book_dir.join(&file.expected_file_name))
+                        .with_context(|| format!("Failed to rename {:?} to {:?}", book_dir.join(&file.file_name), file.expected_file_name))?;
+                    say!("       ✅ Fixed: renamed the file to '{}'", file.expected_file_name);
+                }
+                tx.execute(
+                    "INSERT OR IGNORE INTO data (book, format, uncompressed_size, name) VALUES (?1, ?2, ?3, ?4)",
+                    params![book_id, file.format, file.size as i64, file.name],
+                )?;
+                say!("       ✅ Fixed: added a {} data row", file.format);
+            }
+        }
+    }
+
+    tx.commit()?;
+
+    if missing_dirs > 0 {
+        say!(" -> Skipped {} book(s) whose directory is missing; 'clean-db' removes those.", missing_dirs);
+    }
+    if problem_count == 0 {
+        say!(" -> All {} book(s) have data rows matching their files.", books.len());
+    } else if fix {
+        say!(" -> Fixed {} format problem(s).", problem_count);
+    } else {
+        say!(" -> Found {} format problem(s). Run again with --fix to reconcile the data rows with the files.", problem_count);
+    }
+    Ok(())
+}
+
+/// Finds books whose `books.path` directory is missing while the parent directory holds a sibling
+/// with the same ` ({id})` suffix, which is what renaming a book in Calibre leaves behind. With
+/// `fix`, `books.path` is pointed at that sibling; otherwise nothing is changed.
+pub(crate) fn verify_book_paths(metadata_conn: &mut Connection, library_dir: &Path, fix: bool) -> Result<()> {
+    say!("🔍 Checking book directories against books.path...");
+
+    let tx = metadata_conn.transaction()?;
+    let books: Vec<(i64, String, String)> = tx.prepare("SELECT id, title, path FROM books ORDER BY title")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut renamed = 0;
+    let mut unresolved = 0;
+    for (book_id, title, book_path) in &books {
+        if library_dir.join(book_path).is_dir() {
+            continue;
+        }
+        let (parent, _) = book_path.rsplit_once('/').unwrap_or(("", book_path));
+        let suffix = format!(" ({})", book_id);
+        let mut candidates: Vec<String> = std::fs::read_dir(library_dir.join(parent))
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|name| name.ends_with(&suffix))
+            .collect();
+        candidates.sort();
+
+        match candidates.as_slice() {
+            [name] => {
+                renamed += 1;
+                let new_path = if parent.is_empty() { name.clone() } else { format!("{}/{}", parent, name) };
+                say!("    ⚠️  ID {} — '{}': '{}' is missing but '{}' exists", book_id, title, book_path, new_path);
+                if fix {
+                    tx.execute("UPDATE books SET path = ?1 WHERE id = ?2", params![new_path, book_id])?;
+                    say!("       ✅ Fixed: updated books.path");
+                }
+            }
+            [] => {
+                unresolved += 1;
+                say!("    ⚠️  ID {} — '{}': '{}' is missing and no renamed directory was found", book_id, title, book_path);
+            }
+            _ => {
+                unresolved += 1;
+                say!("    ⚠️  ID {} — '{}': '{}' is missing and several directories match: {}", book_id, title, book_path, candidates.join(", "));
+            }
+        }
+    }
+
+    tx.commit()?;
+
+    if renamed == 0 && unresolved == 0 {
+        say!(" -> All {} book(s) have a directory matching books.path.", books.len());
+    } else {
+        if renamed > 0 && fix {
+            say!(" -> Repaired the path of {} renamed book(s).", renamed);
+        } else if renamed > 0 {
+            say!(" -> Found {} renamed book directory(ies). Run again with --fix to update books.path.", renamed);
+        }
+        if unresolved > 0 {
+            say!(" -> {} book(s) have a missing directory that couldn't be matched.", unresolved);
+        }
+    }
+    Ok(())
+}
+
+/// The stored fields of a book that `normalize_library` recomputes
+struct NormalizeRow {
+    id: i64,
+    title: String,
+    sort: Option<String>,
+    author_sort: Option<String>,
+    has_cover: bool,
+    path: String,
+}
+
+/// Recomputes every book's `sort` and `author_sort` and its `has_cover` flag from disk, and every series' `sort`.
+/// Authors with an empty `sort` get one computed first, so book author sorts can be built
+/// from them. With `dry_run`, the changes are counted and then rolled back.
+pub(crate) fn normalize_library(metadata_conn: &mut Connection, library_dir: &Path, dry_run: bool) -> Result<()> {
+    if dry_run {
+        say!("🧪 Normalizing library (dry run, nothing will be saved)...");
+    } else {
+        say!("🔧 Normalizing library...");
+    }
+
+    let tx = metadata_conn.transaction()?;
+
+    let authors: Vec<(i64, String)> = tx.prepare("SELECT id, name FROM authors WHERE sort IS NULL OR TRIM(sort) = ''")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (author_id, name) in &authors {
+        tx.execute("UPDATE authors SET sort = ?1 WHERE id = ?2", params![AuthorSortMethod::CalibreWeb.author_sort(name), author_id])?;
+    }
+
+    let series: Vec<(i64, String, Option<String>)> = tx.prepare("SELECT id, name, sort FROM series")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut series_fixed = 0;
+    for (series_id, name, sort) in &series {
+        let new_sort = title_sort(name);
+        if sort.as_deref() != Some(new_sort.as_str()) {
+            tx.execute("UPDATE series SET sort = ?1 WHERE id = ?2", params![new_sort, series_id])?;
+            series_fixed += 1;
+        }
+    }
+
+    let books: Vec<NormalizeRow> = tx.prepare(
+        "SELECT id, title, sort, author_sort, has_cover, path FROM books ORDER BY id"
+    )?
+    .query_map([], |row| Ok(NormalizeRow {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        sort: row.get(2)?,
+        author_sort: row.get(3)?,
+        has_cover: row.get(4)?,
+        path: row.get(5)?,
+    }))?
+    .collect::<Result<Vec<_>, _>>()?;
+
+    let (mut sort_fixed, mut author_sort_fixed, mut cover_fixed) = (0, 0, 0);
+    for book in &books {
+        let new_sort = title_sort(&book.title);
+        if book.sort.as_deref() != Some(new_sort.as_str()) {
+            tx.execute("UPDATE books SET sort = ?1 WHERE id = ?2", params![new_sort, book.id])?;
+            sort_fixed += 1;
+        }
+
+        let new_author_sort = linked_author_sort(&tx, book.id)?;
+        if !new_author_sort.is_empty() && book.author_sort.as_deref() != Some(new_author_sort.as_str()) {
+            tx.execute("UPDATE books SET author_sort = ?1 WHERE id = ?2", params![new_author_sort, book.id])?;
+            author_sort_fixed += 1;
+        }
+
+        let cover_exists = library_dir.join(&book.path).join("cover.jpg").exists();
+        if cover_exists != book.has_cover {
+            tx.execute("UPDATE books SET has_cover = ?1 WHERE id = ?2", params![cover_exists, book.id])?;
+            cover_fixed += 1;
+        }
+    }
+
+    let verb = if dry_run { "Would fix" } else { "Fixed" };
+    say!(" -> {} {} author(s) with an empty sort", verb, authors.len());
+    say!(" -> {} {} series sort(s)", verb, series_fixed);
+    say!(" -> {} {} book title sort(s)", verb, sort_fixed);
+    say!(" -> {} {} book author sort(s)", verb, author_sort_fixed);
+    say!(" -> {} {} has_cover flag(s)", verb, cover_fixed);
+
+    if dry_run {
+        tx.rollback()?;
+        say!("\n🧪 Dry run complete; all changes rolled back across {} book(s).", books.len());
+    } else {
+        tx.commit()?;
+        say!("\n✅ Normalized {} book(s).", books.len());
+    }
+    Ok(())
+}
+
+/// Gives every book with a missing or duplicated `uuid` a fresh one. Of books sharing a UUID,
+/// the lowest ID keeps it. Calibre-Web's Kobo sync identifies books by UUID, so these books
+/// otherwise fail to sync without any error.
+pub(crate) fn repair_uuids(metadata_conn: &mut Connection, dry_run: bool) -> Result<()> {
+    if dry_run {
+        say!("🧪 Checking book UUIDs (dry run, nothing will be saved)...");
+    } else {
+        say!("🔧 Repairing book UUIDs...");
+    }
+
+    let tx = metadata_conn.transaction()?;
+    let books: Vec<(i64, String, Option<String>)> = tx.prepare("SELECT id, title, uuid FROM books ORDER BY id")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut seen = std::collections::HashSet::new();
+    let mut missing = 0;
+    let mut duplicated = 0;
+    for (book_id, title, uuid) in &books {
+        let reason = match uuid.as_deref().map(str::trim) {
+            None | Some("") => {
+                missing += 1;
+                "has no UUID".to_string()
+            }
+            Some(uuid) if !seen.insert(uuid.to_lowercase()) => {
+                duplicated += 1;
+                format!("shares UUID {} with an earlier book", uuid)
+            }
+            Some(_) => continue,
+        };
+        let new_uuid = uuid::Uuid::new_v4().to_string();
+        say!("    ⚠️  ID {} — '{}' {}; new UUID {}", book_id, title, reason, new_uuid);
+        tx.execute("UPDATE books SET uuid = ?1 WHERE id = ?2", params![new_uuid, book_id])?;
+        set_metadata_dirty(&tx, *book_id)?;
+    }
+
+    let verb = if dry_run { "Would fix" } else { "Fixed" };
+    say!(" -> {} {} book(s) with no UUID", verb, missing);
+    say!(" -> {} {} book(s) with a duplicated UUID", verb, duplicated);
+
+    if dry_run {
+        tx.rollback()?;
+        say!("\n🧪 Dry run complete; all changes rolled back across {} book(s).", books.len());
+    } else {
+        tx.commit()?;
+        say!("\n✅ Repaired {} UUID(s) across {} book(s).", missing + duplicated, books.len());
+    }
+    Ok(())
+}
+
+/// Re-derives the series index of books in a series (or in every series) from their title and
+/// file names, e.g. after an import that stored 1 for every book. Books with no index, several
+/// conflicting ones, or an index another book of the series also got are only reported.
+pub(crate) fn reindex_series(metadata_conn: &mut Connection, series: Option<&str>, dry_run: bool) -> Result<()> {
+    if dry_run {
+        say!("🧪 Re-deriving series indices (dry run, nothing will be saved)...");
+    } else {
+        say!("🔧 Re-deriving series indices from titles and file names...");
+    }
+
+    let tx = metadata_conn.transaction()?;
+    let books: Vec<(i64, String, f64, String, Option<String>)> = tx.prepare(
+        "SELECT b.id, b.title, b.series_index, s.name,
+                (SELECT group_concat(d.name, char(31)) FROM data d WHERE d.book = b.id)
+         FROM books b
+         JOIN books_series_link bsl ON bsl.book = b.id
+         JOIN series s ON s.id = bsl.series
+         WHERE ?1 IS NULL OR s.name = ?1 COLLATE NOCASE
+         ORDER BY s.sort, s.name, b.series_index, b.id"
+    )?
+    .query_map(params![series], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)))?
+    .collect::<Result<Vec<_>, _>>()?;
+
+    if books.is_empty() {
+        match series {
+            Some(name) => anyhow::bail!("No books found in series '{}'", name),
+            None => say!(" -> No books belong to a series."),
+        }
+        return Ok(());
+    }
+
+    // Derive each book's index first, so duplicates within a series can be spotted
+    let derived: Vec<Vec<f64>> = books.iter()
+        .map(|(_, title, _, _, data_names)| {
+            let mut candidates = series_index_candidates(title);
+            for name in data_names.iter().flat_map(|names| names.split('\u{1f}')) {
+                for index in series_index_candidates(name) {
+                    if !candidates.iter().any(|&seen| format_series_index(seen) == format_series_index(index)) {
+                        candidates.push(index);
+                    }
+                }
+            }
+            candidates
+        })
+        .collect();
+
+    let mut updated = 0;
+    let mut unchanged = 0;
+    let mut ambiguous = 0;
+    let mut current_series = "";
+    for (i, ((book_id, title, series_index, series_name, _), candidates)) in books.iter().zip(&derived).enumerate() {
+        if series_name != current_series {
+            say!("\n📚 {}", series_name);
+            current_series = series_name;
+        }
+
+        let problem = match candidates.as_slice() {
+            [] => Some("no index found in the title or file name".to_string()),
+            [index] => books.iter().zip(&derived).enumerate()
+                .find(|(j, ((_, _, _, other_series, _), other))| {
+                    *j != i && other_series == series_name
+                        && matches!(other.as_slice(), [other_index] if format_series_index(*other_index) == format_series_index(*index))
+                })
+                .map(|(_, ((other_id, ..), _))| format!("index {} also found for book {}", format_series_index(*index), other_id)),
+            several => Some(format!("conflicting indices {}",
+                several.iter().map(|&index| format_series_index(index)).collect::<Vec<_>>().join(", "))),
+        };
+        if let Some(problem) = problem {
+            say!("    ⚠️  ID {} — '{}' (#{}): {}; fix by hand", book_id, title, format_series_index(*series_index), problem);
+            ambiguous += 1;
+            continue;
+        }
+
+        let index = candidates[0];
+        if format_series_index(index) == format_series_index(*series_index) {
+            unchanged += 1;
+            continue;
+        }
+        say!("    ID {} — '{}': #{} -> #{}", book_id, title, format_series_index(*series_index), format_series_index(index));
+        tx.execute("UPDATE books SET series_index = ?1, last_modified = ?2 WHERE id = ?3",
+            params![index, now_utc_micro(), book_id])?;
+        set_metadata_dirty(&tx, *book_id)?;
+        updated += 1;
+    }
+
+    let verb = if dry_run { "Would update" } else { "Updated" };
+    say!("\n -> {} {} book(s); {} already correct; {} need a manual fix", verb, updated, unchanged, ambiguous);
+
+    if dry_run {
+        tx.rollback()?;
+        say!("\n🧪 Dry run complete; all changes rolled back across {} book(s).", books.len());
+    } else {
+        tx.commit()?;
+        say!("\n✅ Re-derived the series index of {} book(s).", updated);
+    }
+    Ok(())
+}
+
+/// Sets `authors.sort` for every author in `author_sorts` (keyed by lowercased name) and
+/// rebuilds `books.author_sort` for their books. Names that match no author are listed.
+pub(crate) fn apply_author_sorts(metadata_conn: &mut Connection, author_sorts: &HashMap<String, String>, dry_run: bool) -> Result<()> {
+    if dry_run {
+        say!("🧪 Applying author sort names (dry run, nothing will be saved)...");
+    } else {
+        say!("🔧 Applying author sort names...");
+    }
+
+    let tx = metadata_conn.transaction()?;
+    let authors: Vec<(i64, String, Option<String>)> = tx.prepare("SELECT id, name, sort FROM authors ORDER BY sort, name")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut matched = HashSet::new();
+    let mut books = BTreeSet::new();
+    let mut authors_fixed = 0;
+    for (author_id, name, sort) in &authors {
+        let key = name.trim().to_lowercase();
+        let Some(new_sort) = author_sorts.get(&key) else { continue };
+        matched.insert(key);
+        if sort.as_deref() == Some(new_sort.as_str()) {
+            continue;
+        }
+        say!("    {}: '{}' -> '{}'", name, sort.as_deref().unwrap_or(""), new_sort);
+        tx.execute("UPDATE authors SET sort = ?1 WHERE id = ?2", params![new_sort, author_id])?;
+        let mut stmt = tx.prepare("SELECT book FROM books_authors_link WHERE author = ?1")?;
+        for book_id in stmt.query_map(params![author_id], |row| row.get::<_, i64>(0))? {
+            books.insert(book_id?);
+        }
+        authors_fixed += 1;
+    }
+
+    let mut books_fixed = 0;
+    for &book_id in &books {
+        let current: Option<String> = tx.query_row("SELECT author_sort FROM books WHERE id = ?1", params![book_id], |row| row.get(0))?;
+        if current != Some(refresh_book_author_sort(&tx, book_id)?) {
+            set_metadata_dirty(&tx, book_id)?;
+            books_fixed += 1;
+        }
+    }
+
+    let mut unmatched: Vec<&String> = author_sorts.keys().filter(|key| !matched.contains(*key)).collect();
+    unmatched.sort();
+    if !unmatched.is_empty() {
+        say!("\n⚠️  {} name(s) in the file match no author in the library:", unmatched.len());
+        for name in unmatched {
+            say!("    - {}", name);
+        }
+    }
+
+    let verb = if dry_run { "Would update" } else { "Updated" };
+    say!("\n -> {} the sort name of {} author(s)", verb, authors_fixed);
+    say!(" -> {} the author sort of {} book(s)", verb, books_fixed);
+
+    if dry_run {
+        tx.rollback()?;
+        say!("\n🧪 Dry run complete; all changes rolled back.");
+    } else {
+        tx.commit()?;
+        say!("\n✅ Applied {} author sort name(s).", authors_fixed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_file_status_knows_every_format() {
+        let files = vec!["Comic - Jane Roe.cbz".to_string(), "Other Name.fb2".to_string(), "Book.kepub.epub".to_string()];
+        assert_eq!(data_file_status("CBZ", "Comic - Jane Roe", &files), DataFileStatus::Present);
+        assert_eq!(data_file_status("FB2", "Comic - Jane Roe", &files), DataFileStatus::Renamed("Other Name".to_string()));
+        assert_eq!(data_file_status("KEPUB", "Book", &files), DataFileStatus::Present);
+        assert_eq!(data_file_status("EPUB", "Book", &files), DataFileStatus::Missing);
+        assert_eq!(data_file_status("ORIGINAL_EPUB", "Book", &files), DataFileStatus::Missing);
+    }
+
+    #[test]
+    fn test_data_file_status_is_case_sensitive() {
+        let files = vec!["Other Name.FB2".to_string(), "book.EPUB".to_string()];
+        let wrong_case = |file: &str, expected: &str| DataFileStatus::WrongCase { file: file.to_string(), expected: expected.to_string() };
+        assert_eq!(data_file_status("FB2", "Comic - Jane Roe", &files), wrong_case("Other Name.FB2", "Comic - Jane Roe.fb2"));
+        assert_eq!(data_file_status("EPUB", "Book", &files), wrong_case("book.EPUB", "Book.epub"));
+        assert_eq!(data_file_status("PDF", "Book", &files), DataFileStatus::Missing);
+    }
+
+    #[test]
+    fn test_trim_orphan_data_rows_keeps_other_formats() {
+        let library_dir = std::env::temp_dir().join(format!("cwh-trim-data-{}", std::process::id()));
+        let book_dir = library_dir.join("Jane Roe/Comic (1)");
+        std::fs::create_dir_all(&book_dir).unwrap();
+        std::fs::write(book_dir.join("Comic - Jane Roe.cbz"), b"zip").unwrap();
+        std::fs::write(book_dir.join("Comic - Jane Roe.original_epub"), b"zip").unwrap();
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE books (id INTEGER PRIMARY KEY, title TEXT, path TEXT);
+             CREATE TABLE data (id INTEGER PRIMARY KEY, book INTEGER, format TEXT, name TEXT);
+             INSERT INTO books VALUES (1, 'Comic', 'Jane Roe/Comic (1)');
+             INSERT INTO data (book, format, name) VALUES
+                 (1, 'CBZ', 'Comic - Jane Roe'), (1, 'ORIGINAL_EPUB', 'Comic - Jane Roe'), (1, 'FB2', 'Comic - Jane Roe');"
+        ).unwrap();
+
+        let tx = conn.transaction().unwrap();
+        trim_orphan_data_rows(&tx, &library_dir).unwrap();
+        let formats: Vec<String> = tx.prepare("SELECT format FROM data ORDER BY format").unwrap()
+            .query_map([], |row| row.get(0)).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        assert_eq!(formats, vec!["CBZ", "ORIGINAL_EPUB"]);
+
+        drop(tx);
+        std::fs::remove_dir_all(&library_dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_formats_adds_uppercase_extension_file() {
+        let library_dir = std::env::temp_dir().join(format!("cwh-check-formats-{}", std::process::id()));
+        let book_dir = library_dir.join("Jane Roe/Loud (1)");
+        std::fs::create_dir_all(&book_dir).unwrap();
+        std::fs::write(book_dir.join("Loud - Jane Roe.EPUB"), b"zip").unwrap();
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE books (id INTEGER PRIMARY KEY, title TEXT, path TEXT);
+             CREATE TABLE data (id INTEGER PRIMARY KEY, book INTEGER, format TEXT, uncompressed_size INTEGER, name TEXT);
+             INSERT INTO books VALUES (1, 'Loud', 'Jane Roe/Loud (1)');"
+        ).unwrap();
+
+        check_formats(&mut conn, &library_dir, true).unwrap();
+        let name: String = conn.query_row("SELECT name FROM data WHERE format = 'EPUB'", [], |row| row.get(0)).unwrap();
+        assert_eq!(name, "Loud - Jane Roe");
+        assert_eq!(file_names_in_dir(&book_dir).unwrap(), vec!["Loud - Jane Roe.epub"]);
+
+        std::fs::remove_dir_all(&library_dir).unwrap();
+    }
+
+    #[test]
+    fn test_dry_run_opens_file_database_writable() {
+        use crate::db::{open_connection, DatabaseConfig};
+        let path = std::env::temp_dir().join(format!("cwh-dry-run-{}.db", std::process::id()));
+        Connection::open(&path).unwrap().execute_batch(
+            "CREATE TABLE books (id INTEGER PRIMARY KEY, title TEXT, uuid TEXT);
+             CREATE TABLE metadata_dirtied (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, UNIQUE(book));
+             INSERT INTO books VALUES (1, 'No UUID', NULL);"
+        ).unwrap();
+
+        let command = crate::cli::Commands::RepairUuids { dry_run: true };
+        assert!(command.is_read_only());
+        let config = DatabaseConfig { read_only: command.never_writes(), ..Default::default() };
+        let mut conn = open_connection(&path, &config).unwrap();
+        repair_uuids(&mut conn, true).expect("the dry run writes in a transaction it rolls back");
+        let uuid: Option<String> = conn.query_row("SELECT uuid FROM books", [], |row| row.get(0)).unwrap();
+        assert_eq!(uuid, None);
+
+        // The same dry run fails on a read-only connection
+        let mut conn = open_connection(&path, &DatabaseConfig { read_only: true, ..Default::default() }).unwrap();
+        assert!(repair_uuids(&mut conn, true).is_err());
+
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,4 +1,7 @@
-use clap::{Parser, Subcommand};
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use encoding_rs::Encoding;
+use regex::Regex;
 use std::path::PathBuf;
 
 /// A command-line tool to manage a Calibre library.
@@ -21,10 +24,143 @@ pub struct Cli {
     #[clap(long, value_parser, global = true)]
     pub epub_dir: Option<PathBuf>,
 
+    /// Don't repair NULL timestamps in the databases before running the command.
+    #[clap(long, global = true)]
+    pub no_auto_repair: bool,
+
+    /// Open both databases read-only, so any command that tries to write fails instead. Commands
+    /// that never write (list, inspect-db, check-fk, ...) always open them this way
+    #[clap(long, global = true)]
+    pub read_only: bool,
+
+    /// Print every SQL statement executed against the databases to stderr.
+    #[clap(long, global = true)]
+    pub print_sql: bool,
+
+    /// Use plain ASCII markers instead of emoji in output (also enabled by setting CWH_ASCII)
+    #[clap(long, alias = "no-emoji", global = true)]
+    pub ascii: bool,
+
+    /// Output format for reports that support machine-readable output (inspect-db, find-by-identifier)
+    #[clap(long, alias = "output", value_enum, default_value_t = OutputFormat::Text, global = true)]
+    pub format: OutputFormat,
+
+    /// When to color success, warning and error lines (NO_COLOR disables `auto`)
+    #[clap(long, value_enum, default_value_t = ColorChoice::Auto, global = true)]
+    pub color: ColorChoice,
+
+    /// Hash used to compare book files on add and for new verify-hashes manifests
+    #[clap(long, alias = "checksum-algorithm", value_enum, default_value_t = ChecksumAlgorithm::Sha1, global = true)]
+    pub checksum: ChecksumAlgorithm,
+
+    /// Directory to stage copied files in before renaming them into the library (defaults to the
+    /// book's own directory; files staged on another file system are copied across first)
+    #[clap(long, value_parser, global = true)]
+    pub temp_dir: Option<PathBuf>,
+
+    /// Refuse commands that delete books or records (delete, clean-db, clean-shelves, prune-*,
+    /// check-formats --fix) unless each database has a backup at most this many hours old
+    #[clap(long, value_name = "HOURS", global = true)]
+    pub require_backup_within: Option<u64>,
+
+    /// Directory of externally managed backups; with --require-backup-within, any file in it
+    /// modified recently enough also counts as a backup
+    #[clap(long, value_parser, requires = "require_backup_within", global = true)]
+    pub backup_dir: Option<PathBuf>,
+
+    /// Calibre-Web user that owns shelves when a command is given no --username (defaults to
+    /// the admin user; also read from CWH_DEFAULT_USER)
+    #[clap(long, alias = "shelf-owner-default", value_name = "NAME", global = true)]
+    pub default_user: Option<String>,
+
     #[clap(subcommand)]
     pub command: Commands,
 }
 
+/// How reports are printed
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable text
+    Text,
+    /// A JSON document on stdout; progress messages go to stderr
+    Json,
+}
+
+/// Hash function for file checksums
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// SHA-1, fast and compatible with manifests written by earlier versions
+    Sha1,
+    /// SHA-256
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    /// The name stored in hash manifests
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha1 => "sha1",
+            ChecksumAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    /// Parses a name stored in a hash manifest
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sha1" => Some(ChecksumAlgorithm::Sha1),
+            "sha256" => Some(ChecksumAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+}
+
+/// When to use colored output
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color when writing to a terminal and NO_COLOR is not set
+    Auto,
+    /// Always color
+    Always,
+    /// Never color
+    Never,
+}
+
+/// What to do with an existing book's comments when the re-added file's description differs
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentsStrategy {
+    /// Replace them with the file's description
+    Overwrite,
+    /// Keep them unchanged (a book without comments still gets the file's description)
+    Preserve,
+    /// Add the file's description after them, unless they already contain it
+    Append,
+    /// Keep whichever of the two is longer
+    PreferLonger,
+}
+
+impl CommentsStrategy {
+    /// The name used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            CommentsStrategy::Overwrite => "overwrite",
+            CommentsStrategy::Preserve => "preserve",
+            CommentsStrategy::Append => "append",
+            CommentsStrategy::PreferLonger => "prefer-longer",
+        }
+    }
+}
+
+/// Order in which `add --epub-dir` imports files
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportOrder {
+    /// By file name
+    Filename,
+    /// By title, ignoring leading articles like "The"
+    Title,
+    /// By series name and index, then title; books without a series come last
+    SeriesIndex,
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Add an EPUB file to the library
@@ -32,12 +168,172 @@ pub enum Commands {
         /// The name of the shelf to add the book to.
         #[clap(long)]
         shelf: Option<String>,
-        /// The username to associate the shelf with. If not provided, uses the default admin user.
-        #[clap(long, help = "The username to associate the shelf with. If not provided, uses the default admin user.")]
+        /// The username to associate the shelf with. If not provided, uses --default-user or the admin user.
+        #[clap(long, help = "The username to associate the shelf with. If not provided, uses --default-user or the admin user.")]
         username: Option<String>,
         /// Show what would be done without making any changes
         #[clap(long)]
         dry_run: bool,
+        /// Write a JSON report of per-file results when adding a directory
+        #[clap(long, value_parser)]
+        report_file: Option<PathBuf>,
+        /// When adding a directory, import only the first of several files with identical content
+        #[clap(long, alias = "dedupe")]
+        skip_content_duplicates: bool,
+        /// Date the book was added (YYYY-MM-DD), for preserving history when migrating a back catalog
+        #[clap(long, alias = "created", value_parser = parse_date)]
+        added_date: Option<NaiveDate>,
+        /// JPEG quality (1-100) used when re-encoding oversized cover images
+        #[clap(long, alias = "compress-cover-quality", default_value_t = 82, value_parser = clap::value_parser!(u8).range(1..=100))]
+        cover_quality: u8,
+        /// Skip cover images larger than this many pixels (width x height) to avoid running out of memory
+        #[clap(long, default_value_t = 40_000_000)]
+        max_cover_pixels: u64,
+        /// Downscale covers wider than this many pixels, preserving aspect ratio
+        #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+        cover_max_width: Option<u32>,
+        /// Downscale covers taller than this many pixels, preserving aspect ratio
+        #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+        cover_max_height: Option<u32>,
+        /// Fail the add when the cover image is unreadable or can't be saved, instead of importing
+        /// the book without a cover
+        #[clap(long)]
+        fail_on_cover_errors: bool,
+        /// Give books without a usable cover a generated one showing their title and author.
+        /// The built-in font covers ASCII and accented Latin letters only; a title or author in
+        /// another script is left off the cover
+        #[clap(long, alias = "cover-placeholder")]
+        generate_placeholder_cover: bool,
+        /// Background color of generated placeholder covers, as hex RGB
+        #[clap(long, value_name = "RRGGBB", default_value = "2f4f4f", value_parser = parse_hex_color, requires = "generate_placeholder_cover")]
+        placeholder_cover_color: [u8; 3],
+        /// Legacy encoding (e.g. windows-1252, iso-8859-1) to re-decode EPUB metadata text from,
+        /// for batches known to have garbled titles. Without it only text that looks garbled is repaired
+        #[clap(long, value_parser = parse_encoding)]
+        source_encoding: Option<&'static Encoding>,
+        /// Prepare the book for Kobo sync: enable Kobo sync on the shelf and create the reading state records
+        #[clap(long, requires = "shelf")]
+        for_kobo: bool,
+        /// Layout of new book directories, using the tokens {author}, {title}, {series}, {series_index},
+        /// {id} and {id_bucket} (the ID modulo 1000, zero-padded)
+        #[clap(long, default_value = crate::utils::DEFAULT_PATH_PATTERN, value_parser = parse_path_pattern)]
+        path_pattern: String,
+        /// Put new books under a `{id_bucket}/` directory (e.g. `345/Author/Title (12345)`) so no single
+        /// directory grows huge. Calibre itself doesn't understand this layout and may move or lose
+        /// track of these books; only use it for libraries managed through Calibre-Web alone
+        #[clap(long)]
+        shard_by_id: bool,
+        /// Fail on EPUBs with a missing or blank title or author instead of falling back to the filename or "Unknown",
+        /// and on EPUBs that appear to be DRM-protected instead of only warning
+        #[clap(long)]
+        strict: bool,
+        /// File of custom language mappings (JSON object or source<TAB>code lines) tried before the built-in table
+        #[clap(long, value_parser)]
+        lang_map_file: Option<PathBuf>,
+        /// When adding a directory, stop at the first file that fails instead of continuing
+        #[clap(long)]
+        fail_fast: bool,
+        /// Compute author sort names with Calibre's rules instead of Calibre-Web's simpler ones
+        #[clap(long)]
+        canonical_author_sort: bool,
+        /// With --canonical-author-sort, keep particles like "van" or "de" with the surname,
+        /// like Calibre's author_use_surname_prefixes tweak
+        #[clap(long, requires = "canonical_author_sort")]
+        author_surname_prefixes: bool,
+        /// Store author names unchanged as their sort name instead of inverting them to "Last, First"
+        #[clap(long, conflicts_with = "canonical_author_sort")]
+        no_author_sort_inversion: bool,
+        /// File of author sort names (JSON object or name<TAB>sort lines) used for new authors it
+        /// lists instead of computing one, e.g. "bell hooks" or "Miyazaki Hayao"; authors already in
+        /// the library keep their sort, which apply-author-sorts rewrites
+        #[clap(long, value_parser)]
+        author_sort_map: Option<PathBuf>,
+        /// When re-adding an existing book whose description differs: keep the library's comments
+        /// (so edits made in Calibre survive), replace them, append the new text, or keep the longer one
+        #[clap(long, alias = "merge-comments", value_enum, default_value_t = CommentsStrategy::Preserve)]
+        comments_strategy: CommentsStrategy,
+        /// Same as `--comments-strategy preserve`, kept for existing scripts
+        #[clap(long, hide = true, conflicts_with = "comments_strategy")]
+        preserve_comments: bool,
+        /// Give the copied book file and cover the modification time of their source file
+        #[clap(long)]
+        preserve_timestamps: bool,
+        /// Treat an existing book's file as unchanged when its size and modification time match the
+        /// incoming file, instead of hashing both (much faster on a NAS). Only library files copied
+        /// with --preserve-timestamps can match; an edit that keeps size and mtime goes unnoticed
+        #[clap(long)]
+        skip_hash_check: bool,
+        /// When adding a directory, stream one JSON object per line to stdout for each progress
+        /// event (start, file_begin, file_result, complete); other messages go to stderr
+        #[clap(long)]
+        progress_json: bool,
+        /// When adding a directory, skip files whose title and author are already in the library
+        /// without hashing or updating them
+        #[clap(long)]
+        skip_existing: bool,
+        /// When adding a directory, only print the output of files that became new books (and of
+        /// failures); the summary still counts every outcome
+        #[clap(long)]
+        only_new: bool,
+        /// When adding a directory, only print the output of files that updated an existing book
+        /// (and of failures); combine with --only-new to show both
+        #[clap(long, alias = "only-updates")]
+        only_updated: bool,
+        /// Skip book files larger than this many megabytes instead of importing them
+        #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+        max_file_size_mb: Option<u64>,
+        /// When adding a directory, continue even if the library volume looks too full for the files
+        #[clap(long)]
+        ignore_space: bool,
+        /// When adding a directory, the order to import files in, which is also the order they're
+        /// added to --shelf (and shown on a Kobo); title and series-index read each file's metadata first
+        #[clap(long, alias = "sort-by", value_enum, default_value_t = ImportOrder::Filename)]
+        order_by: ImportOrder,
+        /// When an existing book has several formats, which one to hash against the incoming file
+        /// after any file of the incoming file's own format (comma-separated extensions)
+        #[clap(long, alias = "library-format-priority", value_delimiter = ',', default_value = "kepub,epub")]
+        prefer_format: Vec<String>,
+        /// Store series indices that are whole numbers up to rounding noise (e.g. 2.9999999) as exact whole numbers
+        #[clap(long)]
+        int_series_index: bool,
+        /// Store valid ISBN-10s as their ISBN-13 equivalent (978 prefix and a new check digit)
+        #[clap(long)]
+        isbn13: bool,
+        /// With --isbn13, also keep the original ISBN-10 as an ISBN10 identifier
+        #[clap(long, requires = "isbn13")]
+        keep_isbn10: bool,
+        /// Tell EPUB from KEPUB by looking for Kobo's markup in the content instead of trusting the
+        /// extension, and store and name the file by the format found
+        #[clap(long, alias = "detect-kepub-by-content")]
+        sniff_format: bool,
+        /// Regex with named groups `series` and optionally `index` and `title`, matched against the
+        /// file name (without extension) of books that have no series metadata, e.g.
+        /// '^(?P<series>.+?) (?P<index>\d+) - (?P<title>.+)$'. `title` is only used when the book
+        /// has no title of its own. Files that don't match fall back to the built-in heuristics
+        #[clap(long, value_parser = parse_series_regex)]
+        series_regex: Option<Regex>,
+        /// Read the book files to add from stdin, one path per line (e.g. from `find` or `fd`),
+        /// instead of --epub-file or --epub-dir; missing files are skipped with a warning
+        #[clap(long)]
+        stdin: bool,
+        /// Only check that each file would import (metadata, EPUB structure, cover) and print a
+        /// pass/fail line per file; nothing is written and no database is needed
+        #[clap(long, conflicts_with = "dry_run")]
+        validate_only: bool,
+        /// After the import, list each language code that didn't map to a known code (and was stored
+        /// as "und") with how many books used it, for extending --lang-map-file
+        #[clap(long)]
+        report_unmapped_languages: bool,
+        /// Also put the book on a shelf for each of its EPUB subjects (tags), creating shelves as
+        /// needed. Without --tag-shelf-map every tag goes to the shelf of the same name
+        #[clap(long)]
+        auto_shelf_by_tag: bool,
+        /// With --auto-shelf-by-tag, only shelve tags listed in this file (JSON object or
+        /// tag<TAB>shelf lines), on the shelf the file gives for each
+        #[clap(long, value_parser, requires = "auto_shelf_by_tag")]
+        tag_shelf_map: Option<PathBuf>,
+        #[clap(flatten)]
+        overrides: Box<MetadataOverrides>,
     },
     /// List all books in the library with their attributes
     List {
@@ -47,38 +343,428 @@ pub enum Commands {
         /// Show only books that aren't on any shelf
         #[clap(long, conflicts_with = "shelf")]
         unshelved: bool,
+        /// With --unshelved, only count shelves owned by this user as shelved
+        #[clap(long, requires = "unshelved")]
+        username: Option<String>,
+        /// Also show books archived in Calibre-Web, marked with [archived]
+        #[clap(long)]
+        include_archived: bool,
+        /// Only list books with an ID greater than this, for incremental processing
+        #[clap(long)]
+        since_id: Option<i64>,
+        /// Only list books in this series (case-insensitive), ordered by series index
+        #[clap(long)]
+        series: Option<String>,
+        /// With --series, only list books at or after this series index
+        #[clap(long, requires = "series")]
+        min_series_index: Option<f64>,
+        /// With --series, only list books at or before this series index
+        #[clap(long, requires = "series")]
+        max_series_index: Option<f64>,
+        /// Only list the book with the highest series index in each series, ordered by series,
+        /// to see where each series was left off
+        #[clap(long, alias = "newest-per-series")]
+        latest_per_series: bool,
         /// List all attributes for each book.
         #[clap(long)]
         verbose: bool,
+        /// Print only the number of matching books
+        #[clap(long, conflicts_with = "verbose")]
+        count: bool,
+        /// Also print the absolute path of each book file, ready to pass to other tools
+        #[clap(long, conflicts_with = "count")]
+        full_paths: bool,
+        /// Print one line per book on stdout as `ID<TAB>Title<TAB>Authors<TAB>Series #n`, for cut/awk;
+        /// all other messages go to stderr
+        #[clap(long, conflicts_with_all = ["verbose", "count", "full_paths"])]
+        compact: bool,
+        /// Print books under a heading for each Calibre-Web shelf, then an "Unshelved" group;
+        /// books on several shelves appear under each. Needs --appdb-file
+        #[clap(long, conflicts_with_all = ["shelf", "unshelved", "series", "since_id", "latest_per_series", "verbose", "count", "full_paths", "compact", "username", "min_series_index", "max_series_index"])]
+        group_by_shelf: bool,
     },
     /// Delete a book from the library by its ID. Also removes it from Calibre-Web shelves.
     Delete {
         /// The ID of the book to delete.
-        #[clap(value_parser)]
-        book_id: i64,
+        #[clap(value_parser, required_unless_present = "book_id_file")]
+        book_id: Option<i64>,
+        /// Delete every book listed in this file (one ID per line; blank lines and `#` comments are ignored)
+        #[clap(long, conflicts_with = "book_id")]
+        book_id_file: Option<PathBuf>,
+        /// Only delete the metadata.db and app.db records, leaving the book files on disk
+        #[clap(long)]
+        db_only: bool,
+        /// Only delete the book directories, leaving the database records
+        #[clap(long, conflicts_with = "db_only")]
+        files_only: bool,
     },
-    /// List all available shelves from the Calibre-Web database
+    /// List all shelves from the Calibre-Web database with their owner, Kobo sync status and book count
     ListShelves,
     /// Remove any shelves that don't have any books on them.
-    CleanShelves,
+    CleanShelves {
+        /// Only report the orphaned links and empty shelves that would be removed
+        #[clap(long, alias = "report-orphans-only")]
+        report_only: bool,
+    },
     /// Inspect the app.db database
     InspectDb,
     /// Clean up orphaned data in both databases
-    CleanDb,
+    CleanDb {
+        /// First repair books whose directory was renamed in Calibre, so they aren't removed as orphans
+        #[clap(long)]
+        verify_paths: bool,
+        /// Also remove `data` (format) rows of existing books whose directory has no file of that
+        /// format, e.g. after a format was deleted by hand
+        #[clap(long)]
+        trim_orphan_data_rows: bool,
+    },
+    /// Remove authors, publishers, series and tags that no book uses, without the full clean-db pass
+    PruneMetadata,
+    /// Remove identifiers of missing books and collapse duplicate (book, type) identifiers
+    PruneIdentifiers,
+    /// Recompute every book's title sort, author sort and cover flag, and series sorts, in one pass
+    Normalize {
+        /// Show what would change without saving anything
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Give books with a missing or duplicated UUID a new one, which Calibre-Web's Kobo sync needs
+    RepairUuids {
+        /// Show which books would get a new UUID without saving anything
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Set the sort name of each author listed in a file (JSON object or name<TAB>sort lines)
+    /// and rebuild the author sort of their books
+    ApplyAuthorSorts {
+        /// The file of author names and sort names
+        map_file: PathBuf,
+        /// Show what would change without saving anything
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Re-derive series indices from book titles and file names, e.g. after an import stored 1 for every book
+    ReindexSeries {
+        /// Only reindex this series (default: every series)
+        series: Option<String>,
+        /// Show the new indices without saving anything
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Check that each book's format records match the files in its directory
+    CheckFormats {
+        /// Reconcile the format records with the files actually present, renaming files with a
+        /// wrongly cased extension or name
+        #[clap(long)]
+        fix: bool,
+        /// Also check for books whose directory was renamed in Calibre (repaired with --fix)
+        #[clap(long)]
+        verify_paths: bool,
+    },
+    /// Find books whose directory was renamed in Calibre, leaving books.path pointing at a missing folder
+    VerifyPaths {
+        /// Point books.path at the renamed directory
+        #[clap(long)]
+        fix: bool,
+    },
+    /// Find books stored with the undetermined language "und" and the codes their files actually list
+    AuditLanguages,
     /// Fix Kobo sync issues for books on Kobo shelves
-    FixKoboSync,
+    FixKoboSync {
+        /// Show what would be fixed without changing app.db
+        #[clap(long)]
+        dry_run: bool,
+        /// Only fix this user's Kobo shelves and reading states (default: all users)
+        #[clap(long)]
+        username: Option<String>,
+    },
     /// Diagnose Kobo sync setup and show detailed information
-    DiagnoseKoboSync,
+    DiagnoseKoboSync {
+        /// Only report on this user's Kobo shelves (default: all users)
+        #[clap(long)]
+        username: Option<String>,
+    },
+    /// Find books by an identifier such as an ISBN or ASIN (ISBN hyphens are ignored)
+    FindByIdentifier {
+        /// The identifier type, e.g. isbn, asin, goodreads
+        #[clap(value_name = "TYPE")]
+        id_type: String,
+        /// The identifier value
+        value: String,
+    },
+    /// Copy a book's cover.jpg out of the library, e.g. for building a thumbnail catalog
+    DumpCover {
+        /// The ID of the book
+        book_id: i64,
+        /// Where to write the cover image
+        output: PathBuf,
+        /// Scale the cover down to this width in pixels, keeping its aspect ratio
+        #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+        width: Option<u32>,
+    },
+    /// Print the tables and columns of the given databases and check the columns this tool uses
+    DumpSchema,
+    /// Check the given databases for rows referencing missing rows (PRAGMA foreign_key_check)
+    #[clap(alias = "verify-foreign-keys")]
+    CheckFk,
+    /// Verify library files against a stored hash manifest to detect bit-rot
+    VerifyHashes {
+        /// Record the current hash for files whose hash has changed
+        #[clap(long)]
+        accept_changes: bool,
+    },
+    /// Compare two shelves and show which books are on one, the other, or both
+    ShelfDiff {
+        /// The first shelf to compare
+        shelf_a: String,
+        /// The second shelf to compare
+        shelf_b: String,
+        /// The user owning both shelves. If not provided, uses --default-user or the admin user
+        #[clap(long)]
+        username: Option<String>,
+    },
+    /// Copy all books from one shelf to another, creating the target shelf if needed
+    CopyShelf {
+        /// The shelf to copy books from
+        source: String,
+        /// The shelf to copy books to
+        target: String,
+        /// The user owning the source shelf. If not provided, uses --default-user or the admin user
+        #[clap(long)]
+        source_user: Option<String>,
+        /// The user owning the target shelf. If not provided, uses --default-user or the admin user
+        #[clap(long)]
+        target_user: Option<String>,
+    },
+    /// Mark a shelf as modified and clear its Kobo sync records so the Kobo re-pulls it
+    TouchShelf {
+        /// The shelf to touch
+        shelf: String,
+        /// The user owning the shelf. If not provided, uses --default-user or the admin user
+        #[clap(long)]
+        username: Option<String>,
+    },
+    /// Copy the EPUB of every book on a shelf into a flat directory as "{author} - {title}.epub"
+    #[clap(alias = "shelf-export")]
+    ExportShelf {
+        /// The shelf to export
+        shelf: String,
+        /// The directory to copy the EPUB files into
+        output_dir: PathBuf,
+        /// Only export books with an ID greater than this, for incremental exports
+        #[clap(long)]
+        since_id: Option<i64>,
+        /// The user owning the shelf. If not provided, uses --default-user or the admin user
+        #[clap(long)]
+        username: Option<String>,
+    },
+    /// List the database backups made before destructive operations
+    ListBackups,
+    /// Replace a database with one of its backups, saving a copy of the current file first
+    RestoreBackup {
+        /// The backup file to restore
+        backup: PathBuf,
+        /// The database to replace. Defaults to the database the backup was made from
+        #[clap(long)]
+        target: Option<PathBuf>,
+        /// Don't ask for confirmation
+        #[clap(long)]
+        yes: bool,
+    },
     /// Add an existing book to a shelf (like Calibre-Web does)
     AddToShelf {
         /// The ID of the book to add to the shelf
-        #[clap(value_parser)]
-        book_id: i64,
+        #[clap(value_parser, required_unless_present = "book_id_file")]
+        book_id: Option<i64>,
+        /// Add every book listed in this file (one ID per line; blank lines and `#` comments are ignored)
+        #[clap(long, conflicts_with = "book_id")]
+        book_id_file: Option<PathBuf>,
         /// The name of the shelf to add the book to
         #[clap(long)]
         shelf: String,
-        /// The username to associate the shelf with. If not provided, uses the default admin user
+        /// The username to associate the shelf with. If not provided, uses --default-user or the admin user
         #[clap(long)]
         username: Option<String>,
     },
-}
\ No newline at end of file
+    /// Move a book from one shelf to another in a single step
+    MoveToShelf {
+        /// The ID of the book to move
+        #[clap(value_parser)]
+        book_id: i64,
+        /// The shelf to take the book off
+        #[clap(long)]
+        from_shelf: String,
+        /// The shelf to put the book on (created if it doesn't exist)
+        #[clap(long)]
+        to_shelf: String,
+        /// The user owning both shelves. If not provided, uses --default-user or the admin user
+        #[clap(long)]
+        username: Option<String>,
+    },
+}
+
+impl Commands {
+    /// Returns true for commands that leave the databases unchanged, including dry runs that
+    /// roll their changes back. Used to skip the automatic timestamp repair.
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Commands::List { .. }
+                | Commands::Add { validate_only: true, .. }
+                | Commands::ListShelves
+                | Commands::InspectDb
+                | Commands::DiagnoseKoboSync { .. }
+                | Commands::DumpSchema
+                | Commands::DumpCover { .. }
+                | Commands::CheckFk
+                | Commands::FindByIdentifier { .. }
+                | Commands::CleanShelves { report_only: true }
+                | Commands::FixKoboSync { dry_run: true, .. }
+                | Commands::CheckFormats { fix: false, .. }
+                | Commands::VerifyPaths { fix: false }
+                | Commands::AuditLanguages
+                | Commands::Normalize { dry_run: true }
+                | Commands::RepairUuids { dry_run: true }
+                | Commands::ReindexSeries { dry_run: true, .. }
+                | Commands::ApplyAuthorSorts { dry_run: true, .. }
+                | Commands::VerifyHashes { .. }
+                | Commands::ShelfDiff { .. }
+                | Commands::ExportShelf { .. }
+        )
+    }
+
+    /// Returns true for commands that never write to the databases, so they can be opened
+    /// read-only. Unlike `is_read_only`, this excludes dry runs that make their changes in a
+    /// transaction and roll it back.
+    pub fn never_writes(&self) -> bool {
+        self.is_read_only()
+            && !matches!(
+                self,
+                Commands::Normalize { .. }
+                    | Commands::RepairUuids { .. }
+                    | Commands::ReindexSeries { .. }
+                    | Commands::ApplyAuthorSorts { .. }
+                    | Commands::FixKoboSync { .. }
+            )
+    }
+
+    /// Returns true for commands that delete books or database records, which
+    /// `--require-backup-within` guards
+    pub fn is_destructive(&self) -> bool {
+        matches!(
+            self,
+            Commands::Delete { .. }
+                | Commands::CleanDb { .. }
+                | Commands::CleanShelves { report_only: false }
+                | Commands::PruneMetadata
+                | Commands::PruneIdentifiers
+                | Commands::CheckFormats { fix: true, .. }
+        )
+    }
+
+    /// Returns the Calibre-Web usernames given on the command line
+    pub fn usernames(&self) -> Vec<&str> {
+        match self {
+            Commands::Add { username, .. }
+            | Commands::List { username, .. }
+            | Commands::ShelfDiff { username, .. }
+            | Commands::ExportShelf { username, .. }
+            | Commands::AddToShelf { username, .. }
+            | Commands::MoveToShelf { username, .. }
+            | Commands::FixKoboSync { username, .. }
+            | Commands::DiagnoseKoboSync { username }
+            | Commands::TouchShelf { username, .. } => username.iter().map(String::as_str).collect(),
+            Commands::CopyShelf { source_user, target_user, .. } => {
+                source_user.iter().chain(target_user).map(String::as_str).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Metadata values that replace what was read from the book file (single-file add only, apart from `--corporate-author`).
+#[derive(Args, Debug, Default, Clone)]
+pub struct MetadataOverrides {
+    /// Override the book title
+    #[clap(long)]
+    pub title: Option<String>,
+    /// Override the book author
+    #[clap(long)]
+    pub author: Option<String>,
+    /// Override the series name
+    #[clap(long)]
+    pub series: Option<String>,
+    /// Override the position within the series
+    #[clap(long)]
+    pub series_index: Option<f64>,
+    /// Override the publisher
+    #[clap(long)]
+    pub publisher: Option<String>,
+    /// Override the book's languages, replacing all of those in the file (e.g. "en" or "eng,fra")
+    #[clap(long, alias = "force-language", value_delimiter = ',')]
+    pub language: Vec<String>,
+    /// The author is an organization: store its name unchanged as the author sort. Unlike the
+    /// other overrides, this also applies to every file of a directory or list import.
+    #[clap(long)]
+    pub corporate_author: bool,
+}
+
+impl MetadataOverrides {
+    /// Returns true if none of the per-book values were given. `--corporate-author` isn't
+    /// counted, since it applies to any number of books.
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.author.is_none()
+            && self.series.is_none()
+            && self.series_index.is_none()
+            && self.publisher.is_none()
+            && self.language.is_empty()
+    }
+
+    /// Drops the per-book values, keeping the overrides that apply to every file
+    pub fn for_every_file(&self) -> Self {
+        MetadataOverrides { corporate_author: self.corporate_author, ..Default::default() }
+    }
+}
+
+/// Parses a `YYYY-MM-DD` date argument
+fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|e| format!("invalid date '{}' (expected YYYY-MM-DD): {}", value, e))
+}
+
+/// Parses a `RRGGBB` color, with or without a leading `#`
+fn parse_hex_color(value: &str) -> Result<[u8; 3], String> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    let channel = |i: usize| hex.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok());
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok([r, g, b]),
+        _ => Err(format!("invalid color '{}' (expected hex RRGGBB, e.g. 2f4f4f)", value)),
+    }
+}
+
+/// Parses a WHATWG encoding label such as `windows-1252` or `latin1`
+fn parse_encoding(value: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(value.as_bytes())
+        .ok_or_else(|| format!("unknown encoding '{}'", value))
+}
+
+/// Compiles a `--series-regex`, which must have a `series` group
+fn parse_series_regex(value: &str) -> Result<Regex, String> {
+    let regex = Regex::new(value).map_err(|e| e.to_string())?;
+    let names: Vec<&str> = regex.capture_names().flatten().collect();
+    if !names.contains(&"series") {
+        return Err("the pattern needs a named group (?P<series>...)".to_string());
+    }
+    if let Some(unknown) = names.iter().find(|name| !["series", "index", "title"].contains(name)) {
+        return Err(format!("unknown group '{}'; only series, index and title are used", unknown));
+    }
+    Ok(regex)
+}
+
+/// Parses a book directory pattern, rejecting unknown tokens
+fn parse_path_pattern(value: &str) -> Result<String, String> {
+    crate::utils::validate_path_pattern(value)
+        .map(|_| value.to_string())
+        .map_err(|e| e.to_string())
+}
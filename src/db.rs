@@ -1,11 +1,19 @@
 use anyhow::{Context, Result};
-use rusqlite::Connection;
-use std::path::Path;
+use rusqlite::{Connection, OpenFlags};
+use rusqlite::trace::{TraceEvent, TraceEventCodes};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::output::say;
 
 /// Configuration for database connections
 pub(crate) struct DatabaseConfig {
     pub(crate) enable_foreign_keys: bool,
     pub(crate) busy_timeout_ms: u32,
+    /// Print every executed SQL statement to stderr
+    pub(crate) print_sql: bool,
+    /// Open with SQLITE_OPEN_READ_ONLY, so any write fails instead of touching the file
+    pub(crate) read_only: bool,
 }
 
 impl Default for DatabaseConfig {
@@ -13,6 +21,8 @@ impl Default for DatabaseConfig {
         Self {
             enable_foreign_keys: true,
             busy_timeout_ms: 5000,
+            print_sql: false,
+            read_only: false,
         }
     }
 }
@@ -23,8 +33,13 @@ pub(crate) fn open_connection(path: &Path, config: &DatabaseConfig) -> Result<Co
         anyhow::bail!("Database file does not exist: {:?}", path);
     }
 
-    let conn = Connection::open(path)
-        .with_context(|| format!("Failed to open database at {:?}", path))?;
+    let conn = if config.read_only {
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        Connection::open_with_flags(path, flags)
+    } else {
+        Connection::open(path)
+    }
+    .with_context(|| format!("Failed to open database at {:?}", path))?;
 
     if config.enable_foreign_keys {
         conn.pragma_update(None, "foreign_keys", "ON")
@@ -36,13 +51,23 @@ pub(crate) fn open_connection(path: &Path, config: &DatabaseConfig) -> Result<Co
             .context("Failed to set busy timeout")?;
     }
 
+    if config.print_sql {
+        conn.trace_v2(TraceEventCodes::SQLITE_TRACE_STMT, Some(print_sql_statement));
+    }
+
     Ok(conn)
 }
 
+/// Trace callback that echoes each statement, with bound parameters expanded, to stderr
+fn print_sql_statement(event: TraceEvent<'_>) {
+    if let TraceEvent::Stmt(stmt, sql) = event {
+        eprintln!("[SQL] {}", stmt.expanded_sql().unwrap_or_else(|| sql.to_string()));
+    }
+}
+
 /// Opens the Calibre metadata.db connection
-pub(crate) fn open_calibre_db(path: &Path) -> Result<Connection> {
-    let config = DatabaseConfig::default();
-    let conn = open_connection(path, &config)?;
+pub(crate) fn open_calibre_db(path: &Path, config: &DatabaseConfig) -> Result<Connection> {
+    let conn = open_connection(path, config)?;
     
     // Add custom functions required by Calibre
     create_calibre_functions(&conn)?;
@@ -50,10 +75,163 @@ pub(crate) fn open_calibre_db(path: &Path) -> Result<Connection> {
     Ok(conn)
 }
 
-/// Opens the Calibre-Web app.db connection
-pub(crate) fn open_appdb(path: &Path) -> Result<Connection> {
-    let config = DatabaseConfig::default();
-    open_connection(path, &config)
+/// Opens the Calibre-Web app.db connection.
+/// Calibre-Web usually keeps app.db in WAL mode; the journal mode is left as found so
+/// writes stay visible to a running server, with a warning if its WAL hasn't been checkpointed.
+pub(crate) fn open_appdb(path: &Path, config: &DatabaseConfig) -> Result<Connection> {
+    // Measured before opening: SQLite removes the -wal file when the last connection closes,
+    // so a non-empty one means another process has it open or left changes behind
+    let pending_wal_bytes = fs::metadata(wal_path(path)).map_or(0, |metadata| metadata.len());
+
+    let conn = open_connection(path, config)?;
+
+    let journal_mode: String = conn.pragma_query_value(None, "journal_mode", |row| row.get(0))
+        .context("Failed to read app.db journal mode")?;
+    if journal_mode.eq_ignore_ascii_case("wal") && pending_wal_bytes > 0 {
+        say!("⚠️  app.db is in WAL mode with {}KB not yet checkpointed into {:?}.", pending_wal_bytes / 1024, path);
+        say!("   Calibre-Web may be running; changes are made through the WAL so it will see them.");
+    }
+
+    Ok(conn)
+}
+
+/// Returns the path of SQLite's write-ahead log for a database file
+pub(crate) fn wal_path(path: &Path) -> PathBuf {
+    let mut wal = path.as_os_str().to_owned();
+    wal.push("-wal");
+    PathBuf::from(wal)
+}
+
+/// Columns this tool reads or writes that older or newer Calibre and Calibre-Web schemas
+/// may lack: (table, column)
+const METADATA_DEPENDENCIES: &[(&str, &str)] = &[
+    ("books", "sort"),
+    ("books", "author_sort"),
+    ("books", "has_cover"),
+    ("books", "last_modified"),
+    ("authors", "sort"),
+    ("data", "uncompressed_size"),
+    ("identifiers", "type"),
+    ("metadata_dirtied", "book"),
+];
+const APPDB_DEPENDENCIES: &[(&str, &str)] = &[
+    ("shelf", "kobo_sync"),
+    ("shelf", "last_modified"),
+    ("book_shelf_link", "date_added"),
+    ("book_shelf_link", "order"),
+    ("kobo_reading_state", "current_bookmark"),
+    ("kobo_reading_state", "priority_timestamp"),
+    ("kobo_synced_books", "book_id"),
+    ("kobo_bookmark", "kobo_reading_state_id"),
+    ("kobo_statistics", "kobo_reading_state_id"),
+    ("book_read_link", "last_time_started_reading"),
+    ("archived_book", "is_archived"),
+    ("user", "kobo_only_shelves_sync"),
+];
+
+/// Returns the names of a table's columns, or an empty list if the table doesn't exist
+fn table_columns(conn: &Connection, table: &str) -> Result<Vec<(String, String)>> {
+    let mut stmt = conn.prepare("SELECT name, type FROM pragma_table_info(?1) ORDER BY cid")?;
+    let columns = stmt.query_map([table], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(columns)
+}
+
+/// Returns true if the table exists and has the column
+pub(crate) fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    Ok(table_columns(conn, table)?.iter().any(|(name, _)| name == column))
+}
+
+/// Prints every table and its columns for each database given, then whether each column
+/// this tool depends on is present. Meant for bug reports about schema versions.
+pub(crate) fn dump_schema(metadata_conn: Option<&Connection>, appdb_conn: Option<&Connection>) -> Result<()> {
+    let databases = [("metadata.db", metadata_conn, METADATA_DEPENDENCIES), ("app.db", appdb_conn, APPDB_DEPENDENCIES)];
+    for (db_name, conn, dependencies) in databases {
+        let Some(conn) = conn else { continue };
+
+        say!("\n📊 {} schema:", db_name);
+        let tables: Vec<String> = conn.prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name"
+        )?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+        for table in &tables {
+            say!("  {}", table);
+            for (column, column_type) in table_columns(conn, table)? {
+                let marker = if dependencies.contains(&(table.as_str(), column.as_str())) { "*" } else { " " };
+                say!("   {} {} {}", marker, column, column_type);
+            }
+        }
+
+        say!("\n🔍 {} columns used by this tool (marked * above):", db_name);
+        for (table, column) in dependencies {
+            if has_column(conn, table, column)? {
+                say!("   ✅ {}.{}", table, column);
+            } else {
+                say!("   ❌ {}.{} is missing", table, column);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A row that references a missing parent row, as reported by `PRAGMA foreign_key_check`
+#[derive(Debug, PartialEq)]
+pub(crate) struct ForeignKeyViolation {
+    pub(crate) table: String,
+    /// `None` for WITHOUT ROWID tables
+    pub(crate) rowid: Option<i64>,
+    pub(crate) parent: String,
+    /// The referencing column(s), comma-separated
+    pub(crate) columns: String,
+}
+
+/// Returns every foreign key violation in the database. Works whether or not foreign key
+/// enforcement is currently on, so it also catches rows written while it was turned off.
+pub(crate) fn foreign_key_violations(conn: &Connection) -> Result<Vec<ForeignKeyViolation>> {
+    let mut stmt = conn.prepare(
+        "SELECT c.\"table\", c.rowid, c.parent, group_concat(l.\"from\", ', ')
+         FROM pragma_foreign_key_check() c
+         LEFT JOIN pragma_foreign_key_list(c.\"table\") l ON l.id = c.fkid
+         GROUP BY c.\"table\", c.rowid, c.parent, c.fkid
+         ORDER BY c.\"table\", c.rowid"
+    )?;
+    let violations = stmt.query_map([], |row| {
+        Ok(ForeignKeyViolation {
+            table: row.get(0)?,
+            rowid: row.get(1)?,
+            parent: row.get(2)?,
+            columns: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+        })
+    })?
+    .collect::<Result<Vec<_>, _>>()?;
+    Ok(violations)
+}
+
+/// Runs `PRAGMA foreign_key_check` on each database given and lists every dangling
+/// reference. Returns the total number of violations.
+pub(crate) fn check_foreign_keys(metadata_conn: Option<&Connection>, appdb_conn: Option<&Connection>) -> Result<usize> {
+    let mut total = 0;
+    for (db_name, conn) in [("metadata.db", metadata_conn), ("app.db", appdb_conn)] {
+        let Some(conn) = conn else { continue };
+
+        let violations = foreign_key_violations(conn)
+            .with_context(|| format!("Failed to check the foreign keys of {}", db_name))?;
+        if violations.is_empty() {
+            say!("✅ {}: no foreign key violations", db_name);
+            continue;
+        }
+
+        say!("❌ {}: {} foreign key violation(s):", db_name, violations.len());
+        for violation in &violations {
+            let rowid = violation.rowid.map_or_else(|| "?".to_string(), |id| id.to_string());
+            say!("   - {} rowid {}: {} references a missing row in {}",
+                violation.table, rowid, violation.columns, violation.parent);
+        }
+        total += violations.len();
+    }
+    Ok(total)
 }
 
 /// Creates Calibre-specific custom SQL functions needed by the database triggers
@@ -102,4 +280,38 @@ mod tests {
         assert_eq!(title_sort_logic("Les Misérables"), "Misérables, Les");
         assert_eq!(title_sort_logic("L'Étranger"), "Étranger, L'");
     }
+
+    #[test]
+    fn test_read_only_connection_rejects_writes() {
+        let path = std::env::temp_dir().join(format!("cwh-read-only-{}.db", std::process::id()));
+        Connection::open(&path).unwrap().execute_batch("CREATE TABLE books (id INTEGER PRIMARY KEY);").unwrap();
+
+        let config = DatabaseConfig { read_only: true, ..Default::default() };
+        let conn = open_connection(&path, &config).unwrap();
+        assert_eq!(conn.query_row("SELECT COUNT(*) FROM books", [], |row| row.get::<_, i64>(0)).unwrap(), 0);
+        assert!(conn.execute("INSERT INTO books (id) VALUES (1)", []).is_err());
+
+        drop(conn);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_foreign_key_violations_found_with_enforcement_off() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "PRAGMA foreign_keys = OFF;
+             CREATE TABLE shelf (id INTEGER PRIMARY KEY);
+             CREATE TABLE book_shelf_link (id INTEGER PRIMARY KEY, shelf INTEGER REFERENCES shelf(id));
+             INSERT INTO shelf (id) VALUES (1);
+             INSERT INTO book_shelf_link (id, shelf) VALUES (1, 1), (2, 7);"
+        ).unwrap();
+
+        let violations = foreign_key_violations(&conn).unwrap();
+        assert_eq!(violations, vec![ForeignKeyViolation {
+            table: "book_shelf_link".to_string(),
+            rowid: Some(2),
+            parent: "shelf".to_string(),
+            columns: "shelf".to_string(),
+        }]);
+    }
 }
@@ -1,39 +1,148 @@
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
-use image::{ImageFormat, GenericImageView};
+use encoding_rs::{Encoding, WINDOWS_1252};
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, GenericImageView, ImageReader};
+use regex::Regex;
+use std::collections::HashMap;
 use std::fs;
-use std::io::Cursor;
+use std::io::{Cursor, Read, Seek};
 use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
 
-use crate::models::BookMetadata;
-use crate::utils::{get_valid_filename, detect_book_format};
+use crate::models::{BookFileOptions, BookMetadata, CoverOptions, MetadataOptions};
+use crate::utils::{get_valid_filename, detect_book_format, is_epub_format, isbn10_to_isbn13, normalize_text, parse_series_index, read_mapping_file, series_index_from_title, strip_isbn_separators};
+use crate::output::say;
 
 /// Maximum cover image size in bytes (200KB)
 const MAX_COVER_SIZE: u64 = 200 * 1024;
 
-/// Resizes a cover image if it exceeds the maximum size limit.
+/// Lowest JPEG quality tried before falling back to shrinking the image
+const MIN_COVER_QUALITY: u8 = 50;
+
+/// JPEG quality of covers scaled down by `dump-cover --width`
+const SCALED_COVER_QUALITY: u8 = 85;
+
+/// Matches a UTF-8 lead byte followed by a continuation byte as they appear once UTF-8 text
+/// has been decoded as Windows-1252/Latin-1 (e.g. "Ã©" for "é", "â€™" for "’")
+static MOJIBAKE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[\x{C2}-\x{EF}][\x{80}-\x{BF}\x{152}\x{153}\x{160}\x{161}\x{178}\x{17D}\x{17E}\x{192}\x{2C6}\x{2DC}\x{2013}-\x{203A}\x{20AC}\x{2122}]").expect("invalid regex"));
+
+/// Matches the first image reference on an XHTML cover page: `<img src>` or SVG `<image href>`
+static COVER_PAGE_IMAGE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(?is)<(?:img|image)\b[^>]*?\s(?:src|xlink:href|href)\s*=\s*["']([^"']+)["']"#).expect("invalid regex"));
+
+/// Matches the algorithm of each `<EncryptionMethod>` in `META-INF/encryption.xml`
+static ENCRYPTION_ALGORITHM_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(?i)<(?:\w+:)?EncryptionMethod\b[^>]*\bAlgorithm\s*=\s*["']([^"']+)["']"#).expect("invalid regex"));
+
+/// Font obfuscation algorithms (IDPF and Adobe), which also use `encryption.xml` but don't stop a book from opening
+const FONT_OBFUSCATION_ALGORITHMS: &[&str] = &["http://www.idpf.org/2008/embedding", "http://ns.adobe.com/pdf/enc#RC"];
+
+/// Reads only the image header and rejects covers whose pixel count exceeds the
+/// configured limit, so a huge image can't exhaust memory when decoded.
+/// Images whose dimensions can't be read are allowed through; decoding them will fail safely.
+fn check_cover_dimensions(cover_data: &[u8], options: &CoverOptions) -> Result<()> {
+    if let Some((width, height)) = read_cover_dimensions(cover_data) {
+        let pixels = width as u64 * height as u64;
+        if pixels > options.max_pixels {
+            anyhow::bail!(
+                "cover image is {}x{} ({} pixels), exceeding the limit of {} pixels",
+                width, height, pixels, options.max_pixels
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the width and height from the image header without decoding the pixels.
+fn read_cover_dimensions(cover_data: &[u8]) -> Option<(u32, u32)> {
+    ImageReader::new(Cursor::new(cover_data))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok())
+}
+
+/// Returns the largest size with the same aspect ratio that fits within the optional
+/// maximum width and height. Images that already fit are returned unchanged.
+fn fit_cover_dimensions(width: u32, height: u32, max_width: Option<u32>, max_height: Option<u32>) -> (u32, u32) {
+    let width_scale = max_width.map_or(1.0, |max| max as f64 / width as f64);
+    let height_scale = max_height.map_or(1.0, |max| max as f64 / height as f64);
+    let scale = width_scale.min(height_scale);
+    if scale >= 1.0 {
+        return (width, height);
+    }
+    let fitted_width = ((width as f64 * scale) as u32).clamp(1, max_width.unwrap_or(u32::MAX));
+    let fitted_height = ((height as f64 * scale) as u32).clamp(1, max_height.unwrap_or(u32::MAX));
+    (fitted_width, fitted_height)
+}
+
+/// Encodes an image as JPEG at the given quality (1-100).
+fn encode_jpeg(img: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut encoder = JpegEncoder::new_with_quality(&mut output, quality);
+    encoder.encode_image(&img.to_rgb8())
+        .context("Failed to encode cover image as JPEG")?;
+    Ok(output)
+}
+
+/// Resizes a cover image if it exceeds the maximum size limit or the optional dimension limits.
+/// Downscales to fit the dimension limits first, then lowers JPEG quality to keep that
+/// resolution, and only then scales dimensions further.
 /// Returns the resized image data or the original data if already small enough.
-fn resize_cover_if_needed(cover_data: &[u8]) -> Result<Vec<u8>> {
+fn resize_cover_if_needed(cover_data: &[u8], options: &CoverOptions) -> Result<Vec<u8>> {
+    let exceeds_dimensions = read_cover_dimensions(cover_data).is_some_and(|(width, height)| {
+        fit_cover_dimensions(width, height, options.max_width, options.max_height) != (width, height)
+    });
+
     // If the image is already small enough, return it as-is
-    if cover_data.len() as u64 <= MAX_COVER_SIZE {
+    if cover_data.len() as u64 <= MAX_COVER_SIZE && !exceeds_dimensions {
         return Ok(cover_data.to_vec());
     }
     
-    println!(" -> Cover image is {}KB, resizing to fit ~200KB limit...", cover_data.len() / 1024);
+    if cover_data.len() as u64 > MAX_COVER_SIZE {
+        say!(" -> Cover image is {}KB, resizing to fit ~200KB limit...", cover_data.len() / 1024);
+    }
     
     // Load the image
-    let img = image::load_from_memory(cover_data)
+    let mut img = image::load_from_memory(cover_data)
         .context("Failed to load cover image for resizing")?;
+    let (original_width, original_height) = img.dimensions();
+
+    // Fit within the dimension limits before looking at the byte size
+    let (fitted_width, fitted_height) = fit_cover_dimensions(original_width, original_height, options.max_width, options.max_height);
+    if (fitted_width, fitted_height) != (original_width, original_height) {
+        say!(" -> Cover image is {}x{}, downscaling to {}x{} to fit the dimension limit",
+                 original_width, original_height, fitted_width, fitted_height);
+        img = img.resize_exact(fitted_width, fitted_height, image::imageops::FilterType::Lanczos3);
+    }
+    let (base_width, base_height) = img.dimensions();
+
+    // Step down the JPEG quality at full resolution first
+    let mut quality = options.quality;
+    loop {
+        let output = encode_jpeg(&img, quality)?;
+        if output.len() as u64 <= MAX_COVER_SIZE {
+            say!(" -> Re-encoded cover from {}KB to {}KB at quality {} ({}x{})",
+                     cover_data.len() / 1024,
+                     output.len() / 1024,
+                     quality,
+                     base_width,
+                     base_height);
+            return Ok(output);
+        }
+        if quality <= MIN_COVER_QUALITY {
+            break;
+        }
+        quality = quality.saturating_sub(10).max(MIN_COVER_QUALITY);
+    }
     
     // Calculate new dimensions to reduce file size
     // Start with 80% of original dimensions and adjust if needed
-    let (original_width, original_height) = img.dimensions();
     let mut scale_factor = 0.8;
     
     // Try different scale factors until we get under the size limit
     for _attempt in 0..5 {
-        let new_width = ((original_width as f64) * scale_factor) as u32;
-        let new_height = ((original_height as f64) * scale_factor) as u32;
+        let new_width = ((base_width as f64) * scale_factor) as u32;
+        let new_height = ((base_height as f64) * scale_factor) as u32;
         
         // Ensure minimum dimensions
         if new_width < 200 || new_height < 200 {
@@ -41,17 +150,12 @@ fn resize_cover_if_needed(cover_data: &[u8]) -> Result<Vec<u8>> {
         }
         
         let resized = img.resize(new_width, new_height, image::imageops::FilterType::Lanczos3);
-        
-        // Encode as JPEG with high quality
-        let mut output = Vec::new();
-        let mut cursor = Cursor::new(&mut output);
-        
-        resized.write_to(&mut cursor, ImageFormat::Jpeg)
+        let output = encode_jpeg(&resized, quality)
             .context("Failed to encode resized cover image")?;
         
         // Check if the resized image meets our size requirement
         if output.len() as u64 <= MAX_COVER_SIZE {
-            println!(" -> Resized cover from {}KB to {}KB ({}x{} -> {}x{})", 
+            say!(" -> Resized cover from {}KB to {}KB ({}x{} -> {}x{})", 
                      cover_data.len() / 1024, 
                      output.len() / 1024,
                      original_width, 
@@ -65,114 +169,341 @@ fn resize_cover_if_needed(cover_data: &[u8]) -> Result<Vec<u8>> {
         scale_factor *= 0.85;
     }
     
-    // If we couldn't get it small enough, return the best attempt
-    let final_width = ((original_width as f64) * scale_factor) as u32;
-    let final_height = ((original_height as f64) * scale_factor) as u32;
+    // If we couldn't get it small enough, return the best attempt.
+    // Never grow past the size that fits the dimension limits.
+    let final_width = (((base_width as f64) * scale_factor) as u32).max(200).min(base_width);
+    let final_height = (((base_height as f64) * scale_factor) as u32).max(200).min(base_height);
     
     let resized = img.resize(
-        final_width.max(200), 
-        final_height.max(200), 
+        final_width, 
+        final_height, 
         image::imageops::FilterType::Lanczos3
     );
     
-    let mut output = Vec::new();
-    let mut cursor = Cursor::new(&mut output);
-    
-    resized.write_to(&mut cursor, ImageFormat::Jpeg)
+    let output = encode_jpeg(&resized, quality)
         .context("Failed to encode final resized cover image")?;
     
-    println!(" -> Resized cover from {}KB to {}KB ({}x{} -> {}x{})", 
+    say!(" -> Resized cover from {}KB to {}KB ({}x{} -> {}x{})", 
              cover_data.len() / 1024, 
              output.len() / 1024,
              original_width, 
              original_height,
-             final_width.max(200), 
-             final_height.max(200));
+             resized.width(), 
+             resized.height());
     
     Ok(output)
 }
 
+/// Returns the EPUB's cover image. When the cover reference points at an XHTML cover page
+/// rather than an image, the first image on that page is used instead.
+fn get_epub_cover<R: Read + Seek>(doc: &mut epub::doc::EpubDoc<R>) -> Option<(Vec<u8>, String)> {
+    let cover_id = doc.get_cover_id()?;
+    let (data, mime) = doc.get_resource(&cover_id)?;
+    if mime != "application/xhtml+xml" && mime != "text/html" {
+        return Some((data, mime));
+    }
+
+    let page_path = doc.resources.get(&cover_id)?.path.clone();
+    let page = String::from_utf8_lossy(&data);
+    let src = COVER_PAGE_IMAGE_RE.captures(&page)?.get(1)?.as_str();
+    let image_path = resolve_epub_href(&page_path, src);
+    let image_data = doc.get_resource_by_path(&image_path)?;
+    let image_mime = doc.get_resource_mime_by_path(&image_path).unwrap_or_default();
+    say!(" -> Cover reference is a cover page; using its image {}", image_path.display());
+    Some((image_data, image_mime))
+}
+
+/// Looks for the marks DRM schemes leave in an EPUB: Adobe's `rights.xml`, Apple's `sinf.xml`,
+/// or an `encryption.xml` that encrypts more than fonts. Returns which one was found.
+fn detect_drm<R: Read + Seek>(doc: &mut epub::doc::EpubDoc<R>) -> Option<&'static str> {
+    if doc.get_resource_by_path("META-INF/rights.xml").is_some() {
+        return Some("META-INF/rights.xml (Adobe DRM)");
+    }
+    if doc.get_resource_by_path("META-INF/sinf.xml").is_some() {
+        return Some("META-INF/sinf.xml (Apple FairPlay)");
+    }
+    let encryption = doc.get_resource_str_by_path("META-INF/encryption.xml")?;
+    ENCRYPTION_ALGORITHM_RE.captures_iter(&encryption)
+        .any(|caps| !FONT_OBFUSCATION_ALGORITHMS.contains(&caps[1].trim()))
+        .then_some("META-INF/encryption.xml encrypts the book's content")
+}
+
+/// Number of spine documents searched for Kobo spans. Kobo's converter wraps the text of every
+/// content document in them, but cover and title pages may have no text.
+const KEPUB_SNIFF_DOCUMENTS: usize = 5;
+
+/// Returns true if the EPUB's content has the `koboSpan` markup that Kobo's KEPUB conversion
+/// adds, whatever the file is named.
+fn has_kobo_spans<R: Read + Seek>(doc: &mut epub::doc::EpubDoc<R>) -> bool {
+    let ids: Vec<String> = doc.spine.iter().take(KEPUB_SNIFF_DOCUMENTS).map(|item| item.idref.clone()).collect();
+    ids.iter().any(|id| doc.get_resource_str(id)
+        .is_some_and(|(content, _)| content.contains("koboSpan") || content.contains("id=\"kobo.")))
+}
+
+/// Resolves an href found in the EPUB document at `base` to a path inside the archive
+fn resolve_epub_href(base: &Path, href: &str) -> PathBuf {
+    let href = href.split(['#', '?']).next().unwrap_or_default();
+    let mut resolved = base.parent().map(Path::to_path_buf).unwrap_or_default();
+    for component in href.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                resolved.pop();
+            }
+            name => resolved.push(name),
+        }
+    }
+    resolved
+}
+
+/// Reads metadata for any supported book file. EPUB/KEPUB files are parsed fully;
+/// other formats fall back to metadata derived from the filename.
+pub(crate) fn get_book_metadata(path: &Path, options: &MetadataOptions) -> Result<BookMetadata> {
+    let (format, _extension) = detect_book_format(path)?;
+    if is_epub_format(format) {
+        get_epub_metadata(path, options)
+    } else {
+        get_filename_metadata(path, options.series_regex.as_ref())
+    }
+}
+
+/// Series information read from a file name with `--series-regex`
+#[derive(Debug, PartialEq)]
+struct FilenameSeries {
+    series: String,
+    index: Option<f64>,
+    title: Option<String>,
+}
+
+/// Matches the file stem against `--series-regex`. Returns `None` if it doesn't match or the
+/// `series` group is empty.
+fn series_from_filename(path: &Path, series_regex: &Regex) -> Option<FilenameSeries> {
+    let stem = path.file_stem()?.to_string_lossy();
+    let captures = series_regex.captures(&stem)?;
+    let group = |name| captures.name(name).map(|m| m.as_str().trim()).filter(|value| !value.is_empty());
+    Some(FilenameSeries {
+        series: group("series")?.to_string(),
+        index: group("index").and_then(parse_series_index),
+        title: group("title").map(str::to_string),
+    })
+}
+
+/// Derives a title from the file stem, for books without usable title metadata.
+fn title_from_filename(path: &Path) -> Result<String> {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().trim().to_string())
+        .filter(|stem| !stem.is_empty())
+        .with_context(|| format!("Cannot derive a title from filename {:?}", path))
+}
+
+/// Builds minimal metadata for formats we can't parse: the title is the file stem
+/// and the author is "Unknown". A matching `--series-regex` supplies the series and title.
+fn get_filename_metadata(path: &Path, series_regex: Option<&Regex>) -> Result<BookMetadata> {
+    let filename_series = series_regex.and_then(|re| series_from_filename(path, re));
+    let title = match filename_series.as_ref().and_then(|fs| fs.title.clone()) {
+        Some(title) => title,
+        None => title_from_filename(path)?,
+    };
+
+    let file_size = fs::metadata(path)
+        .with_context(|| format!("Failed to get file size for {:?}", path))?
+        .len();
+
+    Ok(BookMetadata {
+        title,
+        author: "Unknown".to_string(),
+        path: path.to_path_buf(),
+        description: None,
+        languages: Vec::new(),
+        unmapped_languages: Vec::new(),
+        tags: Vec::new(),
+        isbn: None,
+        isbn10: None,
+        rights: None,
+        subtitle: None,
+        series_index: filename_series.as_ref().and_then(|fs| fs.index),
+        series: filename_series.map(|fs| fs.series),
+        publisher: None,
+        pubdate: None,
+        modified: None,
+        file_size,
+        sniffed_format: None,
+    })
+}
+
+/// Loads custom language mappings for `--lang-map-file`: either a JSON object or lines of
+/// `source<TAB>iso639_2`, with `#` comments. Sources are matched case-insensitively.
+pub(crate) fn load_language_map(path: &Path) -> Result<HashMap<String, String>> {
+    let mut map = HashMap::new();
+    for (source, target) in read_mapping_file(path, "language map")? {
+        let target = target.trim().to_lowercase();
+        if target.len() != 3 || !target.chars().all(|c| c.is_ascii_lowercase()) {
+            anyhow::bail!("Invalid ISO 639-2 code '{}' for '{}' in language map {:?}", target, source, path);
+        }
+        map.insert(source.trim().to_lowercase(), target);
+    }
+    Ok(map)
+}
+
+/// Normalizes a language tag (e.g. "en-US", "fr", "deu") to an ISO 639-2 code.
+/// Custom mappings from `language_map` are tried first, for the full tag and then its
+/// base language. Unknown codes are mapped to "und".
+pub(crate) fn normalize_language_code(lang: &str, language_map: &HashMap<String, String>) -> String {
+    let lang = lang.trim().to_lowercase();
+
+    // Split on hyphens to handle extended tags (e.g., "en-US" -> "en")
+    let base_lang = lang.split(['-', '_']).next().unwrap_or(&lang);
+
+    if let Some(code) = language_map.get(&lang).or_else(|| language_map.get(base_lang)) {
+        return code.clone();
+    }
+
+    // Helper closure to normalize language codes
+    let normalize_language = |code: &str| -> String {
+        match code {
+            // Common ISO 639-1 to ISO 639-2 mappings (using terminological codes)
+            "en" => "eng".to_string(),
+            "fr" => "fra".to_string(),  // French: fra (not fre)
+            "es" => "spa".to_string(),
+            "de" => "deu".to_string(),  // German: deu (not ger)
+            "it" => "ita".to_string(),
+            "ja" => "jpn".to_string(),
+            "zh" => "zho".to_string(),  // Chinese: zho (not chi)
+            "ru" => "rus".to_string(),
+            "ar" => "ara".to_string(),
+            "hi" => "hin".to_string(),
+            "pt" => "por".to_string(),
+            "nl" => "nld".to_string(),  // Dutch: nld (not dut)
+            "pl" => "pol".to_string(),
+            "ko" => "kor".to_string(),
+            // Add more mappings as needed
+            _ => code.to_string(),
+        }
+    };
+
+    // Normalize the language code
+    let normalized = if base_lang.len() == 2 {
+        normalize_language(base_lang)
+    } else if base_lang.len() == 3 {
+        // Assume it's already ISO 639-2
+        base_lang.to_string()
+    } else {
+        // Unknown format, keep as is
+        base_lang.to_string()
+    };
+
+    // Verify it's a known ISO 639-2 code and convert unknown codes to "und"
+    match normalized.as_str() {
+        "eng" | "fra" | "deu" | "spa" | "ita" | "jpn" | "zho" | "rus" | "ara" |
+        "hin" | "por" | "ben" | "urd" | "nld" | "tur" | "vie" | "tel" | "mar" |
+        "tam" | "kor" | "fas" | "tha" | "pol" | "ukr" |
+        "ron" | "mal" | "hun" | "ces" | "ell" | "swe" | "bul" | "dan" | "fin" |
+        "nor" | "slk" | "cat" | "hrv" | "heb" | "lit" | "slv" | "est" |
+        "lav" | "fil" | "mkd" | "gle" | "hye" | "lat" | "cym" |
+        "eus" | "kat" | "aze" | "swa" | "afr" | "glg" | "alb" | "bel" | "kan" |
+        "yue" | "cmn" => normalized,
+        _ => "und".to_string()
+    }
+}
+
 /// Extracts full metadata from the EPUB file.
-pub(crate) fn get_epub_metadata(path: &Path) -> Result<BookMetadata> {
-    let doc = epub::doc::EpubDoc::new(path)?;
-    let title = doc
-        .mdata("title")
-        .context("EPUB has no title metadata")?;
-    let author = doc
-        .mdata("creator")
-        .context("EPUB has no author (creator) metadata")?;
+/// A missing or blank title falls back to the filename and a missing or blank author to
+/// "Unknown", unless `strict` is set, in which case both are errors.
+/// `options.source_encoding` forces re-decoding of text fields from that legacy encoding;
+/// without it only strings that look misdecoded are repaired, assuming Windows-1252.
+pub(crate) fn get_epub_metadata(path: &Path, options: &MetadataOptions) -> Result<BookMetadata> {
+    let MetadataOptions { source_encoding, strict, language_map, isbn13, keep_isbn10, sniff_format, series_regex } = options;
+    let mut doc = epub::doc::EpubDoc::new(path)?;
+    let sniffed_format = if *sniff_format {
+        let (named_format, _) = detect_book_format(path)?;
+        let content_format = if has_kobo_spans(&mut doc) { ("KEPUB", ".kepub") } else { ("EPUB", ".epub") };
+        (content_format.0 != named_format).then(|| {
+            say!("⚠️  {} is named as {} but its content is {}; storing it as {}.",
+                path.display(), named_format, content_format.0, content_format.0);
+            content_format
+        })
+    } else {
+        None
+    };
+    let filename_series = match series_regex {
+        Some(re) if doc.mdata("calibre:series").is_none() => series_from_filename(path, re),
+        _ => None,
+    };
+    let title = match doc.mdata("title").map(|t| t.value.trim().to_string()).filter(|t| !t.is_empty()) {
+        Some(title) => title,
+        None if *strict => anyhow::bail!("EPUB has no title metadata"),
+        None => {
+            let title = match filename_series.as_ref().and_then(|fs| fs.title.clone()) {
+                Some(title) => title,
+                None => title_from_filename(path)?,
+            };
+            say!("⚠️  EPUB has no title metadata; using the filename '{}'.", title);
+            title
+        }
+    };
+    let author = match doc.mdata("creator").map(|a| a.value.trim().to_string()).filter(|a| !a.is_empty()) {
+        Some(author) => author,
+        None if *strict => anyhow::bail!("EPUB has no author (creator) metadata"),
+        None => {
+            say!("⚠️  EPUB has no author (creator) metadata; using 'Unknown'.");
+            "Unknown".to_string()
+        }
+    };
+    // DRM-protected books import fine but won't open in Calibre-Web or on the device
+    if let Some(evidence) = detect_drm(&mut doc) {
+        if *strict {
+            anyhow::bail!("this EPUB appears to be DRM-protected ({})", evidence);
+        }
+        say!("⚠️  WARNING: this EPUB appears to be DRM-protected ({}); it will import but probably won't open in Calibre-Web or on your device.", evidence);
+    }
+
     let description = doc.mdata("description");
     let rights = doc.mdata("rights");
+    let mut tags: Vec<String> = Vec::new();
+    for subject in doc.metadata.iter().filter(|m| m.property == "subject") {
+        let tag = normalize_text(&subject.value);
+        if !tag.is_empty() && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
     let subtitle = doc.mdata("subtitle");
 
-    // Handle language codes with proper normalization
-    let language = doc.mdata("language").map(|lang| {
-        let lang = lang.value.trim().to_lowercase();
-        
-        // Helper closure to normalize language codes
-        let normalize_language = |code: &str| -> String {
-            match code {
-                // Common ISO 639-1 to ISO 639-2 mappings (using terminological codes)
-                "en" => "eng".to_string(),
-                "fr" => "fra".to_string(),  // French: fra (not fre)
-                "es" => "spa".to_string(),
-                "de" => "deu".to_string(),  // German: deu (not ger)
-                "it" => "ita".to_string(),
-                "ja" => "jpn".to_string(),
-                "zh" => "zho".to_string(),  // Chinese: zho (not chi)
-                "ru" => "rus".to_string(),
-                "ar" => "ara".to_string(),
-                "hi" => "hin".to_string(),
-                "pt" => "por".to_string(),
-                "nl" => "nld".to_string(),  // Dutch: nld (not dut)
-                "pl" => "pol".to_string(),
-                "ko" => "kor".to_string(),
-                // Add more mappings as needed
-                _ => code.to_string(),
-            }
-        };
-
-        // Split on hyphens to handle extended tags (e.g., "en-US" -> "en")
-        let base_lang = lang.split(['-', '_']).next().unwrap_or(&lang);
-
-        // Normalize the language code
-        let normalized = if base_lang.len() == 2 {
-            normalize_language(base_lang)
-        } else if base_lang.len() == 3 {
-            // Assume it's already ISO 639-2
-            base_lang.to_string()
-        } else {
-            // Unknown format, keep as is
-            base_lang.to_string()
-        };
-
-        // Verify it's a known ISO 639-2 code and convert unknown codes to "und"
-        match normalized.as_str() {
-            "eng" | "fra" | "deu" | "spa" | "ita" | "jpn" | "zho" | "rus" | "ara" |
-            "hin" | "por" | "ben" | "urd" | "nld" | "tur" | "vie" | "tel" | "mar" |
-            "tam" | "kor" | "fas" | "tha" | "pol" | "ukr" |
-            "ron" | "mal" | "hun" | "ces" | "ell" | "swe" | "bul" | "dan" | "fin" |
-            "nor" | "slk" | "cat" | "hrv" | "heb" | "lit" | "slv" | "est" |
-            "lav" | "fil" | "mkd" | "gle" | "hye" | "lat" | "cym" |
-            "eus" | "kat" | "aze" | "swa" | "afr" | "glg" | "alb" | "bel" | "kan" |
-            "yue" | "cmn" => normalized,
-            _ => "und".to_string()
+    // Handle language codes with proper normalization; translations often list several
+    let mut languages: Vec<String> = Vec::new();
+    let mut unmapped_languages: Vec<String> = Vec::new();
+    for entry in doc.metadata.iter().filter(|m| m.property == "language") {
+        let code = normalize_language_code(&entry.value, language_map);
+        let source = entry.value.trim();
+        if code == "und" && !source.eq_ignore_ascii_case("und") && !unmapped_languages.iter().any(|s| s == source) {
+            unmapped_languages.push(source.to_string());
         }
-    });
+        if !languages.contains(&code) {
+            languages.push(code);
+        }
+    }
 
     let isbn = doc.metadata.iter()
         .filter(|m| m.property == "identifier")
         .find_map(|id| {
             let id = id.value.trim();
             if id.starts_with("urn:isbn:") {
-                return Some(id.trim_start_matches("urn:isbn:").to_string());
+                return Some(strip_isbn_separators(id.trim_start_matches("urn:isbn:")).to_uppercase());
             }
-            let digits: String = id.chars().filter(|c| c.is_ascii_digit()).collect();
-            if digits.len() == 10 || digits.len() == 13 {
+            // ISBN-10 check digits can be X
+            let digits: String = id.chars().filter(|c| c.is_ascii_digit() || *c == 'X' || *c == 'x').collect::<String>().to_uppercase();
+            let x_position = digits.find('X');
+            if (digits.len() == 10 && x_position.is_none_or(|pos| pos == 9)) || (digits.len() == 13 && x_position.is_none()) {
                 return Some(digits);
             }
             None
         });
+    let (isbn, isbn10) = match isbn {
+        Some(isbn) if *isbn13 => match isbn10_to_isbn13(&isbn) {
+            Some(converted) => (Some(converted), keep_isbn10.then_some(isbn)),
+            None => (Some(isbn), None),
+        },
+        isbn => (isbn, None),
+    };
 
     // Get publisher
     let publisher = doc.mdata("publisher");
@@ -225,14 +556,20 @@ pub(crate) fn get_epub_metadata(path: &Path) -> Result<BookMetadata> {
             None
         });
 
+    // EPUB3 records the content modification time as an RFC 3339 `dcterms:modified` meta
+    let modified = doc.mdata("dcterms:modified")
+        .and_then(|m| DateTime::parse_from_rfc3339(m.value.trim()).ok())
+        .map(|dt| dt.with_timezone(&Utc));
+
     // Extract series information from metadata
     // Look for calibre:series and calibre:series_index first
     let series = doc.mdata("calibre:series")
         .map(|s| s.value.clone())
+        .or_else(|| filename_series.as_ref().map(|fs| fs.series.clone()))
         .or_else(|| {
             // Fallback to looking for series information in the title
             // Common format: Series Name #X - Book Title
-            let title_str = title.value.trim();
+            let title_str = title.as_str();
             if let Some(hash_idx) = title_str.find('#') {
                 if let Some(_dash_idx) = title_str[hash_idx..].find('-') {
                     // Extract everything before the # as the series name
@@ -251,50 +588,300 @@ pub(crate) fn get_epub_metadata(path: &Path) -> Result<BookMetadata> {
         });
 
     let series_index = doc.mdata("calibre:series_index")
-        .and_then(|idx| idx.value.parse::<f64>().ok())
-        .or_else(|| {
-            // Try to extract series index from title if in #X format
-            title.value.find('#')
-                .and_then(|i| {
-                    let rest = &title.value[i + 1..];
-                    let num_str: String = rest.chars()
-                        .take_while(|c| c.is_ascii_digit() || *c == '.')
-                        .collect();
-                    num_str.parse::<f64>().ok()
-                })
-        });
+        .and_then(|idx| parse_series_index(&idx.value))
+        .or_else(|| filename_series.as_ref().and_then(|fs| fs.index))
+        .or_else(|| series_index_from_title(&title));
 
     // Get the file size
     let file_size = fs::metadata(path)
         .with_context(|| format!("Failed to get file size for {:?}", path))?
         .len();
 
-    Ok(BookMetadata {
-        title: title.value.clone(),
-        author: author.value.clone(),
+    let mut metadata = BookMetadata {
+        title,
+        author,
         path: path.to_path_buf(),
         description: description.map(|d| d.value.clone()),
-        language,
+        languages,
+        unmapped_languages,
+        tags,
         isbn,
+        isbn10,
         rights: rights.map(|r| r.value.clone()),
         subtitle: subtitle.map(|s| s.value.clone()),
         series,
         series_index,
         publisher: publisher.map(|p| p.value.clone()),
         pubdate,
+        modified,
         file_size,
-    })
+        sniffed_format,
+    };
+
+    // Replacement characters can only be recovered from the undecoded OPF, so only read it when needed
+    let has_replacement_chars = [Some(&metadata.title), Some(&metadata.author), metadata.description.as_ref(),
+        metadata.rights.as_ref(), metadata.subtitle.as_ref(), metadata.series.as_ref(), metadata.publisher.as_ref()]
+        .into_iter()
+        .flatten()
+        .any(|value| value.contains('\u{FFFD}'));
+    let encoding = source_encoding.unwrap_or(WINDOWS_1252);
+    let raw_opf = if has_replacement_chars {
+        let root_file = doc.root_file.clone();
+        doc.get_resource_by_path(&root_file)
+            .map(|bytes| encoding.decode_without_bom_handling(&bytes).0.into_owned())
+    } else {
+        None
+    };
+
+    let forced = source_encoding.is_some();
+    let repair = |value: &mut String, field: OpfField| {
+        let repaired = repair_encoding(value, encoding, forced, raw_opf.as_deref().map(|opf| (opf, field)));
+        if repaired != *value {
+            say!(" -> Repaired text encoding: {:?} -> {:?}", value, repaired);
+            *value = repaired;
+        }
+    };
+    repair(&mut metadata.title, OpfField::Element("title"));
+    repair(&mut metadata.author, OpfField::Element("creator"));
+    for (value, field) in [
+        (&mut metadata.description, OpfField::Element("description")),
+        (&mut metadata.rights, OpfField::Element("rights")),
+        (&mut metadata.subtitle, OpfField::Element("title")),
+        (&mut metadata.series, OpfField::Meta("calibre:series")),
+        (&mut metadata.publisher, OpfField::Element("publisher")),
+    ] {
+        if let Some(value) = value {
+            repair(value, field);
+        }
+    }
+
+    // Stray and doubled whitespace would break the existing-book lookup and the library path
+    metadata.title = normalize_text(&metadata.title);
+    metadata.author = normalize_text(&metadata.author);
+    for value in [&mut metadata.series, &mut metadata.publisher].into_iter().flatten() {
+        *value = normalize_text(value);
+    }
+
+    Ok(metadata)
 }
 
-/// Copies or updates the EPUB file in the Calibre library structure.
+/// Where a metadata value is stored in the OPF, for recovering it from the raw file
+#[derive(Debug, Clone, Copy)]
+enum OpfField {
+    /// The text of a metadata element, by local name (`title` for `<dc:title>`)
+    Element(&'static str),
+    /// The `content` of a `<meta name="...">`
+    Meta(&'static str),
+}
+
+/// Repairs text that was decoded with the wrong character encoding.
+/// Mojibake (UTF-8 read as `encoding`) is reversed by re-encoding and decoding as UTF-8;
+/// replacement characters are recovered from the value's field in the raw OPF decoded with
+/// `encoding`. When `forced` is false only strings that look misdecoded are touched. The original
+/// string is returned if re-decoding doesn't produce clean text.
+fn repair_encoding(value: &str, encoding: &'static Encoding, forced: bool, raw_opf: Option<(&str, OpfField)>) -> String {
+    if value.contains('\u{FFFD}') {
+        return raw_opf
+            .and_then(|(opf, field)| recover_from_opf(value, field, opf))
+            .unwrap_or_else(|| value.to_string());
+    }
+
+    if value.is_ascii() || !(forced || MOJIBAKE_RE.is_match(value)) {
+        return value.to_string();
+    }
+
+    let (bytes, _, had_errors) = encoding.encode(value);
+    if !had_errors
+        && let Ok(decoded) = std::str::from_utf8(&bytes)
+        && !decoded.chars().any(|c| c.is_control() && !c.is_whitespace()) {
+            return decoded.to_string();
+        }
+    value.to_string()
+}
+
+/// Finds `field` in the raw OPF with a value matching `value`, with each replacement character
+/// standing in for the few characters the undecodable bytes map to. A value made only of
+/// replacement characters has nothing to match on and isn't recovered.
+fn recover_from_opf(value: &str, field: OpfField, opf: &str) -> Option<String> {
+    if value.chars().all(|c| c == '\u{FFFD}' || c.is_whitespace()) {
+        return None;
+    }
+    let value_pattern = value
+        .split('\u{FFFD}')
+        .map(regex::escape)
+        .collect::<Vec<_>>()
+        .join("[^<\"\u{FFFD}]{1,4}?");
+    let pattern = match field {
+        OpfField::Element(name) => format!(r"<(?:[\w.-]+:)?{}\b[^>]*>\s*({})\s*</", name, value_pattern),
+        OpfField::Meta(name) => {
+            let name = regex::escape(name);
+            format!(r#"<(?:[\w.-]+:)?meta\b[^>]*?(?:name="{name}"[^>]*?content="({value})"|content="({value})"[^>]*?name="{name}")"#,
+                name = name, value = value_pattern)
+        }
+    };
+    let re = Regex::new(&pattern).ok()?;
+    let captures = re.captures(opf)?;
+    captures.get(1).or_else(|| captures.get(2)).map(|m| m.as_str().to_string())
+}
+
+/// Checks that an EPUB opens and that every item in its reading order is present in the archive.
+pub(crate) fn validate_epub(path: &Path) -> Result<()> {
+    let mut doc = epub::doc::EpubDoc::new(path)
+        .with_context(|| format!("{} is not a readable EPUB", path.display()))?;
+    if doc.spine.is_empty() {
+        anyhow::bail!("the EPUB has no reading order (empty spine)");
+    }
+    let idrefs: Vec<String> = doc.spine.iter().map(|item| item.idref.clone()).collect();
+    for idref in idrefs {
+        if doc.get_resource(&idref).is_none() {
+            anyhow::bail!("spine item '{}' is missing from the archive", idref);
+        }
+    }
+    Ok(())
+}
+
+/// Decodes the cover a book would be imported with, the same way `update_book_files` finds it,
+/// without writing anything. Returns a short description of the cover, or `None` if there is none.
+pub(crate) fn trial_decode_cover(path: &Path, options: &CoverOptions) -> Result<Option<String>> {
+    if !is_epub_format(detect_book_format(path)?.0) {
+        return Ok(None);
+    }
+    let mut doc = epub::doc::EpubDoc::new(path)
+        .with_context(|| format!("{} is not a readable EPUB", path.display()))?;
+    let cover_data = match get_epub_cover(&mut doc) {
+        Some((cover_data, _mime)) => cover_data,
+        None => {
+            let cover_src = path.parent().map(|p| p.join("cover.jpg")).unwrap_or_else(|| PathBuf::from("cover.jpg"));
+            if !cover_src.exists() {
+                return Ok(None);
+            }
+            fs::read(&cover_src)
+                .with_context(|| format!("Failed to read external cover from {:?}", cover_src))?
+        }
+    };
+
+    let description = match prepare_cover(&cover_data, options)? {
+        PreparedCover::Skip(reason) => format!("{}; it would be skipped", reason),
+        PreparedCover::Store(final_cover_data) => match read_cover_dimensions(&final_cover_data) {
+            Some((width, height)) => format!("cover {}x{}", width, height),
+            None => "cover stored as-is".to_string(),
+        },
+    };
+    Ok(Some(description))
+}
+
+/// Scales a cover image down to the given width, keeping its aspect ratio, and re-encodes it
+/// as JPEG. Covers already that narrow are returned unchanged.
+pub(crate) fn scale_cover_to_width(cover_data: &[u8], width: u32) -> Result<Vec<u8>> {
+    let img = image::load_from_memory(cover_data).context("cover image is corrupt")?;
+    let (original_width, original_height) = img.dimensions();
+    let (scaled_width, scaled_height) = fit_cover_dimensions(original_width, original_height, Some(width), None);
+    if scaled_width == original_width {
+        return Ok(cover_data.to_vec());
+    }
+    let resized = img.resize_exact(scaled_width, scaled_height, image::imageops::FilterType::Lanczos3);
+    encode_jpeg(&resized, SCALED_COVER_QUALITY)
+}
+
+/// What a cover image turns into on import
+enum PreparedCover {
+    /// The cover data to write, resized if it was too large
+    Store(Vec<u8>),
+    /// The cover is over the pixel limit and is left out
+    Skip(anyhow::Error),
+}
+
+/// Checks and resizes a cover image the way it's imported, without writing anything.
+/// Returns an error if the cover is unreadable.
+fn prepare_cover(cover_data: &[u8], options: &CoverOptions) -> Result<PreparedCover> {
+    let reader = ImageReader::new(Cursor::new(cover_data))
+        .with_guessed_format()
+        .context("Failed to read cover image")?;
+    let decodable = match reader.format() {
+        None => anyhow::bail!("cover image is not in a recognized image format"),
+        Some(format) if format.reading_enabled() => {
+            reader.into_dimensions().context("cover image is corrupt")?;
+            true
+        }
+        Some(_) => false,
+    };
+
+    if let Err(e) = check_cover_dimensions(cover_data, options) {
+        return Ok(PreparedCover::Skip(e));
+    }
+
+    // Formats without a decoder built in (PNG, GIF, WebP) can't be resized, so they are stored
+    // as they are; only covers that should decode and don't are errors
+    let final_cover_data = if decodable {
+        resize_cover_if_needed(cover_data, options)
+            .context("Failed to resize cover image")?
+    } else {
+        cover_data.to_vec()
+    };
+    Ok(PreparedCover::Store(final_cover_data))
+}
+
+/// Checks, resizes and writes a cover image. Returns false if the cover was skipped for being
+/// over the pixel limit, and an error if it's unreadable or can't be written.
+fn save_cover(cover_data: &[u8], cover_dest: &Path, options: &CoverOptions, temp_dir: Option<&Path>) -> Result<bool> {
+    let final_cover_data = match prepare_cover(cover_data, options)? {
+        PreparedCover::Store(final_cover_data) => final_cover_data,
+        PreparedCover::Skip(reason) => {
+            say!("Warning: {}, skipping cover", reason);
+            return Ok(false);
+        }
+    };
+    write_atomically(cover_dest, temp_dir, |temp| fs::write(temp, &final_cover_data))
+        .with_context(|| format!("Failed to write cover image to {:?}", cover_dest))?;
+    Ok(true)
+}
+
+/// Writes `dest` by having `write` fill a temporary file, then renaming it into place, so an
+/// interrupted copy never leaves a partial file behind. The temporary file goes in `temp_dir`,
+/// or next to `dest` by default; if `temp_dir` is on another file system, it's copied across
+/// next to `dest` first so the final step is still a rename.
+fn write_atomically(dest: &Path, temp_dir: Option<&Path>, write: impl FnOnce(&Path) -> std::io::Result<()>) -> std::io::Result<()> {
+    let dest_dir = dest.parent().unwrap_or(Path::new("."));
+    let temp_name = format!(".{}.cwh-tmp-{}", dest.file_name().unwrap_or_default().to_string_lossy(), std::process::id());
+    let temp = temp_dir.unwrap_or(dest_dir).join(&temp_name);
+
+    let result = write(&temp).and_then(|()| match fs::rename(&temp, dest) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            let local_temp = dest_dir.join(&temp_name);
+            let result = fs::copy(&temp, &local_temp).and_then(|_| fs::rename(&local_temp, dest));
+            if result.is_err() {
+                let _ = fs::remove_file(&local_temp);
+            }
+            result
+        }
+        result => result,
+    });
+    // Whatever happened, the staged copy shouldn't outlive this call
+    let _ = fs::remove_file(&temp);
+    result
+}
+
+/// Gives `dest` the modification time of `src`
+fn copy_mtime(src: &Path, dest: &Path) -> Result<()> {
+    let modified = fs::metadata(src)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Failed to read the modification time of {:?}", src))?;
+    fs::File::options().write(true).open(dest)
+        .and_then(|file| file.set_modified(modified))
+        .with_context(|| format!("Failed to set the modification time of {:?}", dest))
+}
+
+/// Copies or updates the book file in the Calibre library structure.
 /// If updating, it first clears the destination directory of old files.
+/// Files are staged in `options.temp_dir` and renamed into place. With `preserve_timestamps`,
+/// the copied book and cover keep the modification time of their source. Books left without a
+/// cover get a generated one when `options.cover.placeholder_background` is set.
 /// Returns true if a cover was saved.
-pub(crate) fn update_book_files(library_dir: &Path, epub_file: &Path, book_path: &str, is_update: bool, metadata: &BookMetadata) -> Result<bool> {
+pub(crate) fn update_book_files(library_dir: &Path, epub_file: &Path, book_path: &str, is_update: bool, metadata: &BookMetadata, options: &BookFileOptions) -> Result<bool> {
     let dest_dir = library_dir.join(book_path);
-    let mut cover_saved = false;
 
     if is_update && dest_dir.exists() {
-        println!(" -> Removing old book file(s)...");
+        say!(" -> Removing old book file(s)...");
         for entry in fs::read_dir(&dest_dir)? {
             let entry = entry?;
             let path = entry.path();
@@ -308,54 +895,355 @@ pub(crate) fn update_book_files(library_dir: &Path, epub_file: &Path, book_path:
     fs::create_dir_all(&dest_dir)
         .with_context(|| format!("Failed to create directory: {:?}", dest_dir))?;
 
-    let (_format, extension) = detect_book_format(epub_file)?;
+    let (format, extension) = metadata.format()?;
 
     let epub_filename = format!("{} - {}{}", get_valid_filename(&metadata.title, 42), get_valid_filename(&metadata.author, 42), extension);
     let dest_file = dest_dir.join(epub_filename);
-    fs::copy(epub_file, &dest_file)
-        .with_context(|| format!("Failed to copy EPUB to {:?}", dest_file))?;
+    write_atomically(&dest_file, options.temp_dir, |temp| fs::copy(epub_file, temp).map(drop))
+        .with_context(|| format!("Failed to copy book file to {:?}", dest_file))?;
+    if options.preserve_timestamps {
+        copy_mtime(epub_file, &dest_file)?;
+    }
 
-    // Handle cover image: extract from EPUB if present, else fallback to external cover.jpg
     let cover_dest = dest_dir.join("cover.jpg");
-    if let Ok(mut doc) = epub::doc::EpubDoc::new(epub_file) {
-        match doc.get_cover() {
-            Some((cover_data, _mime)) => {
-                // Resize cover if it's too large
-                let final_cover_data = resize_cover_if_needed(&cover_data)
-                    .unwrap_or_else(|e| {
-                        println!("Warning: Failed to resize cover image: {}, using original", e);
-                        cover_data.clone()
-                    });
-                
-                fs::write(&cover_dest, &final_cover_data)
-                    .with_context(|| format!("Failed to write cover image to {:?}", cover_dest))?;
-                println!(" -> Cover image extracted from EPUB and saved.");
-                cover_saved = true;
-            }
-            None => {
-                // Fallback: copy external cover.jpg if it exists
-                let cover_src = epub_file.parent().map(|p| p.join("cover.jpg")).unwrap_or_else(|| PathBuf::from("cover.jpg"));
-                if cover_src.exists() {
-                    // Read external cover and resize if needed
-                    let cover_data = fs::read(&cover_src)
-                        .with_context(|| format!("Failed to read external cover from {:?}", cover_src))?;
-                    
-                    let final_cover_data = resize_cover_if_needed(&cover_data)
-                        .unwrap_or_else(|e| {
-                            println!("Warning: Failed to resize external cover image: {}, using original", e);
-                            cover_data
-                        });
-                    
-                    fs::write(&cover_dest, &final_cover_data)
-                        .with_context(|| format!("Failed to write cover image to {:?}", cover_dest))?;
-                    println!(" -> Cover image copied from external file and resized if needed.");
-                    cover_saved = true;
-                }
+    let cover_saved = save_book_cover(epub_file, format, &cover_dest, options)?;
+    if let (false, Some(background)) = (cover_saved, options.cover.placeholder_background) {
+        let cover = crate::placeholder::placeholder_cover(&metadata.title, &metadata.author, background);
+        let cover_data = encode_jpeg(&cover, options.cover.quality)?;
+        write_atomically(&cover_dest, options.temp_dir, |temp| fs::write(temp, &cover_data))
+            .with_context(|| format!("Failed to write placeholder cover to {:?}", cover_dest))?;
+        say!(" -> No cover found; generated a placeholder cover.");
+        return Ok(true);
+    }
+    Ok(cover_saved)
+}
+
+/// Saves the book's cover to `cover_dest`: the EPUB's own cover, else a `cover.jpg` next to the
+/// source file. Returns true if a cover was saved.
+fn save_book_cover(epub_file: &Path, format: &str, cover_dest: &Path, options: &BookFileOptions) -> Result<bool> {
+    // Covers can only be extracted from EPUB-based formats
+    if !is_epub_format(format) {
+        say!(" -> Skipping cover extraction for {} file.", format);
+        return Ok(false);
+    }
+
+    // Handle cover image: extract from EPUB if present, else fallback to external cover.jpg
+    let Ok(mut doc) = epub::doc::EpubDoc::new(epub_file) else {
+        say!("Warning: Could not open EPUB for cover extraction.");
+        return Ok(false);
+    };
+    let cover_src = epub_file.parent().map(|p| p.join("cover.jpg")).unwrap_or_else(|| PathBuf::from("cover.jpg"));
+    let cover_result = match get_epub_cover(&mut doc) {
+        Some((cover_data, _mime)) => Some(save_cover(&cover_data, cover_dest, options.cover, options.temp_dir)
+            .map(|saved| saved.then_some(("extracted from EPUB and saved", epub_file)))),
+        None => {
+            // Fallback: copy external cover.jpg if it exists
+            cover_src.exists().then(|| {
+                fs::read(&cover_src)
+                    .with_context(|| format!("Failed to read external cover from {:?}", cover_src))
+                    .and_then(|cover_data| save_cover(&cover_data, cover_dest, options.cover, options.temp_dir))
+                    .map(|saved| saved.then_some(("copied from external file and resized if needed", cover_src.as_path())))
+            })
+        }
+    };
+
+    match cover_result {
+        Some(Ok(Some((source, source_file)))) => {
+            say!(" -> Cover image {}.", source);
+            if options.preserve_timestamps {
+                copy_mtime(source_file, cover_dest)?;
             }
+            Ok(true)
         }
-    } else {
-        println!("Warning: Could not open EPUB for cover extraction.");
+        Some(Err(e)) if options.cover.ignore_errors => {
+            say!("⚠️  Warning: {:#}; importing the book without a cover.", e);
+            let _ = fs::remove_file(cover_dest);
+            Ok(false)
+        }
+        Some(Err(e)) => Err(e),
+        Some(Ok(None)) | None => Ok(false),
     }
+}
 
-    Ok(cover_saved)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A JPEG whose header is cut off, so it can't be decoded
+    const CORRUPT_JPEG: &[u8] = &[0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, b'J', b'F', b'I', b'F', 0x00, 0xDE, 0xAD];
+
+    fn test_cover(width: u32, height: u32) -> Vec<u8> {
+        let img = DynamicImage::new_rgb8(width, height);
+        encode_jpeg(&img, 82).expect("failed to encode test cover")
+    }
+
+    /// Cover options matching the `add` defaults
+    fn test_cover_options() -> CoverOptions {
+        CoverOptions { quality: 82, max_pixels: 40_000_000, max_width: None, max_height: None, ignore_errors: true, placeholder_background: None }
+    }
+
+    #[test]
+    fn test_check_cover_dimensions() {
+        let cover = test_cover(100, 100);
+        let options = |max_pixels| CoverOptions { max_pixels, ..test_cover_options() };
+        assert!(check_cover_dimensions(&cover, &options(10_000)).is_ok());
+        assert!(check_cover_dimensions(&cover, &options(9_999)).is_err());
+        assert!(check_cover_dimensions(b"not an image", &options(1)).is_ok());
+    }
+
+    #[test]
+    fn test_series_from_filename() {
+        let re = Regex::new(r"^(?P<series>.+?) (?P<index>\d+) - (?P<title>.+)$").unwrap();
+        assert_eq!(series_from_filename(Path::new("in/Discworld 07 - Pyramids.epub"), &re), Some(FilenameSeries {
+            series: "Discworld".to_string(),
+            index: Some(7.0),
+            title: Some("Pyramids".to_string()),
+        }));
+        assert_eq!(series_from_filename(Path::new("in/Pyramids.epub"), &re), None);
+    }
+
+    #[test]
+    fn test_scale_cover_to_width() {
+        let cover = test_cover(400, 600);
+        assert_eq!(read_cover_dimensions(&scale_cover_to_width(&cover, 100).unwrap()), Some((100, 150)));
+        assert_eq!(scale_cover_to_width(&cover, 800).unwrap(), cover);
+    }
+
+    #[test]
+    fn test_fit_cover_dimensions() {
+        assert_eq!(fit_cover_dimensions(1200, 1600, None, None), (1200, 1600));
+        assert_eq!(fit_cover_dimensions(1200, 1600, Some(600), None), (600, 800));
+        assert_eq!(fit_cover_dimensions(1200, 1600, None, Some(800)), (600, 800));
+        assert_eq!(fit_cover_dimensions(1200, 1600, Some(900), Some(800)), (600, 800));
+        assert_eq!(fit_cover_dimensions(1200, 1600, Some(2000), Some(2000)), (1200, 1600));
+
+        let options = CoverOptions { max_width: Some(50), max_height: Some(50), ..test_cover_options() };
+        let resized = resize_cover_if_needed(&test_cover(100, 200), &options).unwrap();
+        assert_eq!(read_cover_dimensions(&resized), Some((25, 50)));
+    }
+
+    #[test]
+    fn test_repair_encoding() {
+        assert_eq!(repair_encoding("CafÃ© Society", WINDOWS_1252, false, None), "Café Society");
+        assert_eq!(repair_encoding("Donâ€™t Panic", WINDOWS_1252, false, None), "Don’t Panic");
+        assert_eq!(repair_encoding("Café Society", WINDOWS_1252, true, None), "Café Society");
+        assert_eq!(repair_encoding("Caf\u{FFFD} Society", WINDOWS_1252, false, None), "Caf\u{FFFD} Society");
+        let title = OpfField::Element("title");
+        assert_eq!(
+            repair_encoding("Caf\u{FFFD} Society", WINDOWS_1252, false, Some(("<dc:title>Café Society</dc:title>", title))),
+            "Café Society"
+        );
+    }
+
+    #[test]
+    fn test_recover_from_opf_is_anchored_to_the_field() {
+        let opf = r#"<dc:description>Caf Society notes</dc:description><dc:title>Café Society</dc:title>
+            <dc:creator opf:role="aut">José Saramago</dc:creator>
+            <meta content="Crónicas" name="calibre:series"/>"#;
+        assert_eq!(recover_from_opf("Caf\u{FFFD} Society", OpfField::Element("title"), opf), Some("Café Society".to_string()));
+        assert_eq!(recover_from_opf("Jos\u{FFFD} Saramago", OpfField::Element("creator"), opf), Some("José Saramago".to_string()));
+        assert_eq!(recover_from_opf("Cr\u{FFFD}nicas", OpfField::Meta("calibre:series"), opf), Some("Crónicas".to_string()));
+        assert_eq!(recover_from_opf("Caf\u{FFFD} Society", OpfField::Element("publisher"), opf), None);
+        assert_eq!(recover_from_opf("\u{FFFD}\u{FFFD}", OpfField::Element("title"), opf), None);
+    }
+
+    #[test]
+    fn test_cover_page_image_resolution() {
+        let page = r#"<html><body><div><svg><image width="600" xlink:href="../images/cover.jpg"/></svg></div></body></html>"#;
+        let src = COVER_PAGE_IMAGE_RE.captures(page).unwrap().get(1).unwrap().as_str();
+        assert_eq!(resolve_epub_href(Path::new("OEBPS/text/cover.xhtml"), src), PathBuf::from("OEBPS/images/cover.jpg"));
+
+        let page = r#"<body><p><IMG alt="Cover" SRC='cover.png'></p></body>"#;
+        let src = COVER_PAGE_IMAGE_RE.captures(page).unwrap().get(1).unwrap().as_str();
+        assert_eq!(resolve_epub_href(Path::new("cover.xhtml"), src), PathBuf::from("cover.png"));
+    }
+
+    /// Writes a minimal EPUB with the given `<dc:title>`/`<dc:creator>` elements to a temp file
+    fn write_test_epub(name: &str, metadata_xml: &str) -> PathBuf {
+        write_test_epub_with_files(name, metadata_xml, &[])
+    }
+
+    /// Like `write_test_epub`, also adding each (id, href, media type, contents) file to the manifest
+    fn write_test_epub_with_files(name: &str, metadata_xml: &str, extra_files: &[(&str, &str, &str, &[u8])]) -> PathBuf {
+        use std::io::Write;
+        use zip::write::SimpleFileOptions;
+
+        let path = std::env::temp_dir().join(format!("cwh-test-{}-{}.epub", std::process::id(), name));
+        let mut zip = zip::ZipWriter::new(fs::File::create(&path).expect("failed to create test EPUB"));
+        let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        let files = [
+            ("mimetype", "application/epub+zip".to_string()),
+            ("META-INF/container.xml", r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles><rootfile full-path="content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>"#.to_string()),
+            ("content.opf", format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0" unique-identifier="id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">{}<dc:language>en</dc:language></metadata>
+  <manifest><item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/>{}</manifest>
+  <spine><itemref idref="c1"/></spine>
+</package>"#, metadata_xml, extra_files.iter()
+                .map(|(id, href, media_type, _)| format!(r#"<item id="{}" href="{}" media-type="{}"/>"#, id, href, media_type))
+                .collect::<String>())),
+            ("c1.xhtml", "<html><body><p>Text</p></body></html>".to_string()),
+        ];
+        for (file_name, contents) in files {
+            zip.start_file(file_name, options).expect("failed to add file to test EPUB");
+            zip.write_all(contents.as_bytes()).expect("failed to write test EPUB");
+        }
+        for (_, href, _, contents) in extra_files {
+            zip.start_file(*href, options).expect("failed to add file to test EPUB");
+            zip.write_all(contents).expect("failed to write test EPUB");
+        }
+        zip.finish().expect("failed to finish test EPUB");
+        path
+    }
+
+    #[test]
+    fn test_empty_title_falls_back_to_filename() {
+        let path = write_test_epub("empty-title", "<dc:title>  </dc:title><dc:creator>Jane Doe</dc:creator>");
+        let metadata = get_epub_metadata(&path, &MetadataOptions::default()).expect("lenient mode should accept an empty title");
+        assert_eq!(metadata.title, path.file_stem().unwrap().to_string_lossy());
+        assert_eq!(metadata.author, "Jane Doe");
+        assert!(get_epub_metadata(&path, &MetadataOptions { strict: true, ..Default::default() }).is_err());
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_empty_author_falls_back_to_unknown() {
+        let path = write_test_epub("empty-author", "<dc:title>A Title</dc:title><dc:creator/>");
+        let metadata = get_epub_metadata(&path, &MetadataOptions::default()).expect("lenient mode should accept an empty author");
+        assert_eq!(metadata.title, "A Title");
+        assert_eq!(metadata.author, "Unknown");
+        assert!(get_epub_metadata(&path, &MetadataOptions { strict: true, ..Default::default() }).is_err());
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_corrupt_cover_does_not_block_import() {
+        let path = write_test_epub_with_files(
+            "corrupt-cover",
+            r#"<dc:title>Broken Cover</dc:title><dc:creator>Jane Doe</dc:creator><meta name="cover" content="cover-image"/>"#,
+            &[("cover-image", "cover.jpg", "image/jpeg", CORRUPT_JPEG)],
+        );
+        let metadata = get_epub_metadata(&path, &MetadataOptions::default()).unwrap();
+        let library_dir = std::env::temp_dir().join(format!("cwh-test-{}-corrupt-cover-library", std::process::id()));
+        let mut options = test_cover_options();
+
+        let cover_saved = update_book_files(&library_dir, &path, "Jane Doe/Broken Cover (1)", false, &metadata, &BookFileOptions { cover: &options, preserve_timestamps: false, temp_dir: None })
+            .expect("a corrupt cover shouldn't fail the import");
+        assert!(!cover_saved);
+        let book_dir = library_dir.join("Jane Doe/Broken Cover (1)");
+        assert!(book_dir.join("Broken Cover - Jane Doe.epub").exists());
+        assert!(!book_dir.join("cover.jpg").exists());
+        // The book was staged next to its destination and renamed into place
+        assert_eq!(fs::read_dir(&book_dir).unwrap().count(), 1);
+
+        options.ignore_errors = false;
+        assert!(update_book_files(&library_dir, &path, "Jane Doe/Broken Cover (1)", true, &metadata, &BookFileOptions { cover: &options, preserve_timestamps: false, temp_dir: None }).is_err());
+
+        fs::remove_dir_all(&library_dir).ok();
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_trial_decode_cover_matches_save_cover() {
+        let valid_cover = test_cover(400, 600);
+        let corrupt_path = write_test_epub_with_files(
+            "trial-corrupt-cover",
+            r#"<dc:title>Broken Cover</dc:title><dc:creator>Jane Doe</dc:creator><meta name="cover" content="cover-image"/>"#,
+            &[("cover-image", "cover.jpg", "image/jpeg", CORRUPT_JPEG)],
+        );
+        let valid_path = write_test_epub_with_files(
+            "trial-valid-cover",
+            r#"<dc:title>Good Cover</dc:title><dc:creator>Jane Doe</dc:creator><meta name="cover" content="cover-image"/>"#,
+            &[("cover-image", "cover.jpg", "image/jpeg", &valid_cover)],
+        );
+        let mut options = CoverOptions { max_width: Some(200), ignore_errors: false, ..test_cover_options() };
+
+        // --validate-only reports the corrupt cover as a failure while the EPUB itself is fine
+        validate_epub(&corrupt_path).expect("the EPUB structure is intact");
+        assert!(trial_decode_cover(&corrupt_path, &options).is_err());
+
+        // The dimension limits are applied just as they would be on import
+        assert_eq!(trial_decode_cover(&valid_path, &options).unwrap().as_deref(), Some("cover 200x300"));
+        options.max_pixels = 1_000;
+        assert!(trial_decode_cover(&valid_path, &options).unwrap().unwrap().ends_with("it would be skipped"));
+
+        fs::remove_file(&corrupt_path).ok();
+        fs::remove_file(&valid_path).ok();
+    }
+
+    #[test]
+    fn test_large_png_cover_is_stored_as_is() {
+        let mut png_cover = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_cover.resize(250 * 1024, 0);
+        let cover_dest = std::env::temp_dir().join(format!("cwh-test-{}-png-cover.jpg", std::process::id()));
+        let options = CoverOptions { ignore_errors: false, ..test_cover_options() };
+
+        assert!(save_cover(&png_cover, &cover_dest, &options, None).expect("a PNG cover can't be resized but should still be saved"));
+        assert_eq!(fs::read(&cover_dest).unwrap(), png_cover);
+
+        fs::remove_file(&cover_dest).ok();
+    }
+
+    #[test]
+    fn test_normalize_language_code_with_custom_map() {
+        let language_map = HashMap::from([
+            ("en-gb".to_string(), "eng".to_string()),
+            ("cn".to_string(), "zho".to_string()),
+        ]);
+        assert_eq!(normalize_language_code("cn", &HashMap::new()), "und");
+        assert_eq!(normalize_language_code("CN", &language_map), "zho");
+        assert_eq!(normalize_language_code("en-GB", &language_map), "eng");
+        assert_eq!(normalize_language_code("fr-CA", &language_map), "fra");
+    }
+
+    #[test]
+    fn test_dual_language_epub_keeps_every_language() {
+        let path = write_test_epub("dual-language", "<dc:title>Bilingual</dc:title><dc:creator>Jane Doe</dc:creator><dc:language>fr</dc:language><dc:language>eng</dc:language>");
+        let metadata = get_epub_metadata(&path, &MetadataOptions::default()).unwrap();
+        assert_eq!(metadata.languages, vec!["fra", "eng"]);
+        assert!(metadata.unmapped_languages.is_empty());
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_unmapped_language_is_reported() {
+        let path = write_test_epub("unmapped-language", "<dc:title>Klingon</dc:title><dc:creator>Jane Doe</dc:creator><dc:language>tlh-Latn</dc:language>");
+        let metadata = get_epub_metadata(&path, &MetadataOptions::default()).unwrap();
+        assert_eq!(metadata.languages, vec!["und", "eng"]);
+        assert_eq!(metadata.unmapped_languages, vec!["tlh-Latn"]);
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_drm_is_detected_but_font_obfuscation_is_not() {
+        let encryption = |algorithm: &str| format!(
+            r#"<encryption xmlns="urn:oasis:names:tc:opendocument:xmlns:container"><EncryptedData xmlns="http://www.w3.org/2001/04/xmlenc#"><EncryptionMethod Algorithm="{}"/></EncryptedData></encryption>"#,
+            algorithm,
+        );
+        let metadata_xml = "<dc:title>Locked</dc:title><dc:creator>Jane Doe</dc:creator>";
+        let strict = MetadataOptions { strict: true, ..Default::default() };
+
+        let fonts = encryption("http://www.idpf.org/2008/embedding");
+        let path = write_test_epub_with_files("font-obfuscation", metadata_xml, &[("enc", "META-INF/encryption.xml", "application/xml", fonts.as_bytes())]);
+        assert!(get_epub_metadata(&path, &strict).is_ok());
+        fs::remove_file(&path).ok();
+
+        let aes = encryption("http://www.w3.org/2001/04/xmlenc#aes128-cbc");
+        let path = write_test_epub_with_files("drm", metadata_xml, &[("enc", "META-INF/encryption.xml", "application/xml", aes.as_bytes())]);
+        assert!(get_epub_metadata(&path, &MetadataOptions::default()).is_ok(), "DRM only warns by default");
+        assert!(get_epub_metadata(&path, &strict).is_err());
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_title_and_author_whitespace_is_normalized() {
+        let path = write_test_epub("whitespace", "<dc:title>  The  Double   Spaced Title </dc:title><dc:creator> Jane  Doe </dc:creator>");
+        let metadata = get_epub_metadata(&path, &MetadataOptions::default()).unwrap();
+        assert_eq!(metadata.title, "The Double Spaced Title");
+        assert_eq!(metadata.author, "Jane Doe");
+        fs::remove_file(&path).ok();
+    }
 }
@@ -0,0 +1,70 @@
+use rusqlite::ErrorCode;
+use thiserror::Error;
+
+/// Failure categories of the library operations, so callers can tell them apart
+/// and `main` can choose an exit code
+#[derive(Debug, Error)]
+pub(crate) enum CwhError {
+    /// A book, file or shelf that the operation needs doesn't exist
+    #[error("{0}")]
+    NotFound(String),
+    /// An argument such as a shelf name was rejected before touching the database
+    #[error("{0}")]
+    InvalidInput(String),
+    /// The book file or the metadata read from it can't be used
+    #[error("{0}")]
+    InvalidEpub(String),
+    /// Another process (usually Calibre or Calibre-Web) holds a lock on the database
+    #[error(transparent)]
+    DatabaseLocked(anyhow::Error),
+    /// Reading or writing library files failed
+    #[error(transparent)]
+    FileSystem(anyhow::Error),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl CwhError {
+    /// The process exit code for this error: 2 invalid input (like clap's usage errors),
+    /// 3 not found, 5 invalid book, 6 database locked, 7 file system error, and 1 for anything else
+    pub(crate) fn exit_code(&self) -> u8 {
+        match self {
+            CwhError::InvalidInput(_) => 2,
+            CwhError::NotFound(_) => 3,
+            CwhError::InvalidEpub(_) => 5,
+            CwhError::DatabaseLocked(_) => 6,
+            CwhError::FileSystem(_) => 7,
+            CwhError::Other(_) => 1,
+        }
+    }
+}
+
+/// Sorts errors from helpers that still return `anyhow::Error` by their underlying cause
+impl From<anyhow::Error> for CwhError {
+    fn from(error: anyhow::Error) -> Self {
+        let locked = error.chain().any(|cause| {
+            cause.downcast_ref::<rusqlite::Error>()
+                .and_then(rusqlite::Error::sqlite_error_code)
+                .is_some_and(|code| matches!(code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked))
+        });
+        if locked {
+            CwhError::DatabaseLocked(error)
+        } else if error.chain().any(|cause| cause.is::<std::io::Error>()) {
+            CwhError::FileSystem(error)
+        } else {
+            CwhError::Other(error)
+        }
+    }
+}
+
+impl From<rusqlite::Error> for CwhError {
+    fn from(error: rusqlite::Error) -> Self {
+        anyhow::Error::from(error).into()
+    }
+}
+
+impl From<std::io::Error> for CwhError {
+    fn from(error: std::io::Error) -> Self {
+        anyhow::Error::from(error).into()
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use rusqlite::Connection;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+use crate::cli::ChecksumAlgorithm;
+use crate::utils::{calculate_file_hash, json_escape, json_unescape};
+use crate::output::say;
+
+/// Name of the hash manifest stored in the library directory
+pub(crate) const MANIFEST_FILE_NAME: &str = ".cwh-hashes.json";
+
+/// Matches one `"path": "hash"` entry of the manifest
+static MANIFEST_ENTRY_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#""((?:[^"\\]|\\.)*)"\s*:\s*"([0-9a-fA-F]+)""#).expect("invalid regex"));
+
+/// Matches the manifest's `"algorithm": "..."` field
+static MANIFEST_ALGORITHM_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#""algorithm"\s*:\s*"([a-z0-9]+)""#).expect("invalid regex"));
+
+/// The stored hash manifest: the hash algorithm and library-relative file paths mapped to hashes
+pub(crate) struct Manifest {
+    pub(crate) algorithm: ChecksumAlgorithm,
+    pub(crate) hashes: BTreeMap<String, String>,
+}
+
+/// Reads the hash manifest. A missing manifest is treated as empty and uses `default_algorithm`;
+/// manifests written before the algorithm was recorded are SHA1.
+pub(crate) fn read_manifest(manifest_path: &Path, default_algorithm: ChecksumAlgorithm) -> Result<Manifest> {
+    if !manifest_path.exists() {
+        return Ok(Manifest { algorithm: default_algorithm, hashes: BTreeMap::new() });
+    }
+
+    let contents = fs::read_to_string(manifest_path)
+        .with_context(|| format!("Failed to read hash manifest {:?}", manifest_path))?;
+
+    let algorithm = match MANIFEST_ALGORITHM_RE.captures(&contents) {
+        Some(caps) => ChecksumAlgorithm::from_name(&caps[1])
+            .with_context(|| format!("Unknown hash algorithm '{}' in {:?}", &caps[1], manifest_path))?,
+        None => ChecksumAlgorithm::Sha1,
+    };
+    let hashes = MANIFEST_ENTRY_RE.captures_iter(&contents)
+        .map(|caps| (json_unescape(&caps[1]), caps[2].to_lowercase()))
+        .collect();
+    Ok(Manifest { algorithm, hashes })
+}
+
+/// Writes the hash manifest as a JSON object holding the algorithm and the hashes, one per line.
+pub(crate) fn write_manifest(manifest_path: &Path, manifest: &Manifest) -> Result<()> {
+    let entries: Vec<String> = manifest.hashes.iter()
+        .map(|(path, hash)| format!("    \"{}\": \"{}\"", json_escape(path), hash))
+        .collect();
+    let files = if entries.is_empty() {
+        "{}".to_string()
+    } else {
+        format!("{{\n{}\n  }}", entries.join(",\n"))
+    };
+    let json = format!("{{\n  \"algorithm\": \"{}\",\n  \"files\": {}\n}}\n", manifest.algorithm.name(), files);
+
+    fs::write(manifest_path, json)
+        .with_context(|| format!("Failed to write hash manifest {:?}", manifest_path))
+}
+
+/// Hashes every book file recorded in the `data` table and compares it against the
+/// stored manifest, using the manifest's own algorithm (`algorithm` only applies to a new
+/// manifest). New files are added to the manifest; changed hashes are reported as
+/// potential corruption and only recorded when `accept_changes` is set.
+pub(crate) fn verify_hashes(calibre_conn: &Connection, library_dir: &Path, algorithm: ChecksumAlgorithm, accept_changes: bool) -> Result<()> {
+    say!("🔐 Verifying library file hashes...");
+
+    let manifest_path = library_dir.join(MANIFEST_FILE_NAME);
+    let mut manifest = read_manifest(&manifest_path, algorithm)?;
+    if manifest.hashes.is_empty() {
+        manifest.algorithm = algorithm;
+        say!(" -> No existing manifest found; recording {} hashes for all files.", algorithm.name());
+    } else {
+        say!(" -> Loaded {} {} hashes from {:?}", manifest.hashes.len(), manifest.algorithm.name(), manifest_path);
+        if manifest.algorithm != algorithm {
+            say!(" -> The manifest uses {}, so files are verified with {} rather than {}.", manifest.algorithm.name(), manifest.algorithm.name(), algorithm.name());
+        }
+    }
+
+    let mut stmt = calibre_conn.prepare(
+        "SELECT b.id, b.title, b.path, d.name, d.format
+         FROM data d
+         JOIN books b ON d.book = b.id
+         ORDER BY b.id"
+    )?;
+    let files: Vec<(i64, String, String, String, String)> = stmt.query_map([], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+    })?.collect::<Result<Vec<_>, _>>()?;
+
+    let mut verified = 0;
+    let mut added = 0;
+    let mut changed = 0;
+    let mut missing = 0;
+
+    for (book_id, title, book_path, data_name, format) in &files {
+        let relative_path = format!("{}/{}.{}", book_path, data_name, format.to_lowercase());
+        let file_path = library_dir.join(&relative_path);
+
+        if !file_path.exists() {
+            missing += 1;
+            say!("    ⚠️  ID {} — '{}': file not found ({})", book_id, title, relative_path);
+            continue;
+        }
+
+        let hash = match calculate_file_hash(&file_path, manifest.algorithm) {
+            Ok(hash) => hash,
+            Err(e) => {
+                missing += 1;
+                say!("    ⚠️  ID {} — '{}': could not read {} ({})", book_id, title, relative_path, e);
+                continue;
+            }
+        };
+
+        match manifest.hashes.get(&relative_path) {
+            Some(stored) if *stored == hash => verified += 1,
+            Some(stored) => {
+                changed += 1;
+                say!("    ❌ ID {} — '{}': hash changed, possible corruption ({})", book_id, title, relative_path);
+                say!("       Stored:  {}", stored);
+                say!("       Current: {}", hash);
+                if accept_changes {
+                    manifest.hashes.insert(relative_path, hash);
+                    say!("       ✅ Accepted new hash");
+                }
+            }
+            None => {
+                added += 1;
+                manifest.hashes.insert(relative_path, hash);
+            }
+        }
+    }
+
+    if added > 0 || (accept_changes && changed > 0) {
+        write_manifest(&manifest_path, &manifest)?;
+        say!(" -> Saved manifest to {:?}", manifest_path);
+    }
+
+    say!("\n📊 Hash verification complete:");
+    say!("   ✅ Verified: {}", verified);
+    if added > 0 {
+        say!("   ➕ Newly recorded: {}", added);
+    }
+    if changed > 0 {
+        say!("   ❌ Changed: {}", changed);
+    }
+    if missing > 0 {
+        say!("   ⚠️  Missing or unreadable: {}", missing);
+    }
+
+    if changed > 0 && !accept_changes {
+        say!("\n   If these files were intentionally replaced, re-run with --accept-changes.");
+    }
+
+    Ok(())
+}
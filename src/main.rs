@@ -1,28 +1,103 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use clap::Parser;
 use rusqlite::{Connection, params};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 mod cli;
-use cli::{Cli, Commands};
+use cli::{Cli, Commands, MetadataOverrides, OutputFormat};
 mod models;
 mod db;
+mod error;
 mod appdb;
 mod epub;
 mod calibre;
 mod cleanup;
+mod hashes;
+mod backups;
+mod output;
+mod placeholder;
 mod utils;
+use output::say;
 
 fn library_dir(metadata_file: &Path) -> &Path {
     metadata_file.parent().unwrap_or_else(|| Path::new("."))
 }
 
-fn main() -> Result<()> {
+/// Returns the book IDs to operate on: the single positional ID, or the IDs listed in
+/// `--book-id-file`, one per line. Blank lines and `#` comments are ignored.
+fn book_ids(book_id: Option<i64>, book_id_file: Option<&Path>) -> Result<Vec<i64>> {
+    let Some(path) = book_id_file else {
+        return Ok(book_id.into_iter().collect());
+    };
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read book ID file: {:?}", path))?;
+    let mut ids = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let id = line.parse::<i64>()
+            .with_context(|| format!("Invalid book ID '{}' on line {} of {:?}", line, index + 1, path))?;
+        ids.push(id);
+    }
+    if ids.is_empty() {
+        anyhow::bail!("No book IDs found in {:?}", path);
+    }
+    Ok(ids)
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {:?}", error);
+            // Errors from code that doesn't return CwhError yet are sorted by their cause
+            let code = match error.chain().find_map(|cause| cause.downcast_ref::<error::CwhError>()) {
+                Some(cwh_error) => cwh_error.exit_code(),
+                None => error::CwhError::from(error).exit_code(),
+            };
+            ExitCode::from(code)
+        }
+    }
+}
+
+fn run() -> Result<()> {
     let cli = Cli::parse();
+    output::set_ascii(cli.ascii || std::env::var_os("CWH_ASCII").is_some_and(|value| !value.is_empty()));
+    output::set_messages_to_stderr(cli.format == OutputFormat::Json || matches!(cli.command, Commands::Add { progress_json: true, .. } | Commands::List { compact: true, .. }));
+    output::set_color(cli.color);
+
+    // Backup commands work on the database files themselves, so they run before any connection is opened
+    match &cli.command {
+        Commands::ListBackups => {
+            let db_paths: Vec<&Path> = cli.metadata_file.iter().chain(&cli.appdb_file).map(PathBuf::as_path).collect();
+            return backups::list_backups(&db_paths);
+        }
+        Commands::RestoreBackup { backup, target, yes } => {
+            return backups::restore_backup(backup, target.as_deref(), *yes);
+        }
+        _ => {}
+    }
+
+    if let Some(ref temp_dir) = cli.temp_dir
+        && !temp_dir.is_dir() {
+            anyhow::bail!("The specified temp directory does not exist: {:?}", temp_dir);
+        }
+
+    // Checked before this tool's own pre-operation backup, which would otherwise always count as recent
+    if let Some(hours) = cli.require_backup_within
+        && cli.command.is_destructive() {
+            let db_paths: Vec<&Path> = cli.metadata_file.iter().chain(&cli.appdb_file).map(PathBuf::as_path).collect();
+            backups::require_recent_backup(&db_paths, cli.backup_dir.as_deref(), hours)?;
+        }
 
     // For some commands, metadata_file is not required
-    let needs_metadata = !matches!(cli.command, Commands::FixKoboSync | Commands::AddToShelf { .. } | Commands::ListShelves);
+    let needs_metadata = !matches!(cli.command, Commands::Add { validate_only: true, .. } | Commands::FixKoboSync { .. } | Commands::AddToShelf { .. } | Commands::MoveToShelf { .. } | Commands::ListShelves | Commands::CopyShelf { .. } | Commands::TouchShelf { .. } | Commands::DumpSchema | Commands::CheckFk);
     
     let metadata_file = if needs_metadata {
         Some(cli.metadata_file.context("--metadata-file is required")?)
@@ -39,82 +114,293 @@ fn main() -> Result<()> {
             );
         }
 
+    // Read-only connections can't take a write lock, so they never block a busy Calibre
+    let read_only = cli.read_only || cli.command.is_read_only();
+    let db_config = db::DatabaseConfig {
+        print_sql: cli.print_sql,
+        read_only: cli.read_only || cli.command.never_writes(),
+        ..Default::default()
+    };
+
     let mut calibre_conn = if let Some(ref metadata_file) = metadata_file {
-        let conn = db::open_calibre_db(metadata_file)
+        let conn = db::open_calibre_db(metadata_file, &db_config)
             .with_context(|| format!("Failed to open Calibre database at {:?}", metadata_file))?;
         Some(conn)
     } else {
         None
     };
 
-    let mut appdb_conn = appdb::open_appdb(cli.appdb_file.as_deref())?;
+    // `list` works from metadata.db alone, so a missing app.db only drops its shelf features
+    let appdb_file = match cli.appdb_file.as_deref() {
+        Some(path) if matches!(cli.command, Commands::List { .. }) && !path.exists() => {
+            say!("⚠️  Warning: app.db not found at {:?}; listing without Calibre-Web shelf information.", path);
+            None
+        }
+        path => path,
+    };
+    let mut appdb_conn = appdb::open_appdb(appdb_file, &db_config)?;
 
-    // Verify and repair any NULL timestamps in both databases
-    if let Some(ref mut conn) = calibre_conn {
-        utils::verify_and_repair_timestamps(conn, appdb_conn.as_mut())?;
+    // Fail fast on unknown users, before any database writes or long-running imports
+    if let Some(ref conn) = appdb_conn {
+        appdb::validate_usernames(conn, &cli.command.usernames())?;
+        let default_user = cli.default_user.clone()
+            .or_else(|| std::env::var("CWH_DEFAULT_USER").ok().filter(|name| !name.is_empty()));
+        if let Some(ref name) = default_user {
+            appdb::set_default_user(conn, name)?;
+        }
     }
 
+    // Verify and repair any NULL timestamps in both databases. Read-only connections
+    // only report problems so they never write to a database in use by Calibre-Web.
+    if let Some(ref mut conn) = calibre_conn
+        && !cli.no_auto_repair {
+            utils::verify_and_repair_timestamps(conn, appdb_conn.as_mut(), !read_only)?;
+        }
+
     match cli.command {
-        Commands::Add { shelf, username, dry_run } => {
-            let calibre_conn = calibre_conn.as_mut().context("--metadata-file is required for add command")?;
-            let metadata_file = metadata_file.as_ref().unwrap();
+        Commands::Add { shelf, username, dry_run, report_file, skip_content_duplicates, added_date, cover_quality, max_cover_pixels, cover_max_width, cover_max_height, fail_on_cover_errors, generate_placeholder_cover, placeholder_cover_color, source_encoding, for_kobo, path_pattern, shard_by_id, strict, lang_map_file, fail_fast, comments_strategy, preserve_comments, preserve_timestamps, skip_hash_check, skip_existing, only_new, only_updated, max_file_size_mb, ignore_space, order_by, prefer_format, progress_json, canonical_author_sort, author_surname_prefixes, no_author_sort_inversion, author_sort_map, int_series_index, isbn13, keep_isbn10, sniff_format, series_regex, stdin, validate_only, report_unmapped_languages, auto_shelf_by_tag, tag_shelf_map, overrides } => {
             if shelf.is_some() && cli.appdb_file.is_none() {
                 anyhow::bail!("--appdb-file is required when specifying a shelf");
             }
             
             if dry_run {
-                println!("🧪 DRY RUN MODE: No changes will be made to databases or files\n");
+                say!("🧪 DRY RUN MODE: No changes will be made to databases or files\n");
             }
             
+            let added_date = added_date.map(|date| {
+                let added = date.and_hms_opt(0, 0, 0).expect("midnight is always valid").and_utc();
+                if added > Utc::now() {
+                    say!("⚠️  --added-date {} is in the future.\n", date);
+                }
+                added
+            });
+
+            let author_sort_map = match &author_sort_map {
+                Some(path) => {
+                    let map = load_author_sort_map(path)?;
+                    say!("🔤 Loaded {} author sort name(s) from {:?}\n", map.len(), path);
+                    Some(map)
+                }
+                None => None,
+            };
+
+            let tag_shelf_map = match &tag_shelf_map {
+                Some(path) => {
+                    let map = load_tag_shelf_map(path)?;
+                    say!("🏷️  Loaded {} tag-to-shelf mapping(s) from {:?}\n", map.len(), path);
+                    Some(map)
+                }
+                None => None,
+            };
+            if auto_shelf_by_tag && !validate_only && appdb_conn.is_none() {
+                anyhow::bail!("--auto-shelf-by-tag needs Calibre-Web's app.db (--appdb-file)");
+            }
+
+            let path_pattern = if shard_by_id {
+                format!("{}{}", utils::SHARD_PATH_PREFIX, path_pattern)
+            } else {
+                path_pattern
+            };
+
+            let language_map = match &lang_map_file {
+                Some(path) => {
+                    let map = epub::load_language_map(path)?;
+                    say!("🌐 Loaded {} language mapping(s) from {:?}\n", map.len(), path);
+                    map
+                }
+                None => HashMap::new(),
+            };
+
+            let mut options = AddOptions {
+                shelf_name: shelf.as_deref(),
+                username: username.as_deref(),
+                dry_run,
+                report_file: report_file.as_deref(),
+                skip_content_duplicates,
+                added_date,
+                cover_options: models::CoverOptions {
+                    quality: cover_quality,
+                    max_pixels: max_cover_pixels,
+                    max_width: cover_max_width,
+                    max_height: cover_max_height,
+                    ignore_errors: !fail_on_cover_errors,
+                    placeholder_background: generate_placeholder_cover.then_some(placeholder_cover_color),
+                },
+                for_kobo,
+                path_pattern: &path_pattern,
+                fail_fast,
+                comments_strategy: if preserve_comments { cli::CommentsStrategy::Preserve } else { comments_strategy },
+                preserve_timestamps,
+                skip_existing,
+                only_new,
+                only_updated,
+                max_file_size_mb,
+                ignore_space,
+                order_by,
+                prefer_format: &prefer_format,
+                checksum: cli.checksum,
+                skip_hash_check,
+                temp_dir: cli.temp_dir.as_deref(),
+                progress_json,
+                author_sort: if no_author_sort_inversion {
+                    models::AuthorSortMethod::AsIs
+                } else if canonical_author_sort {
+                    models::AuthorSortMethod::Calibre { surname_prefixes: author_surname_prefixes }
+                } else {
+                    models::AuthorSortMethod::CalibreWeb
+                },
+                author_sort_map,
+                int_series_index,
+                report_unmapped_languages,
+                auto_shelf_by_tag,
+                tag_shelf_map,
+                metadata_options: models::MetadataOptions {
+                    source_encoding,
+                    strict,
+                    language_map,
+                    isbn13,
+                    keep_isbn10,
+                    sniff_format,
+                    series_regex,
+                },
+                overrides: *overrides,
+            };
+
+            if validate_only {
+                let files = match (cli.epub_file, cli.epub_dir) {
+                    (Some(epub_file), None) if !stdin => vec![epub_file],
+                    (None, Some(epub_dir)) if !stdin => find_book_files(&epub_dir)
+                        .with_context(|| format!("Failed to read directory {:?}", epub_dir))?,
+                    (None, None) if stdin => read_stdin_file_list()?,
+                    _ => anyhow::bail!("--validate-only needs exactly one of --epub-file, --epub-dir or --stdin"),
+                };
+                return validate_books_flow(&files, &options);
+            }
+
+            let calibre_conn = calibre_conn.as_mut().context("--metadata-file is required for add command")?;
+            let metadata_file = metadata_file.as_ref().unwrap();
+
+            if stdin && (cli.epub_file.is_some() || cli.epub_dir.is_some()) {
+                anyhow::bail!("--stdin can't be combined with --epub-file or --epub-dir");
+            }
+            if stdin {
+                if !options.overrides.is_empty() {
+                    say!("⚠️  Metadata overrides (--title, --author, etc.) can't apply to every file in a list; ignoring.\n");
+                    options.overrides = options.overrides.for_every_file();
+                }
+                let files = read_stdin_file_list()?;
+                if files.is_empty() {
+                    say!("⚠️  No book files to add were given on stdin.");
+                    return Ok(());
+                }
+                return add_files_flow(calibre_conn, appdb_conn.as_mut(), metadata_file, files, &options);
+            }
+
             // Validate that exactly one of epub_file or epub_dir is provided
             match (cli.epub_file, cli.epub_dir) {
                 (Some(epub_file), None) => {
-                    add_book_flow(calibre_conn, appdb_conn.as_mut(), metadata_file, &epub_file, shelf.as_deref(), username.as_deref(), dry_run)?;
+                    if options.report_file.is_some() {
+                        say!("⚠️  --report-file only applies to --epub-dir and --stdin imports; ignoring.\n");
+                    }
+                    if options.skip_existing {
+                        say!("⚠️  --skip-existing only applies to --epub-dir and --stdin imports; ignoring.\n");
+                    }
+                    let mut unmapped_languages = BTreeMap::new();
+                    add_book_flow(calibre_conn, appdb_conn.as_mut(), metadata_file, &epub_file, &options, &mut unmapped_languages)?;
+                    if options.report_unmapped_languages {
+                        print_unmapped_languages(&unmapped_languages);
+                    }
                 }
                 (None, Some(epub_dir)) => {
-                    add_directory_flow(calibre_conn, appdb_conn.as_mut(), metadata_file, &epub_dir, shelf.as_deref(), username.as_deref(), dry_run)?;
+                    if !options.overrides.is_empty() {
+                        say!("⚠️  Metadata overrides (--title, --author, etc.) can't apply to every file in a directory; ignoring.\n");
+                        options.overrides = options.overrides.for_every_file();
+                    }
+                    add_directory_flow(calibre_conn, appdb_conn.as_mut(), metadata_file, &epub_dir, &options)?;
                 }
                 (Some(_), Some(_)) => {
                     anyhow::bail!("Cannot specify both --epub-file and --epub-dir. Please use one or the other.");
                 }
                 (None, None) => {
-                    anyhow::bail!("Either --epub-file or --epub-dir is required for the add command");
+                    anyhow::bail!("One of --epub-file, --epub-dir or --stdin is required for the add command");
                 }
             }
         }
-        Commands::List { shelf, unshelved, verbose } => {
+        Commands::List { mut shelf, mut unshelved, username, include_archived, since_id, series, min_series_index, max_series_index, latest_per_series, verbose, count, full_paths, compact, mut group_by_shelf } => {
             let calibre_conn = calibre_conn.as_ref().context("--metadata-file is required for list command")?;
-            calibre::list_books(calibre_conn, appdb_conn.as_ref(), shelf.as_deref(), unshelved, verbose)?;
+            if appdb_conn.is_none() {
+                if let Some(shelf) = shelf.take() {
+                    say!("⚠️  Warning: --shelf needs Calibre-Web's app.db (--appdb-file); ignoring --shelf '{}' and listing all books.", shelf);
+                }
+                if unshelved {
+                    say!("⚠️  Warning: --unshelved needs Calibre-Web's app.db (--appdb-file); ignoring it and listing all books.");
+                    unshelved = false;
+                }
+                if include_archived {
+                    say!("⚠️  Warning: --include-archived has no effect without Calibre-Web's app.db (--appdb-file).");
+                }
+                if group_by_shelf {
+                    say!("⚠️  Warning: --group-by-shelf needs Calibre-Web's app.db (--appdb-file); listing all books without grouping.");
+                    group_by_shelf = false;
+                }
+            }
+            let options = models::ListOptions {
+                shelf_name: shelf.as_deref(),
+                unshelved,
+                username: username.as_deref(),
+                include_archived,
+                since_id,
+                series: series.as_deref(),
+                min_series_index,
+                max_series_index,
+                latest_per_series,
+                verbose,
+                count_only: count,
+                full_paths,
+                compact,
+                group_by_shelf,
+            };
+            let metadata_file = metadata_file.as_ref().unwrap();
+            calibre::list_books(calibre_conn, appdb_conn.as_ref(), library_dir(metadata_file), &options)?;
         }
         Commands::ListShelves => {
             appdb::list_shelves(appdb_conn.as_ref())?;
         }
-        Commands::Delete { book_id } => {
+        Commands::Delete { book_id, book_id_file, db_only, files_only } => {
+            let book_ids = book_ids(book_id, book_id_file.as_deref())?;
             let calibre_conn = calibre_conn.as_mut().context("--metadata-file is required for delete command")?;
             let metadata_file = metadata_file.as_ref().unwrap();
-            calibre::delete_book(calibre_conn, appdb_conn.as_ref(), metadata_file, book_id)?;
+            let scope = if db_only {
+                models::DeleteScope::DatabaseOnly
+            } else if files_only {
+                models::DeleteScope::FilesOnly
+            } else {
+                models::DeleteScope::All
+            };
+            calibre::delete_books(calibre_conn, appdb_conn.as_ref(), metadata_file, &book_ids, scope)?;
         }
-        Commands::CleanShelves => {
+        Commands::CleanShelves { report_only } => {
             let calibre_conn = calibre_conn.as_ref().context("--metadata-file is required for clean-shelves command")?;
             if let Some(ref mut conn) = appdb_conn {
-                if let Some(ref appdb_path) = cli.appdb_file {
-                    println!("📦 Creating app.db backup before cleaning shelves...");
-                    crate::utils::backup_database(appdb_path, "clean_shelves")
-                        .context("Failed to backup app.db")?;
-                }
-                appdb::clean_empty_shelves(conn, calibre_conn)?;
+                if !report_only
+                    && let Some(ref appdb_path) = cli.appdb_file {
+                        say!("📦 Creating app.db backup before cleaning shelves...");
+                        crate::utils::backup_database(appdb_path, "clean_shelves")
+                            .context("Failed to backup app.db")?;
+                    }
+                appdb::clean_empty_shelves(conn, calibre_conn, report_only)?;
             }
         }
         Commands::InspectDb => {
             let calibre_conn = calibre_conn.as_ref().context("--metadata-file is required for inspect-db command")?;
-            appdb::inspect_databases(appdb_conn.as_ref(), calibre_conn)?;
+            appdb::inspect_databases(appdb_conn.as_ref(), calibre_conn, cli.format)?;
         }
-        Commands::CleanDb => {
+        Commands::CleanDb { verify_paths, trim_orphan_data_rows } => {
             let calibre_conn = calibre_conn.as_mut().context("--metadata-file is required for clean-db command")?;
             let metadata_file = metadata_file.as_ref().unwrap();
             
             // Create backup before cleanup
-            println!("📦 Creating database backups before cleanup...");
+            say!("📦 Creating database backups before cleanup...");
             crate::utils::backup_database(metadata_file, "clean_db")
                 .context("Failed to backup metadata.db")?;
             
@@ -123,40 +409,239 @@ fn main() -> Result<()> {
                     .context("Failed to backup app.db")?;
             }
             
-            cleanup::cleanup_databases(calibre_conn, appdb_conn.as_mut(), &library_dir(metadata_file).to_path_buf())?;
+            if verify_paths {
+                cleanup::verify_book_paths(calibre_conn, library_dir(metadata_file), true)?;
+            }
+            cleanup::cleanup_databases(calibre_conn, appdb_conn.as_mut(), &library_dir(metadata_file).to_path_buf(), trim_orphan_data_rows)?;
+        }
+        Commands::PruneMetadata => {
+            let calibre_conn = calibre_conn.as_mut().context("--metadata-file is required for prune-metadata command")?;
+            let metadata_file = metadata_file.as_ref().unwrap();
+
+            say!("📦 Creating metadata.db backup before pruning...");
+            crate::utils::backup_database(metadata_file, "prune_metadata")
+                .context("Failed to backup metadata.db")?;
+
+            cleanup::prune_metadata(calibre_conn)?;
+        }
+        Commands::PruneIdentifiers => {
+            let calibre_conn = calibre_conn.as_mut().context("--metadata-file is required for prune-identifiers command")?;
+            let metadata_file = metadata_file.as_ref().unwrap();
+
+            say!("📦 Creating metadata.db backup before pruning...");
+            crate::utils::backup_database(metadata_file, "prune_identifiers")
+                .context("Failed to backup metadata.db")?;
+
+            cleanup::prune_identifiers(calibre_conn)?;
+        }
+        Commands::Normalize { dry_run } => {
+            let calibre_conn = calibre_conn.as_mut().context("--metadata-file is required for normalize command")?;
+            let metadata_file = metadata_file.as_ref().unwrap();
+
+            if !dry_run {
+                say!("📦 Creating metadata.db backup before normalizing...");
+                crate::utils::backup_database(metadata_file, "normalize")
+                    .context("Failed to backup metadata.db")?;
+            }
+
+            cleanup::normalize_library(calibre_conn, library_dir(metadata_file), dry_run)?;
+        }
+        Commands::RepairUuids { dry_run } => {
+            let calibre_conn = calibre_conn.as_mut().context("--metadata-file is required for repair-uuids command")?;
+            let metadata_file = metadata_file.as_ref().unwrap();
+
+            if !dry_run {
+                say!("📦 Creating metadata.db backup before repairing UUIDs...");
+                crate::utils::backup_database(metadata_file, "repair_uuids")
+                    .context("Failed to backup metadata.db")?;
+            }
+
+            cleanup::repair_uuids(calibre_conn, dry_run)?;
+        }
+        Commands::ApplyAuthorSorts { map_file, dry_run } => {
+            let calibre_conn = calibre_conn.as_mut().context("--metadata-file is required for apply-author-sorts command")?;
+            let metadata_file = metadata_file.as_ref().unwrap();
+            let author_sorts = load_author_sort_map(&map_file)?;
+
+            if !dry_run {
+                say!("📦 Creating metadata.db backup before applying author sorts...");
+                crate::utils::backup_database(metadata_file, "apply_author_sorts")
+                    .context("Failed to backup metadata.db")?;
+            }
+
+            cleanup::apply_author_sorts(calibre_conn, &author_sorts, dry_run)?;
         }
-        Commands::FixKoboSync => {
+        Commands::ReindexSeries { series, dry_run } => {
+            let calibre_conn = calibre_conn.as_mut().context("--metadata-file is required for reindex-series command")?;
+            let metadata_file = metadata_file.as_ref().unwrap();
+
+            if !dry_run {
+                say!("📦 Creating metadata.db backup before reindexing series...");
+                crate::utils::backup_database(metadata_file, "reindex_series")
+                    .context("Failed to backup metadata.db")?;
+            }
+
+            cleanup::reindex_series(calibre_conn, series.as_deref(), dry_run)?;
+        }
+        Commands::CheckFormats { fix, verify_paths } => {
+            let calibre_conn = calibre_conn.as_mut().context("--metadata-file is required for check-formats command")?;
+            let metadata_file = metadata_file.as_ref().unwrap();
+
+            if fix {
+                say!("📦 Creating metadata.db backup before fixing formats...");
+                crate::utils::backup_database(metadata_file, "check_formats")
+                    .context("Failed to backup metadata.db")?;
+            }
+
+            if verify_paths {
+                cleanup::verify_book_paths(calibre_conn, library_dir(metadata_file), fix)?;
+            }
+            cleanup::check_formats(calibre_conn, library_dir(metadata_file), fix)?;
+        }
+        Commands::VerifyPaths { fix } => {
+            let calibre_conn = calibre_conn.as_mut().context("--metadata-file is required for verify-paths command")?;
+            let metadata_file = metadata_file.as_ref().unwrap();
+
+            if fix {
+                say!("📦 Creating metadata.db backup before repairing paths...");
+                crate::utils::backup_database(metadata_file, "verify_paths")
+                    .context("Failed to backup metadata.db")?;
+            }
+
+            cleanup::verify_book_paths(calibre_conn, library_dir(metadata_file), fix)?;
+        }
+        Commands::AuditLanguages => {
+            let calibre_conn = calibre_conn.as_ref().context("--metadata-file is required for audit-languages command")?;
+            let metadata_file = metadata_file.as_ref().unwrap();
+            calibre::audit_languages(calibre_conn, library_dir(metadata_file))?;
+        }
+        Commands::FixKoboSync { dry_run, username } => {
             if let Some(mut conn) = appdb_conn {
                 // Create backup before fixing Kobo sync
-                if let Some(ref appdb_path) = cli.appdb_file {
-                    println!("📦 Creating app.db backup before Kobo sync fix...");
-                    crate::utils::backup_database(appdb_path, "fix_kobo_sync")
-                        .context("Failed to backup app.db")?;
-                }
-                appdb::fix_kobo_sync_issues(&mut conn)?;
+                if !dry_run
+                    && let Some(ref appdb_path) = cli.appdb_file {
+                        say!("📦 Creating app.db backup before Kobo sync fix...");
+                        crate::utils::backup_database(appdb_path, "fix_kobo_sync")
+                            .context("Failed to backup app.db")?;
+                    }
+                appdb::fix_kobo_sync_issues(&mut conn, dry_run, username.as_deref())?;
             } else {
                 anyhow::bail!("--appdb-file is required for the fix-kobo-sync command");
             }
         }
-        Commands::DiagnoseKoboSync => {
+        Commands::DiagnoseKoboSync { username } => {
             let metadata_path = metadata_file.as_ref().context("metadata-file is required")?;
             let appdb_path = cli.appdb_file.as_ref().context("appdb-file is required")?;
             
-            appdb::diagnose_kobo_sync(appdb_path, metadata_path)?;
+            appdb::diagnose_kobo_sync(appdb_path, metadata_path, &db_config, username.as_deref())?;
+        }
+        Commands::FindByIdentifier { id_type, value } => {
+            let calibre_conn = calibre_conn.as_ref().context("--metadata-file is required for find-by-identifier command")?;
+            calibre::find_by_identifier(calibre_conn, &id_type, &value, cli.format)?;
+        }
+        Commands::DumpCover { book_id, output, width } => {
+            let calibre_conn = calibre_conn.as_ref().context("--metadata-file is required for dump-cover command")?;
+            let metadata_file = metadata_file.as_ref().unwrap();
+            calibre::dump_cover(calibre_conn, library_dir(metadata_file), book_id, &output, width)?;
+        }
+        Commands::DumpSchema => {
+            if calibre_conn.is_none() && appdb_conn.is_none() {
+                anyhow::bail!("--metadata-file or --appdb-file is required for dump-schema command");
+            }
+            db::dump_schema(calibre_conn.as_ref(), appdb_conn.as_ref())?;
+        }
+        Commands::CheckFk => {
+            if calibre_conn.is_none() && appdb_conn.is_none() {
+                anyhow::bail!("--metadata-file or --appdb-file is required for check-fk command");
+            }
+            let violations = db::check_foreign_keys(calibre_conn.as_ref(), appdb_conn.as_ref())?;
+            if violations > 0 {
+                anyhow::bail!("Found {} foreign key violation(s); run clean-db to remove dangling references", violations);
+            }
+        }
+        Commands::VerifyHashes { accept_changes } => {
+            let calibre_conn = calibre_conn.as_ref().context("--metadata-file is required for verify-hashes command")?;
+            let metadata_file = metadata_file.as_ref().unwrap();
+            hashes::verify_hashes(calibre_conn, library_dir(metadata_file), cli.checksum, accept_changes)?;
+        }
+        Commands::ShelfDiff { shelf_a, shelf_b, username } => {
+            let calibre_conn = calibre_conn.as_ref().context("--metadata-file is required for shelf-diff command")?;
+            let appdb_conn = appdb_conn.as_ref().context("--appdb-file is required for shelf-diff command")?;
+            appdb::shelf_diff(appdb_conn, calibre_conn, &shelf_a, &shelf_b, username.as_deref())?;
+        }
+        Commands::CopyShelf { source, target, source_user, target_user } => {
+            let conn = appdb_conn.as_mut().context("--appdb-file is required for copy-shelf command")?;
+            if let Some(ref appdb_path) = cli.appdb_file {
+                say!("📦 Creating app.db backup before copying shelf...");
+                crate::utils::backup_database(appdb_path, "copy_shelf")
+                    .context("Failed to backup app.db")?;
+            }
+            appdb::copy_shelf(conn, &source, &target, source_user.as_deref(), target_user.as_deref())?;
+        }
+        Commands::TouchShelf { shelf, username } => {
+            let conn = appdb_conn.as_mut().context("--appdb-file is required for touch-shelf command")?;
+            if let Some(ref appdb_path) = cli.appdb_file {
+                say!("📦 Creating app.db backup before touching shelf...");
+                crate::utils::backup_database(appdb_path, "touch_shelf")
+                    .context("Failed to backup app.db")?;
+            }
+            appdb::touch_shelf(conn, &shelf, username.as_deref())?;
         }
-        Commands::AddToShelf { book_id, shelf, username } => {
+        Commands::ExportShelf { shelf, output_dir, since_id, username } => {
+            let calibre_conn = calibre_conn.as_ref().context("--metadata-file is required for export-shelf command")?;
+            let appdb_conn = appdb_conn.as_ref().context("--appdb-file is required for export-shelf command")?;
+            let metadata_file = metadata_file.as_ref().unwrap();
+            let mut book_ids = appdb::shelf_book_ids(appdb_conn, &shelf, username.as_deref())?;
+            if let Some(since) = since_id {
+                book_ids.retain(|&id| id > since);
+            }
+            say!("📤 Exporting {} book(s) from shelf '{}' to {:?}...", book_ids.len(), shelf, output_dir);
+            calibre::export_books(calibre_conn, library_dir(metadata_file), &book_ids, &output_dir)?;
+        }
+        Commands::AddToShelf { book_id, book_id_file, shelf, username } => {
             let appdb_path = cli.appdb_file.as_ref().context("appdb-file is required")?;
-            let mut appdb_conn = appdb::open_appdb(Some(appdb_path))?.context("Failed to open app.db")?;
+            let mut appdb_conn = appdb::open_appdb(Some(appdb_path), &db_config)?.context("Failed to open app.db")?;
+            let mut book_ids = book_ids(book_id, book_id_file.as_deref())?;
             
-            // Validate the book exists in metadata.db if available
+            // Validate the books exist in metadata.db if available
             if let Some(ref _metadata_file) = metadata_file {
                 let calibre_conn = calibre_conn.as_ref().context("Failed to get Calibre connection")?;
+                if book_id_file.is_none() {
+                    crate::utils::validate_foreign_key(calibre_conn, "books", book_ids[0], "book")
+                        .context("Book does not exist in Calibre library")?;
+                } else {
+                    book_ids.retain(|&id| {
+                        let exists = crate::utils::validate_foreign_key(calibre_conn, "books", id, "book").is_ok();
+                        if !exists {
+                            say!(" -> Skipping book {}: not found in the Calibre library.", id);
+                        }
+                        exists
+                    });
+                }
+            }
+            
+            if book_id_file.is_some() {
+                appdb::add_existing_books_to_shelf(&mut appdb_conn, &book_ids, &shelf, username.as_deref())?;
+            } else {
+                appdb::add_existing_book_to_shelf(&mut appdb_conn, book_ids[0], &shelf, username.as_deref())
+                    .map_err(|e| anyhow::anyhow!("{}", e))?;
+            }
+        }
+        Commands::MoveToShelf { book_id, from_shelf, to_shelf, username } => {
+            let conn = appdb_conn.as_mut().context("--appdb-file is required for move-to-shelf command")?;
+            if let Some(calibre_conn) = calibre_conn.as_ref() {
                 crate::utils::validate_foreign_key(calibre_conn, "books", book_id, "book")
                     .context("Book does not exist in Calibre library")?;
             }
-            
-            appdb::add_existing_book_to_shelf(&mut appdb_conn, book_id, &shelf, username.as_deref())
-                .map_err(|e| anyhow::anyhow!("{}", e))?;
+            if let Some(ref appdb_path) = cli.appdb_file {
+                say!("📦 Creating app.db backup before moving book...");
+                crate::utils::backup_database(appdb_path, "move_to_shelf")
+                    .context("Failed to backup app.db")?;
+            }
+            appdb::move_book_to_shelf(conn, book_id, &from_shelf, &to_shelf, username.as_deref())?;
+        }
+        Commands::ListBackups | Commands::RestoreBackup { .. } => {
+            unreachable!("backup commands are handled before the databases are opened")
         }
 
     }
@@ -164,40 +649,106 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Options for the `add` command shared by the single-file and directory flows.
+struct AddOptions<'a> {
+    shelf_name: Option<&'a str>,
+    username: Option<&'a str>,
+    dry_run: bool,
+    report_file: Option<&'a Path>,
+    skip_content_duplicates: bool,
+    added_date: Option<DateTime<Utc>>,
+    cover_options: models::CoverOptions,
+    for_kobo: bool,
+    path_pattern: &'a str,
+    fail_fast: bool,
+    comments_strategy: cli::CommentsStrategy,
+    preserve_timestamps: bool,
+    skip_existing: bool,
+    only_new: bool,
+    only_updated: bool,
+    max_file_size_mb: Option<u64>,
+    ignore_space: bool,
+    order_by: cli::ImportOrder,
+    prefer_format: &'a [String],
+    checksum: cli::ChecksumAlgorithm,
+    skip_hash_check: bool,
+    temp_dir: Option<&'a Path>,
+    progress_json: bool,
+    author_sort: models::AuthorSortMethod,
+    author_sort_map: Option<HashMap<String, String>>,
+    int_series_index: bool,
+    report_unmapped_languages: bool,
+    auto_shelf_by_tag: bool,
+    tag_shelf_map: Option<HashMap<String, String>>,
+    metadata_options: models::MetadataOptions,
+    overrides: MetadataOverrides,
+}
+
 /// Handles the flow for adding a new book.
 fn add_book_flow(
     calibre_conn: &mut Connection,
-    appdb_conn: Option<&mut Connection>,
+    mut appdb_conn: Option<&mut Connection>,
     library_db_path: &Path,
     epub_file: &Path,
-    shelf_name: Option<&str>,
-    username: Option<&str>,
-    dry_run: bool,
-) -> Result<()> {
+    options: &AddOptions,
+    unmapped_languages: &mut BTreeMap<String, usize>,
+) -> Result<models::UpsertResult> {
+    let dry_run = options.dry_run;
+
     if !epub_file.exists() {
-        anyhow::bail!("The specified EPUB file does not exist.");
+        anyhow::bail!("The specified book file does not exist.");
     }
 
-    println!("📚 Reading EPUB metadata...");
-    let metadata = epub::get_epub_metadata(epub_file)?;
+    if let Some(reason) = oversized_file_reason(epub_file, options.max_file_size_mb)? {
+        anyhow::bail!("Skipping {}: {}", epub_file.display(), reason);
+    }
+
+    // A read-only or full library volume would otherwise only fail after the database row exists
+    if !dry_run {
+        check_library_writable(library_dir(library_db_path))?;
+    }
+
+    say!("📚 Reading book metadata...");
+    let mut metadata = epub::get_book_metadata(epub_file, &options.metadata_options)?;
+    if options.for_kobo && metadata.format()?.0 != "KEPUB" {
+        say!("⚠️  {} is not a .kepub file; Calibre-Web will need to convert it before syncing to Kobo.", epub_file.display());
+    }
+    apply_metadata_overrides(&mut metadata, &options.overrides, &options.metadata_options.language_map);
+    if options.int_series_index {
+        metadata.series_index = metadata.series_index.map(utils::snap_series_index);
+    }
+    for code in &metadata.unmapped_languages {
+        *unmapped_languages.entry(code.clone()).or_default() += 1;
+    }
 
     // Language code was already normalized in get_epub_metadata
 
-    println!(" -> Title: {}", metadata.title);
-    println!(" -> Author: {}", metadata.author);
+    say!(" -> Title: {}", metadata.title);
+    say!(" -> Author: {}", metadata.author);
     if let Some(series) = &metadata.series {
-        println!(" -> Series: {} {}", series, 
-            metadata.series_index.map_or(String::new(), |idx| format!("#{}", idx)));
+        say!(" -> Series: {} {}", series, 
+            metadata.series_index.map_or(String::new(), |idx| format!("#{}", utils::format_series_index(idx))));
     }
     if let Some(publisher) = &metadata.publisher {
-        println!(" -> Publisher: {}", publisher);
+        say!(" -> Publisher: {}", publisher);
     }
     if let Some(pubdate) = metadata.pubdate {
-        println!(" -> Published: {}", pubdate.format("%Y-%m-%d"));
+        say!(" -> Published: {}", pubdate.format("%Y-%m-%d"));
     }
 
-    println!("✒️ Writing to Calibre database...");
-    let upsert_result = calibre::add_book_to_db(calibre_conn, &metadata, library_dir(library_db_path), epub_file, dry_run)?;
+    say!("✒️ Writing to Calibre database...");
+    let write_options = models::BookWriteOptions {
+        added_date: options.added_date,
+        path_pattern: options.path_pattern,
+        author_sort: if options.overrides.corporate_author { models::AuthorSortMethod::AsIs } else { options.author_sort },
+        author_sort_map: options.author_sort_map.as_ref(),
+        comments_strategy: options.comments_strategy,
+        prefer_format: options.prefer_format,
+        checksum: options.checksum,
+        skip_hash_check: options.skip_hash_check,
+        dry_run,
+    };
+    let upsert_result = calibre::add_book_to_db(calibre_conn, &metadata, library_dir(library_db_path), epub_file, &write_options)?;
 
     let book_id = upsert_result.book_id();
     let book_path = upsert_result.book_path().to_string();
@@ -206,44 +757,79 @@ fn add_book_flow(
 
     match &upsert_result {
         models::UpsertResult::Created { book_id, .. } => {
-            println!(" -> Successfully created database entry with Book ID: {}", book_id);
+            say!(" -> Successfully created database entry with Book ID: {}", book_id);
         }
         models::UpsertResult::Updated { book_id, .. } => {
-            println!(" -> Successfully updated database entry for Book ID: {}", book_id);
+            say!(" -> Successfully updated database entry for Book ID: {}", book_id);
         }
         models::UpsertResult::NoChanges { book_id, .. } => {
-            println!(" -> No changes needed for Book ID: {}", book_id);
-        }
-    }
-
-    // Clap's `requires` attribute ensures appdb_conn is Some if shelf_name is Some.
-    if let (Some(name), Some(conn)) = (shelf_name, appdb_conn) {
-        if dry_run {
-            println!("📚 Would add book to shelf '{}'", name);
-            println!("   [DRY RUN] Would update app.db with shelf assignment");
-        } else {
-            appdb::add_book_to_shelf_in_appdb(conn, book_id, name, username)?;
+            say!(" -> No changes needed for Book ID: {}", book_id);
         }
     }
 
     if !skip_file_operations && !dry_run {
-        println!("🚚 Updating files in library...");
-        let cover_saved = epub::update_book_files(library_dir(library_db_path), epub_file, &book_path, is_update, &metadata)?;
-        println!(" -> File copied successfully.");
+        say!("🚚 Updating files in library...");
+        let file_options = models::BookFileOptions {
+            cover: &options.cover_options,
+            preserve_timestamps: options.preserve_timestamps,
+            temp_dir: options.temp_dir,
+        };
+        let cover_saved = match epub::update_book_files(library_dir(library_db_path), epub_file, &book_path, is_update, &metadata, &file_options) {
+            Ok(cover_saved) => cover_saved,
+            Err(e) if !is_update && utils::is_storage_full(&e) => {
+                // Don't leave a database row pointing at a half-copied file
+                calibre::remove_created_book(calibre_conn, library_dir(library_db_path), book_id, &book_path)?;
+                say!(" -> Disk full; removed the new database entry for Book ID {}.", book_id);
+                return Err(e.context("Ran out of disk space while copying the book into the library"));
+            }
+            Err(e) => return Err(e),
+        };
+        say!(" -> File copied successfully.");
 
         if cover_saved {
             calibre_conn.execute("UPDATE books SET has_cover = 1 WHERE id = ?1", params![book_id])?;
-            println!(" -> Updated database to reflect cover image.");
+            say!(" -> Updated database to reflect cover image.");
         }
     } else if !skip_file_operations && dry_run {
-        println!("� Would update files in library...");
-        println!("   [DRY RUN] Would copy EPUB file to: {}", book_path);
-        println!("   [DRY RUN] Would extract and resize cover image");
+        say!("� Would update files in library...");
+        say!("   [DRY RUN] Would copy book file to: {}", book_path);
+        say!("   [DRY RUN] Would extract and resize cover image");
     } else {
         if dry_run {
-            println!("📁 Would skip file operations (no changes needed).");
+            say!("📁 Would skip file operations (no changes needed).");
         } else {
-            println!("�📁 Skipping file operations (no changes needed).");
+            say!("�📁 Skipping file operations (no changes needed).");
+        }
+    }
+
+    // Shelve the book only once its files are in place.
+    // Clap's `requires` attribute ensures appdb_conn is Some if shelf_name is Some.
+    if let (Some(name), Some(conn)) = (options.shelf_name, appdb_conn.as_deref_mut()) {
+        if dry_run {
+            say!("📚 Would add book to shelf '{}'", name);
+            say!("   [DRY RUN] Would update app.db with shelf assignment");
+            if options.for_kobo {
+                say!("   [DRY RUN] Would enable Kobo sync on the shelf and create reading state records");
+            }
+        } else {
+            appdb::add_book_to_shelf_in_appdb(conn, book_id, name, options.username)?;
+            if options.for_kobo {
+                appdb::prepare_book_for_kobo(conn, book_id, name, options.username)?;
+            }
+        }
+    }
+
+    if options.auto_shelf_by_tag && let Some(conn) = appdb_conn {
+        for (tag, shelf) in tag_shelves(&metadata.tags, options.tag_shelf_map.as_ref()) {
+            if options.shelf_name == Some(shelf.as_str()) {
+                continue;
+            }
+            if dry_run {
+                say!("📚 Would add book to shelf '{}' (tag '{}')", shelf, tag);
+            } else {
+                say!("🏷️  Tag '{}' files the book on shelf '{}'", tag, shelf);
+                appdb::add_book_to_shelf_in_appdb(conn, book_id, &shelf, options.username)?;
+            }
         }
     }
 
@@ -265,34 +851,107 @@ fn add_book_flow(
     // Check series status for feedback message
     let series_msg = if let Some(series) = &metadata.series {
         format!(" (part of series '{}'{})'", series,
-            metadata.series_index.map_or(String::new(), |idx| format!(" #{}", idx)))
+            metadata.series_index.map_or(String::new(), |idx| format!(" #{}", utils::format_series_index(idx))))
     } else {
         String::new()
     };
 
     let success_icon = if dry_run { "🧪" } else { "✅" };
-    println!("
+    say!("
 {} Success! '{}'{} has been {} your Calibre library.",
         success_icon, metadata.title, series_msg, action_str);
 
     if !skip_file_operations && !dry_run {
-        println!("   Please restart Calibre to see the new book.");
+        say!("   Please restart Calibre to see the new book.");
     } else if dry_run {
-        println!("   [DRY RUN] No actual changes were made.");
+        say!("   [DRY RUN] No actual changes were made.");
     }
 
-    Ok(())
+    Ok(upsert_result)
+}
+
+/// Replaces metadata read from the book file with any values given on the command line.
+fn apply_metadata_overrides(metadata: &mut models::BookMetadata, overrides: &MetadataOverrides, language_map: &HashMap<String, String>) {
+    if let Some(title) = &overrides.title {
+        metadata.title = title.clone();
+    }
+    if let Some(author) = &overrides.author {
+        metadata.author = author.clone();
+    }
+    if let Some(series) = &overrides.series {
+        metadata.series = Some(series.clone());
+    }
+    if let Some(series_index) = overrides.series_index {
+        metadata.series_index = Some(series_index);
+    }
+    if let Some(publisher) = &overrides.publisher {
+        metadata.publisher = Some(publisher.clone());
+    }
+    if !overrides.language.is_empty() {
+        metadata.languages.clear();
+        metadata.unmapped_languages.clear();
+        for language in &overrides.language {
+            let code = epub::normalize_language_code(language, language_map);
+            if !metadata.languages.contains(&code) {
+                metadata.languages.push(code);
+            }
+        }
+    }
+}
+
+/// Pairs each of a book's tags with the shelf `--auto-shelf-by-tag` files it on: the shelf the
+/// `--tag-shelf-map` gives for the tag (matched case-insensitively), or without a map, the shelf
+/// named like the tag. Tags missing from the map are skipped; each shelf appears once.
+fn tag_shelves(tags: &[String], tag_shelf_map: Option<&HashMap<String, String>>) -> Vec<(String, String)> {
+    let mut shelves: Vec<(String, String)> = Vec::new();
+    for tag in tags {
+        let shelf = match tag_shelf_map {
+            Some(map) => match map.get(&tag.to_lowercase()) {
+                Some(shelf) => shelf.clone(),
+                None => continue,
+            },
+            None => tag.clone(),
+        };
+        if !shelves.iter().any(|(_, existing)| existing.eq_ignore_ascii_case(&shelf)) {
+            shelves.push((tag.clone(), shelf));
+        }
+    }
+    shelves
+}
+
+/// Loads `--tag-shelf-map`: tags (matched case-insensitively) to shelf names.
+fn load_tag_shelf_map(path: &Path) -> Result<HashMap<String, String>> {
+    let mut map = HashMap::new();
+    for (tag, shelf) in utils::read_mapping_file(path, "tag-shelf map")? {
+        let shelf = shelf.trim();
+        if shelf.is_empty() {
+            anyhow::bail!("Empty shelf name for tag '{}' in tag-shelf map {:?}", tag, path);
+        }
+        map.insert(tag.trim().to_lowercase(), shelf.to_string());
+    }
+    Ok(map)
 }
 
-/// Handles the flow for adding all EPUB files in a directory.
+/// Loads an `--author-sort-map` or `apply-author-sorts` file, keyed by lowercased author name
+fn load_author_sort_map(path: &Path) -> Result<HashMap<String, String>> {
+    let mut map = HashMap::new();
+    for (author, sort) in utils::read_mapping_file(path, "author sort map")? {
+        let sort = sort.trim();
+        if sort.is_empty() {
+            anyhow::bail!("Empty sort name for author '{}' in author sort map {:?}", author, path);
+        }
+        map.insert(author.trim().to_lowercase(), sort.to_string());
+    }
+    Ok(map)
+}
+
+/// Handles the flow for adding all supported book files in a directory.
 fn add_directory_flow(
     calibre_conn: &mut Connection,
-    mut appdb_conn: Option<&mut Connection>,
+    appdb_conn: Option<&mut Connection>,
     library_db_path: &Path,
     epub_dir: &Path,
-    shelf_name: Option<&str>,
-    username: Option<&str>,
-    dry_run: bool,
+    options: &AddOptions,
 ) -> Result<()> {
     if !epub_dir.exists() {
         anyhow::bail!("The specified directory does not exist: {:?}", epub_dir);
@@ -302,71 +961,384 @@ fn add_directory_flow(
         anyhow::bail!("The specified path is not a directory: {:?}", epub_dir);
     }
 
-    println!("📁 Scanning directory for EPUB files: {:?}", epub_dir);
-    
-    // Find all EPUB files in the directory
-    let mut epub_files = Vec::new();
-    for entry in fs::read_dir(epub_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        
-        if path.is_file()
-            && let Some(extension) = path.extension() {
-                let ext_str = extension.to_string_lossy().to_lowercase();
-                if ext_str == "epub" || ext_str == "kepub" {
-                    epub_files.push(path);
-                }
-            }
-    }
+    say!("📁 Scanning directory for book files: {:?}", epub_dir);
     
+    let epub_files = find_book_files(epub_dir)?;
     if epub_files.is_empty() {
-        println!("⚠️  No EPUB files found in directory: {:?}", epub_dir);
+        say!("⚠️  No book files found in directory: {:?}", epub_dir);
         return Ok(());
     }
+    add_files_flow(calibre_conn, appdb_conn, library_db_path, epub_files, options)
+}
+
+/// Reads the newline-separated book file paths given to `add --stdin`. Blank lines are
+/// ignored; paths that don't exist or aren't a supported book format are skipped with a warning.
+fn read_stdin_file_list() -> Result<Vec<PathBuf>> {
+    say!("📥 Reading book file paths from stdin...");
+    let mut files = Vec::new();
+    for line in std::io::stdin().lines() {
+        let line = line.context("Failed to read the file list from stdin")?;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+        let path = PathBuf::from(line);
+        if !path.is_file() {
+            say!("⚠️  Skipping {:?}: no such file", path);
+        } else if !utils::has_book_extension(&path) {
+            say!("⚠️  Skipping {:?}: not a supported book format", path);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Imports a batch of book files, as listed from a directory or stdin, with per-file reporting.
+fn add_files_flow(
+    calibre_conn: &mut Connection,
+    mut appdb_conn: Option<&mut Connection>,
+    library_db_path: &Path,
+    mut epub_files: Vec<PathBuf>,
+    options: &AddOptions,
+) -> Result<()> {
+    if options.order_by != cli::ImportOrder::Filename {
+        epub_files = sort_by_metadata(epub_files, options.order_by, &options.metadata_options);
+    }
     
-    // Sort files for consistent processing order
-    epub_files.sort();
-    
-    println!("📚 Found {} EPUB file(s) to process:", epub_files.len());
+    say!("📚 Found {} book file(s) to process:", epub_files.len());
     for file in &epub_files {
-        println!("   - {}", file.file_name().unwrap_or_default().to_string_lossy());
+        say!("   - {}", file.file_name().unwrap_or_default().to_string_lossy());
     }
     
+    let total_files = epub_files.len();
+    progress(options, models::ProgressEvent::Start { total: total_files });
     let mut successful = 0;
     let mut failed = 0;
+    let mut skipped = 0;
+    let mut already_present = 0;
+    let mut too_large = 0;
+    let (mut created, mut updated, mut unchanged) = (0, 0, 0);
+    let mut reports = Vec::new();
+
+    if options.skip_content_duplicates {
+        say!("\n🔍 Hashing files to detect content duplicates...");
+        let mut seen_hashes: HashMap<String, PathBuf> = HashMap::new();
+        let mut unique_files = Vec::new();
+        for file in epub_files {
+            // Files that can't be hashed are left in so the normal flow reports the error
+            let Ok(hash) = utils::calculate_file_hash(&file, options.checksum) else {
+                unique_files.push(file);
+                continue;
+            };
+            if let Some(first) = seen_hashes.get(&hash) {
+                say!(" -> Skipping {} (same content as {})",
+                         file.file_name().unwrap_or_default().to_string_lossy(),
+                         first.file_name().unwrap_or_default().to_string_lossy());
+                let report = models::AddFileReport::skipped(&file, &format!("duplicate content of {}", first.display()));
+                progress(options, models::ProgressEvent::FileResult { index: None, report: &report });
+                reports.push(report);
+                skipped += 1;
+            } else {
+                seen_hashes.insert(hash, file.clone());
+                unique_files.push(file);
+            }
+        }
+        if skipped == 0 {
+            say!(" -> No content duplicates found.");
+        }
+        epub_files = unique_files;
+    }
     
-    println!("\n🚀 Starting batch processing...\n");
+    if !options.dry_run {
+        check_disk_space(library_dir(library_db_path), &epub_files, options.ignore_space)?;
+    }
+
+    say!("\n🚀 Starting batch processing...\n");
     
+    let mut abort_error = None;
+    let mut unmapped_languages = BTreeMap::new();
+    let filter_output = options.only_new || options.only_updated;
     for (index, epub_file) in epub_files.iter().enumerate() {
-        println!("📖 Processing ({}/{}) - {}", 
+        if filter_output {
+            output::start_capture();
+        }
+        say!("📖 Processing ({}/{}) - {}", 
                  index + 1, 
                  epub_files.len(), 
                  epub_file.file_name().unwrap_or_default().to_string_lossy());
+        progress(options, models::ProgressEvent::FileBegin { index: index + 1, total: epub_files.len(), file: epub_file });
         
-        match add_book_flow(calibre_conn, appdb_conn.as_deref_mut(), library_db_path, epub_file, shelf_name, username, dry_run) {
-            Ok(()) => {
-                successful += 1;
-                println!("   ✅ Success!\n");
+        let report = if let Ok(Some(reason)) = oversized_file_reason(epub_file, options.max_file_size_mb) {
+            too_large += 1;
+            say!("   ⏭️  Skipped: {}\n", reason);
+            models::AddFileReport::skipped(epub_file, &reason)
+        } else if options.skip_existing
+            && let Ok(Some(book_id)) = find_existing_book(calibre_conn, epub_file, options)
+        {
+            already_present += 1;
+            say!("   ⏭️  Skipped (already present as Book ID {})\n", book_id);
+            models::AddFileReport {
+                book_id: Some(book_id),
+                ..models::AddFileReport::skipped(epub_file, "already present")
             }
-            Err(e) => {
-                failed += 1;
-                println!("   ❌ Failed: {}\n", e);
-                // Continue processing other files even if one fails
+        } else {
+            match add_book_flow(calibre_conn, appdb_conn.as_deref_mut(), library_db_path, epub_file, options, &mut unmapped_languages) {
+                Ok(result) => {
+                    successful += 1;
+                    match result {
+                        models::UpsertResult::Created { .. } => created += 1,
+                        models::UpsertResult::Updated { .. } => updated += 1,
+                        models::UpsertResult::NoChanges { .. } => unchanged += 1,
+                    }
+                    say!("   ✅ Success!\n");
+                    models::AddFileReport::from_result(epub_file, &result)
+                }
+                Err(e) => {
+                    failed += 1;
+                    say!("   ❌ Failed: {}\n", e);
+                    let report = models::AddFileReport::failed(epub_file, &e);
+                    if options.fail_fast {
+                        abort_error = Some(e);
+                    }
+                    // Otherwise continue processing other files even if one fails
+                    report
+                }
             }
+        };
+        if filter_output {
+            let lines = output::finish_capture();
+            let shown = match report.outcome {
+                "created" => options.only_new,
+                "updated" => options.only_updated,
+                "failed" => true,
+                _ => false,
+            };
+            if shown {
+                lines.into_iter().for_each(output::emit);
+            }
+        }
+        progress(options, models::ProgressEvent::FileResult { index: Some(index + 1), report: &report });
+        reports.push(report);
+        if abort_error.is_some() {
+            break;
         }
     }
     
     // Summary
-    println!("📊 Batch processing complete:");
-    println!("   ✅ Successfully processed: {}", successful);
+    say!("📊 Batch processing complete:");
+    say!("   ✅ Successfully processed: {}", successful);
+    say!("      ✨ New: {}  🔄 Updated: {}  ⏭️  Unchanged: {}", created, updated, unchanged);
     if failed > 0 {
-        println!("   ❌ Failed: {}", failed);
+        say!("   ❌ Failed: {}", failed);
+    }
+    if skipped > 0 {
+        say!("   ⏭️  Skipped duplicates: {}", skipped);
+    }
+    if already_present > 0 {
+        say!("   ⏭️  Skipped (already present): {}", already_present);
+    }
+    if too_large > 0 {
+        say!("   ⏭️  Skipped (too large): {}", too_large);
+    }
+    say!("   📚 Total files: {}", total_files);
+    if abort_error.is_some() {
+        let not_processed = total_files - successful - failed - skipped - already_present - too_large;
+        say!("   ⚠️  Stopped at the first failure (--fail-fast); {} file(s) not processed", not_processed);
+    }
+
+    progress(options, models::ProgressEvent::Complete {
+        successful,
+        failed,
+        skipped: skipped + already_present + too_large,
+        total: total_files,
+    });
+
+    if let Some(report_path) = options.report_file {
+        write_add_report(report_path, &reports)?;
+        say!("   📝 Report written to: {:?}", report_path);
+    }
+    if options.report_unmapped_languages {
+        print_unmapped_languages(&unmapped_languages);
     }
-    println!("   📚 Total files: {}", epub_files.len());
     
     if successful > 0 {
-        println!("\n   Please restart Calibre to see the new books.");
+        say!("\n   Please restart Calibre to see the new books.");
+    }
+
+    match abort_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Lists the source language codes that were stored as "und", with the number of books using each.
+fn print_unmapped_languages(unmapped_languages: &BTreeMap<String, usize>) {
+    if unmapped_languages.is_empty() {
+        say!("\n🌐 Every language code mapped to a known language.");
+        return;
+    }
+    say!("\n🌐 Language codes stored as \"und\" (add them to --lang-map-file to keep them):");
+    for (code, count) in unmapped_languages {
+        say!("   '{}' — {} book(s)", code, count);
+    }
+}
+
+/// Prints a directory import event as one line of JSON on stdout when `--progress-json` is set.
+fn progress(options: &AddOptions, event: models::ProgressEvent) {
+    if options.progress_json {
+        println!("{}", event.to_json());
+    }
+}
+
+/// Returns the book files directly inside `dir`, sorted for a consistent processing order.
+fn find_book_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && utils::has_book_extension(&path) {
+            files.push(path);
+        }
     }
+    files.sort();
+    Ok(files)
+}
+
+/// Reorders book files by title or by series and series index. Files whose metadata can't be
+/// read keep their filename order at the end, so the normal flow reports their errors.
+fn sort_by_metadata(files: Vec<PathBuf>, order_by: cli::ImportOrder, metadata_options: &models::MetadataOptions) -> Vec<PathBuf> {
+    say!("🔍 Reading metadata to order the import...");
+    let mut keyed: Vec<(Option<models::BookMetadata>, PathBuf)> = files.into_iter()
+        .map(|file| (epub::get_book_metadata(&file, metadata_options).ok(), file))
+        .collect();
+
+    let title_key = |metadata: &models::BookMetadata| utils::title_sort(&metadata.title).to_lowercase();
+    keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) => match order_by {
+            cli::ImportOrder::Filename => std::cmp::Ordering::Equal,
+            cli::ImportOrder::Title => title_key(a).cmp(&title_key(b)),
+            cli::ImportOrder::SeriesIndex => a.series.is_none().cmp(&b.series.is_none())
+                .then_with(|| a.series.as_deref().map(str::to_lowercase).cmp(&b.series.as_deref().map(str::to_lowercase)))
+                .then_with(|| a.series_index.unwrap_or(0.0).total_cmp(&b.series_index.unwrap_or(0.0)))
+                .then_with(|| title_key(a).cmp(&title_key(b))),
+        },
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+    keyed.into_iter().map(|(_, file)| file).collect()
+}
 
+/// Checks that each book file would import cleanly, without touching the library: the metadata
+/// must have a title and author, EPUBs must be intact, and the cover must decode.
+/// Prints a pass/fail line per file and fails if any file didn't pass.
+fn validate_books_flow(files: &[PathBuf], options: &AddOptions) -> Result<()> {
+    say!("🔍 Validating {} book file(s); nothing will be written...\n", files.len());
+    let metadata_options = models::MetadataOptions { strict: true, ..options.metadata_options.clone() };
+
+    let mut failed = 0;
+    for file in files {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        let result = epub::get_book_metadata(file, &metadata_options).and_then(|metadata| {
+            if utils::is_epub_format(utils::detect_book_format(file)?.0) {
+                epub::validate_epub(file)?;
+            }
+            let cover = epub::trial_decode_cover(file, &options.cover_options)?;
+            Ok(format!("'{}' by {}; {}", metadata.title, metadata.author, cover.as_deref().unwrap_or("no cover")))
+        });
+        match result {
+            Ok(details) => say!("  ✅ PASS  {} — {}", name, details),
+            Err(e) => {
+                failed += 1;
+                say!("  ❌ FAIL  {} — {:#}", name, e);
+            }
+        }
+    }
+
+    say!("\n📊 {} passed, {} failed", files.len() - failed, failed);
+    if failed > 0 {
+        anyhow::bail!("{} of {} file(s) failed validation", failed, files.len());
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Returns why the file should be skipped if it's larger than `max_file_size_mb`.
+fn oversized_file_reason(epub_file: &Path, max_file_size_mb: Option<u64>) -> Result<Option<String>> {
+    let Some(max_mb) = max_file_size_mb else {
+        return Ok(None);
+    };
+    let size = fs::metadata(epub_file)
+        .with_context(|| format!("Failed to read file size of {:?}", epub_file))?
+        .len();
+    if size > max_mb.saturating_mul(1024 * 1024) {
+        Ok(Some(format!("file is {:.1} MB, larger than the --max-file-size-mb limit of {} MB", size as f64 / (1024.0 * 1024.0), max_mb)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Looks up a book file's title and author in the library without hashing or touching files.
+/// Returns the ID of the matching book, if any.
+fn find_existing_book(calibre_conn: &Connection, epub_file: &Path, options: &AddOptions) -> Result<Option<i64>> {
+    let metadata = epub::get_book_metadata(epub_file, &options.metadata_options)?;
+    let author_sort = calibre::author_sort_for(calibre_conn, &metadata.author, options.author_sort, options.author_sort_map.as_ref())?;
+    calibre::find_book_id(calibre_conn, &metadata.title, &author_sort)
+}
+
+/// Checks that the library volume has room for all the files about to be imported.
+fn check_disk_space(library_dir: &Path, files: &[PathBuf], ignore_space: bool) -> Result<()> {
+    let required: u64 = files.iter()
+        .filter_map(|file| fs::metadata(file).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    let available = fs2::available_space(library_dir)
+        .with_context(|| format!("Failed to check free space on {:?}", library_dir))?;
+    if required <= available {
+        return Ok(());
+    }
+
+    const MB: f64 = 1024.0 * 1024.0;
+    let message = format!(
+        "The files to import need about {:.1} MB but only {:.1} MB is free on the library volume",
+        required as f64 / MB, available as f64 / MB
+    );
+    if ignore_space {
+        say!("⚠️  {}; continuing because of --ignore-space", message);
+        Ok(())
+    } else {
+        anyhow::bail!("{} (use --ignore-space to import anyway)", message)
+    }
+}
+
+/// Checks that files can be created in the library directory by creating and removing
+/// a temporary file, so read-only mounts and volumes out of space or inodes fail up front.
+fn check_library_writable(library_dir: &Path) -> Result<()> {
+    let probe = library_dir.join(format!(".cwh-write-test-{}", std::process::id()));
+    let result = fs::File::create(&probe).and_then(|_| fs::remove_file(&probe));
+    let Err(error) = result else {
+        return Ok(());
+    };
+    let reason = match error.kind() {
+        std::io::ErrorKind::ReadOnlyFilesystem => "the library volume is mounted read-only",
+        std::io::ErrorKind::PermissionDenied => "permission denied",
+        std::io::ErrorKind::StorageFull => "the library volume is out of space or inodes",
+        _ => "the library directory is not writable",
+    };
+    Err(anyhow::Error::new(error)
+        .context(format!("Cannot write to the library directory {:?}: {}; nothing was added", library_dir, reason)))
+}
+
+/// Writes the per-file results of a directory import as a JSON array.
+fn write_add_report(report_path: &Path, reports: &[models::AddFileReport]) -> Result<()> {
+    let entries: Vec<String> = reports.iter()
+        .map(|report| format!("  {}", report.to_json()))
+        .collect();
+    let json = if entries.is_empty() {
+        "[]\n".to_string()
+    } else {
+        format!("[\n{}\n]\n", entries.join(",\n"))
+    };
+
+    fs::write(report_path, json)
+        .with_context(|| format!("Failed to write report file {:?}", report_path))
+}
@@ -1,22 +1,188 @@
 use chrono::{DateTime, Utc};
-use std::path::PathBuf;
+use encoding_rs::Encoding;
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::cli::{ChecksumAlgorithm, CommentsStrategy};
+use crate::utils::{author_to_author_sort, detect_book_format, get_sorted_author, is_corporate_author, json_escape};
 
 /// Metadata extracted from an EPUB file
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub(crate) struct BookMetadata {
     pub(crate) title: String,
     pub(crate) author: String,
     pub(crate) path: PathBuf,
     pub(crate) description: Option<String>,
-    pub(crate) language: Option<String>,
+    /// ISO 639-2 codes of every language the book lists, in order and without duplicates
+    pub(crate) languages: Vec<String>,
+    /// Language codes from the file that didn't map to a known code and were stored as "und"
+    pub(crate) unmapped_languages: Vec<String>,
+    /// The EPUB's subjects (`dc:subject`), used by `--auto-shelf-by-tag`
+    pub(crate) tags: Vec<String>,
     pub(crate) isbn: Option<String>,
+    /// The original ISBN-10, kept alongside the converted ISBN-13 with `--keep-isbn10`
+    pub(crate) isbn10: Option<String>,
     pub(crate) rights: Option<String>,
     pub(crate) subtitle: Option<String>,
     pub(crate) series: Option<String>,
     pub(crate) series_index: Option<f64>,
     pub(crate) publisher: Option<String>,
     pub(crate) pubdate: Option<DateTime<Utc>>,
+    /// Content modification time from EPUB3 `dcterms:modified`
+    pub(crate) modified: Option<DateTime<Utc>>,
     pub(crate) file_size: u64,
+    /// Format and extension found by `--sniff-format` when the content disagrees with the extension
+    pub(crate) sniffed_format: Option<(&'static str, &'static str)>,
+}
+
+impl BookMetadata {
+    /// The book's format and extension: the sniffed ones if set, otherwise from the file name
+    pub(crate) fn format(&self) -> anyhow::Result<(&'static str, &'static str)> {
+        match self.sniffed_format {
+            Some(format) => Ok(format),
+            None => detect_book_format(&self.path),
+        }
+    }
+}
+
+/// Settings controlling how cover images are processed when saved to the library
+#[derive(Debug, Clone)]
+pub(crate) struct CoverOptions {
+    /// JPEG quality (1-100) used when re-encoding oversized covers
+    pub(crate) quality: u8,
+    /// Covers with more pixels than this are skipped instead of decoded
+    pub(crate) max_pixels: u64,
+    /// Covers wider than this are downscaled to fit, preserving aspect ratio
+    pub(crate) max_width: Option<u32>,
+    /// Covers taller than this are downscaled to fit, preserving aspect ratio
+    pub(crate) max_height: Option<u32>,
+    /// Save the book without a cover, instead of failing, when the cover can't be read or written
+    pub(crate) ignore_errors: bool,
+    /// Background color of the title/author cover generated for books without one, if enabled
+    pub(crate) placeholder_background: Option<[u8; 3]>,
+}
+
+/// Settings controlling how book and cover files are written into the library
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BookFileOptions<'a> {
+    pub(crate) cover: &'a CoverOptions,
+    /// Give the copied book and cover the modification time of their source
+    pub(crate) preserve_timestamps: bool,
+    /// Where files are staged before being renamed into place; defaults to the book's own directory
+    pub(crate) temp_dir: Option<&'a Path>,
+}
+
+/// Settings controlling how metadata is read from book files
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MetadataOptions {
+    /// Legacy encoding to re-decode text from; `None` only repairs text that looks garbled
+    pub(crate) source_encoding: Option<&'static Encoding>,
+    /// Reject books with a missing or blank title or author instead of falling back, and DRM-protected books
+    pub(crate) strict: bool,
+    /// Custom language tag to ISO 639-2 mappings, consulted before the built-in table
+    pub(crate) language_map: HashMap<String, String>,
+    /// Convert valid ISBN-10s to ISBN-13
+    pub(crate) isbn13: bool,
+    /// With `isbn13`, also keep the original ISBN-10 as an `ISBN10` identifier
+    pub(crate) keep_isbn10: bool,
+    /// Tell EPUB from KEPUB by the content instead of only the extension
+    pub(crate) sniff_format: bool,
+    /// `--series-regex`: reads the series, index and title from the file name of books
+    /// without series metadata
+    pub(crate) series_regex: Option<Regex>,
+}
+
+/// How the author sort name is derived from the author name
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum AuthorSortMethod {
+    /// Calibre-Web's `get_sorted_author()`, matching books added through its UI
+    #[default]
+    CalibreWeb,
+    /// Calibre's `author_to_author_sort()`, optionally with the `author_use_surname_prefixes` tweak
+    Calibre { surname_prefixes: bool },
+    /// The author name unchanged, for organizations or when inversion is turned off
+    AsIs,
+}
+
+impl AuthorSortMethod {
+    /// Calibre-Web's method leaves organization names (see `is_corporate_author`) uninverted;
+    /// Calibre's method already copies them through its own copywords check
+    pub(crate) fn author_sort(self, author: &str) -> String {
+        match self {
+            AuthorSortMethod::AsIs => author.trim().to_string(),
+            AuthorSortMethod::CalibreWeb if is_corporate_author(author) => author.trim().to_string(),
+            AuthorSortMethod::CalibreWeb => get_sorted_author(author),
+            AuthorSortMethod::Calibre { surname_prefixes } => author_to_author_sort(author, surname_prefixes),
+        }
+    }
+}
+
+/// Settings controlling how a book is written to the Calibre database
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BookWriteOptions<'a> {
+    /// Overrides the date added, which otherwise defaults to now
+    pub(crate) added_date: Option<DateTime<Utc>>,
+    /// Layout of new book directories
+    pub(crate) path_pattern: &'a str,
+    /// How author sort names are computed, for both the existing-book lookup and new authors
+    pub(crate) author_sort: AuthorSortMethod,
+    /// Sort names from `--author-sort-map`, keyed by lowercased author name, used for new
+    /// authors instead of computing one with `author_sort`
+    pub(crate) author_sort_map: Option<&'a HashMap<String, String>>,
+    /// How an existing book's comments are updated when the description changed
+    pub(crate) comments_strategy: CommentsStrategy,
+    /// Format extensions to compare an existing book's file by, after the incoming file's own format
+    pub(crate) prefer_format: &'a [String],
+    /// Hash used to tell whether the incoming file matches the existing one
+    pub(crate) checksum: ChecksumAlgorithm,
+    /// Compare the incoming and existing files by size and modification time instead of hashing
+    pub(crate) skip_hash_check: bool,
+    /// Report what would be written without changing anything
+    pub(crate) dry_run: bool,
+}
+
+/// Which parts of a book `delete` removes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DeleteScope {
+    /// The database records and the book's directory
+    All,
+    /// Only the metadata.db and app.db records, leaving the files on disk
+    DatabaseOnly,
+    /// Only the book's directory, leaving its database records
+    FilesOnly,
+}
+
+/// Filters and display settings for the `list` command
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ListOptions<'a> {
+    /// Only list books on this shelf
+    pub(crate) shelf_name: Option<&'a str>,
+    /// Only list books that aren't on any shelf
+    pub(crate) unshelved: bool,
+    /// With `unshelved`, only count this user's shelves
+    pub(crate) username: Option<&'a str>,
+    /// Also list books archived in Calibre-Web
+    pub(crate) include_archived: bool,
+    /// Only list books with an ID greater than this
+    pub(crate) since_id: Option<i64>,
+    /// Only list books in this series, matched case-insensitively
+    pub(crate) series: Option<&'a str>,
+    /// With `series`, the lowest series index to list
+    pub(crate) min_series_index: Option<f64>,
+    /// With `series`, the highest series index to list
+    pub(crate) max_series_index: Option<f64>,
+    /// Only list the book with the highest series index in each series
+    pub(crate) latest_per_series: bool,
+    /// Show all attributes for each book
+    pub(crate) verbose: bool,
+    /// Print only the number of matching books
+    pub(crate) count_only: bool,
+    /// Also print the absolute path of each of the book's files
+    pub(crate) full_paths: bool,
+    /// Print one tab-separated line per book: ID, title, authors and series
+    pub(crate) compact: bool,
+    /// Print books grouped under their shelves, then the unshelved ones
+    pub(crate) group_by_shelf: bool,
 }
 
 /// Existing book data from the database for comparison
@@ -26,6 +192,7 @@ pub(crate) struct ExistingBookData {
     pub(crate) series_index: f64,
     pub(crate) publisher: Option<String>,
     pub(crate) series: Option<String>,
+    pub(crate) comments: Option<String>,
 }
 
 /// Tracks what metadata fields have changed during an update
@@ -35,11 +202,13 @@ pub(crate) struct UpdateChanges {
     pub(crate) series_index_changed: bool,
     pub(crate) publisher_changed: bool,
     pub(crate) series_changed: bool,
+    pub(crate) comments_changed: bool,
 }
 
 impl UpdateChanges {
     pub(crate) fn has_any_changes(&self) -> bool {
         self.pubdate_changed || self.series_index_changed || self.publisher_changed || self.series_changed
+            || self.comments_changed
     }
 }
 
@@ -77,4 +246,92 @@ impl UpsertResult {
     pub(crate) fn skip_file_operations(&self) -> bool {
         matches!(self, UpsertResult::NoChanges { .. })
     }
+
+    /// Short machine-readable name for the outcome, used in batch reports
+    pub(crate) fn outcome_name(&self) -> &'static str {
+        match self {
+            UpsertResult::Created { .. } => "created",
+            UpsertResult::Updated { .. } => "updated",
+            UpsertResult::NoChanges { .. } => "nochanges",
+        }
+    }
+}
+
+/// Per-file result of a directory import, written to the `--report-file`
+#[derive(Debug)]
+pub(crate) struct AddFileReport {
+    pub(crate) file: PathBuf,
+    pub(crate) outcome: &'static str,
+    pub(crate) book_id: Option<i64>,
+    pub(crate) error: Option<String>,
+}
+
+impl AddFileReport {
+    pub(crate) fn from_result(file: &Path, result: &UpsertResult) -> Self {
+        Self {
+            file: file.to_path_buf(),
+            outcome: result.outcome_name(),
+            book_id: Some(result.book_id()),
+            error: None,
+        }
+    }
+
+    pub(crate) fn failed(file: &Path, error: &anyhow::Error) -> Self {
+        Self {
+            file: file.to_path_buf(),
+            outcome: "failed",
+            book_id: None,
+            error: Some(format!("{:#}", error)),
+        }
+    }
+
+    pub(crate) fn skipped(file: &Path, reason: &str) -> Self {
+        Self {
+            file: file.to_path_buf(),
+            outcome: "skipped",
+            book_id: None,
+            error: Some(reason.to_string()),
+        }
+    }
+
+    /// Serializes the entry as a single-line JSON object
+    pub(crate) fn to_json(&self) -> String {
+        format!(
+            "{{\"file\": \"{}\", \"outcome\": \"{}\", \"book_id\": {}, \"error\": {}}}",
+            json_escape(&self.file.to_string_lossy()),
+            self.outcome,
+            self.book_id.map_or("null".to_string(), |id| id.to_string()),
+            self.error.as_deref().map_or("null".to_string(), |e| format!("\"{}\"", json_escape(e))),
+        )
+    }
+}
+
+/// A directory import lifecycle event, streamed as NDJSON on stdout with `--progress-json`
+pub(crate) enum ProgressEvent<'a> {
+    Start { total: usize },
+    FileBegin { index: usize, total: usize, file: &'a Path },
+    /// `index` is `None` for files skipped as content duplicates before processing started
+    FileResult { index: Option<usize>, report: &'a AddFileReport },
+    Complete { successful: usize, failed: usize, skipped: usize, total: usize },
+}
+
+impl ProgressEvent<'_> {
+    /// Serializes the event as a single-line JSON object
+    pub(crate) fn to_json(&self) -> String {
+        match self {
+            ProgressEvent::Start { total } => format!("{{\"event\": \"start\", \"total\": {}}}", total),
+            ProgressEvent::FileBegin { index, total, file } => format!(
+                "{{\"event\": \"file_begin\", \"index\": {}, \"total\": {}, \"file\": \"{}\"}}",
+                index, total, json_escape(&file.to_string_lossy()),
+            ),
+            ProgressEvent::FileResult { index, report } => format!(
+                "{{\"event\": \"file_result\", \"index\": {}, \"result\": {}}}",
+                index.map_or("null".to_string(), |index| index.to_string()), report.to_json(),
+            ),
+            ProgressEvent::Complete { successful, failed, skipped, total } => format!(
+                "{{\"event\": \"complete\", \"successful\": {}, \"failed\": {}, \"skipped\": {}, \"total\": {}}}",
+                successful, failed, skipped, total,
+            ),
+        }
+    }
 }
@@ -0,0 +1,164 @@
+use owo_colors::OwoColorize;
+use std::io::IsTerminal;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::cli::ColorChoice;
+
+/// Set by `--ascii` or the `CWH_ASCII` environment variable
+static ASCII_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--format json`: progress messages go to stderr so stdout carries only the JSON
+static MESSAGES_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--color`: whether lines written to stdout and stderr get colored
+static COLOR_STDOUT: AtomicBool = AtomicBool::new(false);
+static COLOR_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// While set, `say!` lines are held here instead of printed, see `start_capture`
+static CAPTURED: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+/// Emoji and other non-ASCII markers used in output, with their plain ASCII replacements.
+/// Sequences with a variation selector come before their bare form.
+const ICONS: &[(&str, &str)] = &[
+    ("⚠️", "[WARN]"),
+    ("⚠", "[WARN]"),
+    ("✅", "[OK]"),
+    ("❌", "[ERROR]"),
+    ("📚", "[LIBRARY]"),
+    ("📖", "[BOOK]"),
+    ("📁", "[FILES]"),
+    ("🚚", "[COPY]"),
+    ("✒️", "[WRITE]"),
+    ("✒", "[WRITE]"),
+    ("📦", "[BACKUP]"),
+    ("📊", "[STATS]"),
+    ("📤", "[EXPORT]"),
+    ("📝", "[NOTE]"),
+    ("🔍", "[CHECK]"),
+    ("🔎", "[CHECK]"),
+    ("🔐", "[HASH]"),
+    ("🔧", "[FIX]"),
+    ("🔄", "[SYNC]"),
+    ("🧹", "[CLEAN]"),
+    ("🧪", "[DRY RUN]"),
+    ("🚀", "[START]"),
+    ("💡", "[TIP]"),
+    ("👤", "[USER]"),
+    ("🌐", "[LANG]"),
+    ("➕", "[ADD]"),
+    ("✨", "[NEW]"),
+    ("⏰", "[TIME]"),
+    ("⏭️", "[SKIP]"),
+    ("⏭", "[SKIP]"),
+    ("—", "-"),
+    ("─", "-"),
+    ("═", "="),
+    ("\u{FFFD}", ""),
+    ("\u{FE0F}", ""),
+];
+
+/// Switches all output to plain ASCII markers.
+pub(crate) fn set_ascii(enabled: bool) {
+    ASCII_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+/// Sends `say!` messages to stderr instead of stdout, for machine-readable output modes.
+pub(crate) fn set_messages_to_stderr(enabled: bool) {
+    MESSAGES_TO_STDERR.store(enabled, Ordering::Relaxed);
+}
+
+/// Decides whether each output stream gets colored. `auto` colors only terminals,
+/// and only when `NO_COLOR` is unset or empty.
+pub(crate) fn set_color(choice: ColorChoice) {
+    let (stdout, stderr) = match choice {
+        ColorChoice::Always => (true, true),
+        ColorChoice::Never => (false, false),
+        ColorChoice::Auto => {
+            let allowed = std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty());
+            (allowed && std::io::stdout().is_terminal(), allowed && std::io::stderr().is_terminal())
+        }
+    };
+    COLOR_STDOUT.store(stdout, Ordering::Relaxed);
+    COLOR_STDERR.store(stderr, Ordering::Relaxed);
+}
+
+/// Holds back `say!` lines until `finish_capture`, so a caller can decide afterwards whether
+/// they are worth printing.
+pub(crate) fn start_capture() {
+    *CAPTURED.lock().unwrap_or_else(|e| e.into_inner()) = Some(Vec::new());
+}
+
+/// Stops holding back `say!` lines and returns the ones held since `start_capture`.
+pub(crate) fn finish_capture() -> Vec<String> {
+    CAPTURED.lock().unwrap_or_else(|e| e.into_inner()).take().unwrap_or_default()
+}
+
+/// Prints one user-facing line to stdout, or stderr when stdout is reserved for JSON.
+pub(crate) fn emit(line: String) {
+    if let Some(captured) = CAPTURED.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        captured.push(line);
+        return;
+    }
+    if MESSAGES_TO_STDERR.load(Ordering::Relaxed) {
+        let line = if COLOR_STDERR.load(Ordering::Relaxed) { colorize(line) } else { line };
+        eprintln!("{}", line);
+    } else {
+        let line = if COLOR_STDOUT.load(Ordering::Relaxed) { colorize(line) } else { line };
+        println!("{}", line);
+    }
+}
+
+/// Colors success lines green, warnings yellow and errors red, keyed on the line's leading marker
+/// in either its emoji or ASCII form. Other lines are returned unchanged.
+fn colorize(line: String) -> String {
+    let marker = line.trim_start();
+    if marker.starts_with('✅') || marker.starts_with("[OK]") {
+        line.green().to_string()
+    } else if marker.starts_with('⚠') || marker.starts_with("[WARN]") {
+        line.yellow().to_string()
+    } else if marker.starts_with('❌') || marker.starts_with("[ERROR]") {
+        line.red().to_string()
+    } else {
+        line
+    }
+}
+
+/// Returns the message with emoji replaced by ASCII markers when ASCII output is enabled.
+pub(crate) fn render(message: String) -> String {
+    if !ASCII_OUTPUT.load(Ordering::Relaxed) || message.is_ascii() {
+        return message;
+    }
+    ICONS.iter().fold(message, |text, (icon, ascii)| text.replace(icon, ascii))
+}
+
+/// Prints a user-facing line like `println!`, honoring `--ascii` and `--format json`.
+macro_rules! say {
+    () => {
+        $crate::output::emit(String::new())
+    };
+    ($($arg:tt)*) => {
+        $crate::output::emit($crate::output::render(format!($($arg)*)))
+    };
+}
+pub(crate) use say;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_ascii() {
+        set_ascii(true);
+        assert_eq!(render("⚠️  Skipped — 📚 done ✅".to_string()), "[WARN]  Skipped - [LIBRARY] done [OK]");
+        assert_eq!(render("plain".to_string()), "plain");
+        set_ascii(false);
+    }
+
+    #[test]
+    fn test_colorize() {
+        assert_eq!(colorize("\n✅ Done".to_string()), "\u{1b}[32m\n✅ Done\u{1b}[39m");
+        assert_eq!(colorize("  [WARN] Skipped".to_string()), "\u{1b}[33m  [WARN] Skipped\u{1b}[39m");
+        assert_eq!(colorize("❌ Failed".to_string()), "\u{1b}[31m❌ Failed\u{1b}[39m");
+        assert_eq!(colorize("📚 Library".to_string()), "📚 Library");
+    }
+}
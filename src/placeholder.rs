@@ -0,0 +1,199 @@
+use image::{DynamicImage, Rgb, RgbImage};
+
+/// Size of generated placeholder covers, the 2:3 shape Calibre-Web's grid expects
+const COVER_WIDTH: u32 = 600;
+const COVER_HEIGHT: u32 = 900;
+
+/// Space kept clear around the text on each side
+const MARGIN: u32 = 48;
+
+/// Glyph scale and line limits for the title and author blocks
+const TITLE_SCALE: u32 = 6;
+const TITLE_MAX_LINES: usize = 6;
+const AUTHOR_SCALE: u32 = 4;
+const AUTHOR_MAX_LINES: usize = 2;
+
+/// Glyphs are 5x7 pixels, drawn with one pixel of spacing after each column and line
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+/// 5x7 bitmap font for printable ASCII (space to `~`), one byte per column, least significant
+/// bit at the top
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5F, 0x00, 0x00], [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], [0x24, 0x2A, 0x7F, 0x2A, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x56, 0x20, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00], [0x00, 0x1C, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1C, 0x00], [0x14, 0x08, 0x3E, 0x08, 0x14], [0x08, 0x08, 0x3E, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], [0x00, 0x60, 0x60, 0x00, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02], [0x3E, 0x51, 0x49, 0x45, 0x3E], [0x00, 0x42, 0x7F, 0x40, 0x00],
+    [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4B, 0x31], [0x18, 0x14, 0x12, 0x7F, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39], [0x3C, 0x4A, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1E], [0x00, 0x36, 0x36, 0x00, 0x00],
+    [0x00, 0x56, 0x36, 0x00, 0x00], [0x08, 0x14, 0x22, 0x41, 0x00], [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x51, 0x09, 0x06], [0x32, 0x49, 0x79, 0x41, 0x3E],
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], [0x7F, 0x49, 0x49, 0x49, 0x36], [0x3E, 0x41, 0x41, 0x41, 0x22],
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], [0x7F, 0x49, 0x49, 0x49, 0x41], [0x7F, 0x09, 0x09, 0x09, 0x01],
+    [0x3E, 0x41, 0x49, 0x49, 0x7A], [0x7F, 0x08, 0x08, 0x08, 0x7F], [0x00, 0x41, 0x7F, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3F, 0x01], [0x7F, 0x08, 0x14, 0x22, 0x41], [0x7F, 0x40, 0x40, 0x40, 0x40],
+    [0x7F, 0x02, 0x0C, 0x02, 0x7F], [0x7F, 0x04, 0x08, 0x10, 0x7F], [0x3E, 0x41, 0x41, 0x41, 0x3E],
+    [0x7F, 0x09, 0x09, 0x09, 0x06], [0x3E, 0x41, 0x51, 0x21, 0x5E], [0x7F, 0x09, 0x19, 0x29, 0x46],
+    [0x46, 0x49, 0x49, 0x49, 0x31], [0x01, 0x01, 0x7F, 0x01, 0x01], [0x3F, 0x40, 0x40, 0x40, 0x3F],
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], [0x3F, 0x40, 0x38, 0x40, 0x3F], [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x07, 0x08, 0x70, 0x08, 0x07], [0x61, 0x51, 0x49, 0x45, 0x43], [0x00, 0x7F, 0x41, 0x41, 0x00],
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x7F, 0x00], [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40], [0x00, 0x01, 0x02, 0x04, 0x00], [0x20, 0x54, 0x54, 0x54, 0x78],
+    [0x7F, 0x48, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x20], [0x38, 0x44, 0x44, 0x48, 0x7F],
+    [0x38, 0x54, 0x54, 0x54, 0x18], [0x08, 0x7E, 0x09, 0x01, 0x02], [0x0C, 0x52, 0x52, 0x52, 0x3E],
+    [0x7F, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7D, 0x40, 0x00], [0x20, 0x40, 0x44, 0x3D, 0x00],
+    [0x7F, 0x10, 0x28, 0x44, 0x00], [0x00, 0x41, 0x7F, 0x40, 0x00], [0x7C, 0x04, 0x18, 0x04, 0x78],
+    [0x7C, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38], [0x7C, 0x14, 0x14, 0x14, 0x08],
+    [0x08, 0x14, 0x14, 0x18, 0x7C], [0x7C, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x20],
+    [0x04, 0x3F, 0x44, 0x40, 0x20], [0x3C, 0x40, 0x40, 0x20, 0x7C], [0x1C, 0x20, 0x40, 0x20, 0x1C],
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], [0x44, 0x28, 0x10, 0x28, 0x44], [0x0C, 0x50, 0x50, 0x50, 0x3C],
+    [0x44, 0x64, 0x54, 0x4C, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00], [0x00, 0x00, 0x7F, 0x00, 0x00],
+    [0x00, 0x41, 0x36, 0x08, 0x00], [0x08, 0x04, 0x08, 0x10, 0x08],
+];
+
+/// Accented Latin letters and typographic quotes, with the ASCII character drawn in their place
+const FOLDS: &[(&str, char)] = &[
+    ("ÀÁÂÃÄÅ", 'A'), ("àáâãäå", 'a'), ("Ç", 'C'), ("ç", 'c'), ("ÈÉÊË", 'E'), ("èéêë", 'e'),
+    ("ÌÍÎÏ", 'I'), ("ìíîï", 'i'), ("Ñ", 'N'), ("ñ", 'n'), ("ÒÓÔÕÖØ", 'O'), ("òóôõöø", 'o'),
+    ("ÙÚÛÜ", 'U'), ("ùúûü", 'u'), ("Ý", 'Y'), ("ýÿ", 'y'), ("ß", 's'), ("‘’‚", '\''), ("“”„", '"'),
+    ("–—", '-'), ("…", '.'),
+];
+
+/// Renders a plain cover for a book without one: the title in large type near the top and the
+/// author below it, in white or black, whichever contrasts more with `background`. A title or
+/// author the built-in font can't draw, such as one in Cyrillic or CJK script, is left off.
+pub(crate) fn placeholder_cover(title: &str, author: &str, background: [u8; 3]) -> DynamicImage {
+    let mut img = RgbImage::from_pixel(COVER_WIDTH, COVER_HEIGHT, Rgb(background));
+    let [r, g, b] = background.map(u32::from);
+    let ink = if r * 299 + g * 587 + b * 114 > 128_000 { Rgb([20, 20, 20]) } else { Rgb([245, 245, 245]) };
+
+    let title_lines = fold(title).map_or_else(Vec::new, |title| wrap(&title, line_capacity(TITLE_SCALE), TITLE_MAX_LINES));
+    let mut y = COVER_HEIGHT / 6;
+    for line in &title_lines {
+        draw_centered(&mut img, line, y, TITLE_SCALE, ink);
+        y += (GLYPH_HEIGHT + 3) * TITLE_SCALE;
+    }
+
+    // A rule between title and author, then the author in smaller type
+    y += 2 * AUTHOR_SCALE;
+    for x in COVER_WIDTH / 3..COVER_WIDTH * 2 / 3 {
+        for dy in 0..AUTHOR_SCALE / 2 {
+            img.put_pixel(x, y + dy, ink);
+        }
+    }
+    y += 6 * AUTHOR_SCALE;
+    let author_lines = fold(author).map_or_else(Vec::new, |author| wrap(&author, line_capacity(AUTHOR_SCALE), AUTHOR_MAX_LINES));
+    for line in author_lines {
+        draw_centered(&mut img, &line, y, AUTHOR_SCALE, ink);
+        y += (GLYPH_HEIGHT + 3) * AUTHOR_SCALE;
+    }
+
+    DynamicImage::ImageRgb8(img)
+}
+
+/// Number of characters that fit on one line at `scale`
+fn line_capacity(scale: u32) -> usize {
+    ((COVER_WIDTH - 2 * MARGIN) / ((GLYPH_WIDTH + 1) * scale)) as usize
+}
+
+/// Replaces characters outside the font with their ASCII look-alike. Returns `None` if any
+/// character has none, rather than drawing it as `?`.
+fn fold(text: &str) -> Option<String> {
+    text.chars()
+        .map(|c| match c {
+            ' '..='~' => Some(c),
+            c if c.is_whitespace() => Some(' '),
+            c => FOLDS.iter().find(|(from, _)| from.contains(c)).map(|(_, to)| *to),
+        })
+        .collect()
+}
+
+/// Word-wraps `text` into at most `max_lines` lines of `width` characters, breaking words that
+/// are longer than a line and ending with `...` if the text doesn't fit
+fn wrap(text: &str, width: usize, max_lines: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        let mut word = word.to_string();
+        loop {
+            let needed = if current.is_empty() { word.len() } else { current.len() + 1 + word.len() };
+            if needed <= width {
+                if !current.is_empty() {
+                    current.push(' ');
+                }
+                current.push_str(&word);
+                break;
+            }
+            if !current.is_empty() {
+                lines.push(std::mem::take(&mut current));
+            } else {
+                let rest = word.split_off(width);
+                lines.push(std::mem::replace(&mut word, rest));
+            }
+        }
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        let last = &mut lines[max_lines - 1];
+        last.truncate(width.saturating_sub(3));
+        last.push_str("...");
+    }
+    lines
+}
+
+/// Draws one line of text horizontally centered with its top edge at `y`
+fn draw_centered(img: &mut RgbImage, line: &str, y: u32, scale: u32, ink: Rgb<u8>) {
+    let advance = (GLYPH_WIDTH + 1) * scale;
+    let line_width = (line.len() as u32 * advance).saturating_sub(scale);
+    let mut x = COVER_WIDTH.saturating_sub(line_width) / 2;
+    for c in line.chars() {
+        let glyph = FONT.get((c as usize).wrapping_sub(' ' as usize)).unwrap_or(&FONT[usize::from(b'?' - b' ')]);
+        for (column, bits) in glyph.iter().enumerate() {
+            for row in (0..GLYPH_HEIGHT).filter(|row| bits & (1 << row) != 0) {
+                for py in 0..scale {
+                    for px in 0..scale {
+                        let (gx, gy) = (x + column as u32 * scale + px, y + row * scale + py);
+                        if gx < COVER_WIDTH && gy < COVER_HEIGHT {
+                            img.put_pixel(gx, gy, ink);
+                        }
+                    }
+                }
+            }
+        }
+        x += advance;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("The Fellowship of the Ring", 14, 6), vec!["The Fellowship", "of the Ring"]);
+        assert_eq!(wrap("Supercalifragilistic", 8, 6), vec!["Supercal", "ifragili", "stic"]);
+        assert_eq!(wrap("one two three four", 5, 2), vec!["one", "tw..."]);
+        assert_eq!(fold("Les Misérables — “Tome I”").as_deref(), Some("Les Miserables - \"Tome I\""));
+        assert_eq!(fold("Война и мир"), None);
+    }
+
+    #[test]
+    fn test_placeholder_cover_size_and_contrast() {
+        let cover = placeholder_cover("Title", "Author", [250, 250, 250]).to_rgb8();
+        assert_eq!(cover.dimensions(), (COVER_WIDTH, COVER_HEIGHT));
+        assert_eq!(cover.get_pixel(0, 0), &Rgb([250, 250, 250]));
+        assert!(cover.pixels().any(|pixel| pixel == &Rgb([20, 20, 20])));
+    }
+
+    #[test]
+    fn test_undrawable_title_is_left_off() {
+        let cover = placeholder_cover("Война и мир", "Leo Tolstoy", [47, 79, 79]);
+        assert_eq!(cover, placeholder_cover("", "Leo Tolstoy", [47, 79, 79]));
+    }
+}
@@ -3,15 +3,38 @@ use regex::Regex;
 use rusqlite::{params, Transaction, Error as SqliteError, Connection, OptionalExtension};
 use anyhow::{Result, Context};
 use sha1::{Sha1, Digest};
+use sha2::Sha256;
 use std::fs::{self, File};
 use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
 
+use crate::cli::ChecksumAlgorithm;
+use crate::models::BookMetadata;
+use crate::output::say;
+
 static BAD_CHARS_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"[*+:\\"/<>?]+"#).expect("invalid regex"));
 static PIPE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[|]+").expect("invalid regex"));
 static WHITESPACE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?:^[\s\u{200B}-\u{200D}\u{FEFF}]+)|([\s\u{200B}-\u{200D}\u{FEFF}]+$)").expect("invalid regex"));
 static SUFFIX_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)^((JR|SR)\.?|I{1,3}\.?|IV\.?)$").expect("invalid regex"));
+static PATH_TOKEN_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{([^{}]*)\}").expect("invalid regex"));
+/// Matches a series number in a title or file name: "Book 3", "Vol. 3", a leading "03 - " or a
+/// bracketed "(3)". Bare numbers of four digits or more are left alone since they're usually years.
+static SERIES_NUMBER_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)\b(?:book|vol(?:ume)?|part|no|tome|band)\.?\s*(\d+(?:[.,]\d+)?)\b|^\s*(\d{1,3}(?:[.,]\d+)?)\s*[-._)\]]\s|[\[(](\d{1,3}(?:[.,]\d+)?)[\])]").expect("invalid regex"));
+/// Matches one `"source": "target"` entry of a JSON mapping file
+static MAPPING_ENTRY_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#""((?:[^"\\]|\\.)*)"\s*:\s*"((?:[^"\\]|\\.)*)""#).expect("invalid regex"));
+
+/// Default layout for book directories, matching Calibre: `Author/Title (id)`
+pub(crate) const DEFAULT_PATH_PATTERN: &str = "{author}/{title} ({id})";
+
+/// Tokens that may appear in a book path pattern
+const PATH_PATTERN_TOKENS: &[&str] = &["author", "title", "series", "series_index", "id", "id_bucket"];
+
+/// Number of `{id_bucket}` directories new books are spread across
+const ID_BUCKETS: i64 = 1000;
+
+/// Prefix `--shard-by-id` puts in front of the path pattern
+pub(crate) const SHARD_PATH_PREFIX: &str = "{id_bucket}/";
 
 /// Format a timestamp with microsecond precision for database storage
 /// This matches the format used by both Calibre and Calibre-Web
@@ -22,6 +45,26 @@ where
     dt.format("%Y-%m-%d %H:%M:%S.%6f").to_string()
 }
 
+/// Parses a timestamp as stored by Calibre, Calibre-Web or other tools, e.g.
+/// "2024-01-02 03:04:05.123456+00:00", "2024-01-02T03:04:05Z" or just "2024-01-02".
+/// Values without an offset are taken as UTC.
+pub(crate) fn parse_db_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    const WITH_OFFSET: &[&str] = &["%Y-%m-%d %H:%M:%S%.f%:z", "%Y-%m-%d %H:%M:%S%.f%z", "%Y-%m-%dT%H:%M:%S%.f%:z"];
+    const NAIVE: &[&str] = &["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M"];
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    WITH_OFFSET.iter()
+        .find_map(|format| DateTime::parse_from_str(value, format).ok())
+        .map(|dt| dt.with_timezone(&Utc))
+        .or_else(|| NAIVE.iter()
+            .find_map(|format| chrono::NaiveDateTime::parse_from_str(value, format).ok())
+            .or_else(|| chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0)))
+            .map(|naive| DateTime::from_naive_utc_and_offset(naive, Utc)))
+}
+
 /// Get current UTC timestamp formatted for database storage
 pub(crate) fn now_utc_micro() -> String {
     format_timestamp_micro(&Utc::now())
@@ -74,6 +117,132 @@ pub(crate) fn get_valid_filename(value: &str, max_chars: usize) -> String {
     strip_whitespaces(&s)
 }
 
+/// Checks that a book path pattern only uses known tokens, includes `{id}` so every
+/// book gets its own directory, and can't point outside the library.
+pub(crate) fn validate_path_pattern(pattern: &str) -> Result<()> {
+    for caps in PATH_TOKEN_RE.captures_iter(pattern) {
+        if !PATH_PATTERN_TOKENS.contains(&&caps[1]) {
+            anyhow::bail!("Unknown token '{{{}}}' in path pattern (expected one of: {})",
+                &caps[1], PATH_PATTERN_TOKENS.join(", "));
+        }
+    }
+    if !pattern.contains("{id}") {
+        anyhow::bail!("Path pattern must include {{id}} so each book gets its own directory");
+    }
+    if pattern.starts_with('/') || pattern.split('/').any(|component| component.trim() == "..") {
+        anyhow::bail!("Path pattern must stay inside the library directory");
+    }
+    Ok(())
+}
+
+/// Builds a book's directory, relative to the library, from a path pattern.
+/// Token values are sanitized with `get_valid_filename`; missing values (e.g. no series)
+/// become empty and path components left empty are dropped.
+pub(crate) fn render_book_path(pattern: &str, metadata: &BookMetadata, book_id: &str) -> String {
+    let path = PATH_TOKEN_RE.replace_all(pattern, |caps: &regex::Captures| match &caps[1] {
+        "author" => get_valid_filename(&metadata.author, 96),
+        "title" => get_valid_filename(&metadata.title, 96),
+        "series" => metadata.series.as_deref().map(|series| get_valid_filename(series, 96)).unwrap_or_default(),
+        "series_index" => metadata.series_index.map(|index| index.to_string()).unwrap_or_default(),
+        "id" => book_id.to_string(),
+        // Zero-padded so the buckets sort in order; a dry run's placeholder ID is used as-is
+        "id_bucket" => book_id.parse::<i64>()
+            .map(|id| format!("{:03}", id % ID_BUCKETS))
+            .unwrap_or_else(|_| book_id.to_string()),
+        _ => String::new(),
+    });
+    path.split('/')
+        .map(|component| component.trim_matches(|c: char| c.is_whitespace() || c == '-'))
+        .filter(|component| !component.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Normalizes metadata text: trims it, collapses runs of internal whitespace to a single
+/// space and, with the `nfc` feature, converts it to Unicode NFC so that visually identical
+/// titles and names compare equal.
+pub(crate) fn normalize_text(text: &str) -> String {
+    let normalized = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    #[cfg(feature = "nfc")]
+    let normalized = {
+        use unicode_normalization::UnicodeNormalization;
+        normalized.nfc().collect::<String>()
+    };
+    normalized
+}
+
+/// Tolerance within which a series index counts as a whole number
+const SERIES_INDEX_EPSILON: f64 = 1e-6;
+
+/// Formats a series index for display: whole numbers without a fractional part ("3", not
+/// "3.0") and other values with at most two decimals ("1.5").
+pub(crate) fn format_series_index(index: f64) -> String {
+    if (index - index.round()).abs() < SERIES_INDEX_EPSILON {
+        format!("{}", index.round() as i64)
+    } else {
+        format!("{:.2}", index).trim_end_matches('0').trim_end_matches('.').to_string()
+    }
+}
+
+/// Snaps a series index that is a whole number up to float noise (e.g. 2.9999999) to that
+/// whole number, for `--int-series-index`. Genuinely fractional indices are kept.
+pub(crate) fn snap_series_index(index: f64) -> f64 {
+    if (index - index.round()).abs() < SERIES_INDEX_EPSILON {
+        index.round()
+    } else {
+        index
+    }
+}
+
+/// Parses a series index written with either a dot or a comma as the decimal separator.
+///
+/// "1.5" and "1,5" -> 1.5. A comma followed by exactly three digits is a thousands separator
+/// ("1,000" -> 1000), and when both separators appear the last one is the decimal point
+/// ("1.000,5" -> 1000.5, "1,000.5" -> 1000.5).
+pub(crate) fn parse_series_index(value: &str) -> Option<f64> {
+    let value = value.trim().trim_end_matches(['.', ',']);
+    let normalized = match (value.rfind(','), value.rfind('.')) {
+        (Some(comma), Some(dot)) if comma > dot => value.replace('.', "").replace(',', "."),
+        (Some(_), Some(_)) => value.replace(',', ""),
+        (Some(comma), None) => {
+            let is_grouping = value.split(',').skip(1).all(|group| group.len() == 3);
+            if is_grouping || value[comma + 1..].is_empty() {
+                value.replace(',', "")
+            } else {
+                value.replacen(',', ".", 1)
+            }
+        }
+        _ => value.to_string(),
+    };
+    normalized.parse::<f64>().ok().filter(|index| index.is_finite())
+}
+
+/// Extracts a series index written as "#X" in a title (e.g. "Saga #2").
+pub(crate) fn series_index_from_title(title: &str) -> Option<f64> {
+    title.find('#')
+        .and_then(|i| {
+            let rest = &title[i + 1..];
+            let num_str: String = rest.chars()
+                .take_while(|c| c.is_ascii_digit() || *c == '.' || *c == ',')
+                .collect();
+            parse_series_index(&num_str)
+        })
+}
+
+/// Returns every distinct series index a title or file name suggests, from "#X" and the
+/// patterns of `SERIES_NUMBER_RE`, in the order found.
+pub(crate) fn series_index_candidates(text: &str) -> Vec<f64> {
+    let mut candidates: Vec<f64> = series_index_from_title(text).into_iter().collect();
+    for captures in SERIES_NUMBER_RE.captures_iter(text) {
+        let Some(number) = captures.iter().skip(1).flatten().next() else { continue };
+        if let Some(index) = parse_series_index(number.as_str())
+            && !candidates.iter().any(|&seen| (seen - index).abs() < SERIES_INDEX_EPSILON) {
+                candidates.push(index);
+            }
+    }
+    candidates
+}
+
 /// Strip leading/trailing whitespace and Unicode zero-width characters,
 /// matching Calibre-Web's `strip_whitespaces()`.
 fn strip_whitespaces(text: &str) -> String {
@@ -145,6 +314,144 @@ pub(crate) fn get_sorted_author(value: &str) -> String {
     }
 }
 
+/// Returns true if the author name looks like an organization, e.g. "O'Reilly Media" or "Acme Press"
+pub(crate) fn is_corporate_author(author: &str) -> bool {
+    author.split_whitespace()
+        .any(|word| is_name_word(word.trim_end_matches(','), AUTHOR_COPYWORDS))
+}
+
+/// Returns true if `isbn` is ten characters, nine digits and a digit or `X`, with a valid check digit
+pub(crate) fn is_valid_isbn10(isbn: &str) -> bool {
+    let chars: Vec<char> = isbn.chars().collect();
+    if chars.len() != 10 || !chars[..9].iter().all(char::is_ascii_digit) {
+        return false;
+    }
+    let check = match chars[9] {
+        'X' | 'x' => 10,
+        c => match c.to_digit(10) {
+            Some(digit) => digit,
+            None => return false,
+        },
+    };
+    let sum: u32 = chars[..9].iter()
+        .zip((2..=10).rev())
+        .map(|(c, weight)| c.to_digit(10).unwrap_or(0) * weight)
+        .sum();
+    (sum + check).is_multiple_of(11)
+}
+
+/// Removes the hyphens and spaces ISBNs are often written with, e.g. "0-306-40615-2"
+pub(crate) fn strip_isbn_separators(isbn: &str) -> String {
+    isbn.chars().filter(|c| *c != '-' && !c.is_whitespace()).collect()
+}
+
+/// Converts a valid ISBN-10, with or without hyphens, to its ISBN-13: the `978` prefix, the
+/// first nine digits and a recomputed check digit. Returns `None` for anything else.
+pub(crate) fn isbn10_to_isbn13(isbn10: &str) -> Option<String> {
+    let isbn10 = strip_isbn_separators(isbn10);
+    if !is_valid_isbn10(&isbn10) {
+        return None;
+    }
+    let body = format!("978{}", &isbn10[..9]);
+    let sum: u32 = body.chars()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, digit)| if i % 2 == 0 { digit } else { digit * 3 })
+        .sum();
+    Some(format!("{}{}", body, (10 - sum % 10) % 10))
+}
+
+/// Words that mark an author as a company, whose name Calibre copies unchanged as the sort.
+/// Calibre's list, plus legal suffixes and publishing words.
+const AUTHOR_COPYWORDS: &[&str] = &[
+    "agency", "corporation", "company", "co.", "council", "committee", "inc.", "institute",
+    "national", "society", "club", "team", "software", "games", "entertainment", "media", "studios",
+    "inc", "ltd", "llc", "plc", "gmbh", "corp", "press", "publishing", "publishers", "publications",
+];
+/// Honorifics Calibre drops from the front of the author sort
+const AUTHOR_NAME_PREFIXES: &[&str] = &["mr", "mrs", "ms", "dr", "prof"];
+/// Suffixes Calibre keeps after the first names in the author sort
+const AUTHOR_NAME_SUFFIXES: &[&str] = &[
+    "jr", "sr", "inc", "ph.d", "phd", "md", "m.d", "i", "ii", "iii", "iv", "junior", "senior",
+];
+/// Surname particles kept with the surname when `surname_prefixes` is enabled
+const AUTHOR_SURNAME_PREFIXES: &[&str] = &["da", "de", "di", "la", "le", "van", "von"];
+
+/// Removes text in (), [] or {} brackets, like Calibre's `remove_bracketed_text()`.
+fn remove_bracketed_text(text: &str) -> String {
+    const BRACKETS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+    let mut counts = [0usize; 3];
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        if let Some(i) = BRACKETS.iter().position(|(open, _)| *open == c) {
+            counts[i] += 1;
+        } else if let Some(i) = BRACKETS.iter().position(|(_, close)| *close == c) {
+            counts[i] = counts[i].saturating_sub(1);
+        } else if counts.iter().all(|&n| n == 0) {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Returns true if `token` is one of `words`, with or without a trailing period
+fn is_name_word(token: &str, words: &[&str]) -> bool {
+    let lower = token.to_lowercase();
+    words.iter().any(|w| lower == *w || lower.strip_suffix('.') == Some(w))
+}
+
+/// Compute author sort, porting Calibre's `author_to_author_sort()` with its default tweaks
+/// (`author_sort_copy_method = 'comma'`). `surname_prefixes` enables the
+/// `author_use_surname_prefixes` tweak, which keeps particles like "van" with the surname.
+///
+/// "John Doe" -> "Doe, John"
+/// "Dr. Martin Luther King Jr." -> "King, Martin Luther Jr."
+/// "Acme Software" -> "Acme Software"
+pub(crate) fn author_to_author_sort(author: &str, surname_prefixes: bool) -> String {
+    let stripped = remove_bracketed_text(author);
+    let stripped = stripped.trim();
+    if stripped.contains(',') {
+        return author.to_string();
+    }
+
+    let mut tokens: Vec<String> = stripped.split_whitespace().map(str::to_string).collect();
+    if tokens.len() < 2 || tokens.iter().any(|t| AUTHOR_COPYWORDS.contains(&t.to_lowercase().as_str())) {
+        return author.to_string();
+    }
+    if surname_prefixes && tokens.len() == 2 && AUTHOR_SURNAME_PREFIXES.contains(&tokens[0].to_lowercase().as_str()) {
+        return author.to_string();
+    }
+
+    let Some(first) = tokens.iter().position(|t| !is_name_word(t, AUTHOR_NAME_PREFIXES)) else {
+        return author.to_string();
+    };
+    let Some(mut last) = (first..tokens.len()).rev().find(|&i| !is_name_word(&tokens[i], AUTHOR_NAME_SUFFIXES)) else {
+        return author.to_string();
+    };
+    let suffix = tokens[last + 1..].join(" ");
+
+    if surname_prefixes && last > first && AUTHOR_SURNAME_PREFIXES.contains(&tokens[last - 1].to_lowercase().as_str()) {
+        let surname = tokens[last].clone();
+        tokens[last - 1].push(' ');
+        tokens[last - 1].push_str(&surname);
+        last -= 1;
+    }
+
+    let mut sort = tokens[last].clone();
+    if last > first {
+        sort.push(',');
+        for token in &tokens[first..last] {
+            sort.push(' ');
+            sort.push_str(token);
+        }
+    }
+    if !suffix.is_empty() {
+        sort.push(' ');
+        sort.push_str(&suffix);
+    }
+    sort
+}
+
 /// Mark a book as metadata-dirty in the Calibre database.
 /// Calibre-Web writes to `metadata_dirtied` so the Calibre desktop app
 /// knows which OPF files to regenerate.
@@ -260,132 +567,134 @@ pub(crate) fn find_or_create_language(
     )
 }
 
-/// Verifies and repairs any NULL timestamp values in both databases.
-/// This is run automatically when opening the databases to prevent NULL value errors.
-pub(crate) fn verify_and_repair_timestamps(calibre_conn: &mut Connection, appdb_conn: Option<&mut Connection>) -> Result<()> {
-    // Fix timestamps in Calibre database
-    let tx = calibre_conn.transaction()?;
-    
-    // Get current timestamp with microsecond precision
-    let now = now_utc_micro();
-
-    // Fix NULL timestamps in books table
-    let fixed = tx.execute(
-        "UPDATE books SET timestamp = ?1 WHERE timestamp IS NULL",
-        [&now],
-    )?;
-    if fixed > 0 {
-        println!(" -> Fixed {} books with missing timestamp", fixed);
-    }
+/// Timestamp columns in metadata.db that must not be NULL, with a description for log output
+const CALIBRE_TIMESTAMP_COLUMNS: &[(&str, &str, &str)] = &[
+    ("books", "timestamp", "books with missing timestamp"),
+    ("books", "pubdate", "books with missing pubdate"),
+    ("books", "last_modified", "books with missing last_modified"),
+];
 
-    let fixed = tx.execute(
-        "UPDATE books SET pubdate = ?1 WHERE pubdate IS NULL",
-        [&now],
-    )?;
-    if fixed > 0 {
-        println!(" -> Fixed {} books with missing pubdate", fixed);
-    }
+/// Timestamp columns in app.db that must not be NULL, with a description for log output
+const APPDB_TIMESTAMP_COLUMNS: &[(&str, &str, &str)] = &[
+    ("shelf", "created", "shelves with missing created timestamp"),
+    ("shelf", "last_modified", "shelves with missing last_modified timestamp"),
+    ("book_shelf_link", "date_added", "shelf links with missing date_added"),
+    ("archived_book", "last_modified", "archived books with missing last_modified"),
+    ("kobo_reading_state", "last_modified", "Kobo reading states with missing last_modified"),
+    ("kobo_reading_state", "priority_timestamp", "Kobo reading states with missing priority_timestamp"),
+    ("kobo_bookmark", "last_modified", "Kobo bookmarks with missing last_modified"),
+];
 
-    let fixed = tx.execute(
-        "UPDATE books SET last_modified = ?1 WHERE last_modified IS NULL",
-        [&now],
-    )?;
-    if fixed > 0 {
-        println!(" -> Fixed {} books with missing last_modified", fixed);
+/// Fills NULL values in each listed timestamp column with `now`, or when `repair`
+/// is false only counts them and prints a warning.
+fn repair_null_timestamps(conn: &Connection, columns: &[(&str, &str, &str)], now: &str, repair: bool) -> Result<()> {
+    for (table, column, description) in columns {
+        if repair {
+            let fixed = conn.execute(
+                &format!("UPDATE {} SET {} = ?1 WHERE {} IS NULL", table, column, column),
+                [now],
+            )?;
+            if fixed > 0 {
+                say!(" -> Fixed {} {}", fixed, description);
+            }
+        } else {
+            let found: i64 = conn.query_row(
+                &format!("SELECT COUNT(*) FROM {} WHERE {} IS NULL", table, column),
+                [],
+                |row| row.get(0),
+            )?;
+            if found > 0 {
+                say!("⚠️  Found {} {} (not repaired for a read-only command)", found, description);
+            }
+        }
     }
+    Ok(())
+}
 
+/// Verifies and repairs any NULL timestamp values in both databases.
+/// This is run automatically when opening the databases to prevent NULL value errors.
+/// With `repair` set to false nothing is written; problems are only reported.
+pub(crate) fn verify_and_repair_timestamps(calibre_conn: &mut Connection, appdb_conn: Option<&mut Connection>, repair: bool) -> Result<()> {
+    // Fix timestamps in Calibre database
+    let tx = calibre_conn.transaction()?;
+    repair_null_timestamps(&tx, CALIBRE_TIMESTAMP_COLUMNS, &now_utc_micro(), repair)?;
     tx.commit()?;
 
     // Fix timestamps in Calibre-Web database if provided
     // Calibre-Web uses UTC for all its model defaults (datetime.now(timezone.utc))
     if let Some(conn) = appdb_conn {
         let tx = conn.transaction()?;
-        let now_micro = now_utc_micro();
-
-        // Fix shelf timestamps
-        let fixed = tx.execute(
-            "UPDATE shelf SET created = ?1 WHERE created IS NULL",
-            [&now_micro],
-        )?;
-        if fixed > 0 {
-            println!(" -> Fixed {} shelves with missing created timestamp", fixed);
-        }
-
-        let fixed = tx.execute(
-            "UPDATE shelf SET last_modified = ?1 WHERE last_modified IS NULL",
-            [&now_micro],
-        )?;
-        if fixed > 0 {
-            println!(" -> Fixed {} shelves with missing last_modified timestamp", fixed);
-        }
-
-        // Fix book_shelf_link timestamps
-        let fixed = tx.execute(
-            "UPDATE book_shelf_link SET date_added = ?1 WHERE date_added IS NULL",
-            [&now_micro],
-        )?;
-        if fixed > 0 {
-            println!(" -> Fixed {} shelf links with missing date_added", fixed);
-        }
-
-        // Fix archived_book timestamps
-        let fixed = tx.execute(
-            "UPDATE archived_book SET last_modified = ?1 WHERE last_modified IS NULL",
-            [&now_micro],
-        )?;
-        if fixed > 0 {
-            println!(" -> Fixed {} archived books with missing last_modified", fixed);
-        }
-
-        // Fix kobo_reading_state timestamps
-        let fixed = tx.execute(
-            "UPDATE kobo_reading_state SET last_modified = ?1 WHERE last_modified IS NULL",
-            [&now_micro],
-        )?;
-        if fixed > 0 {
-            println!(" -> Fixed {} Kobo reading states with missing last_modified", fixed);
-        }
-
-        let fixed = tx.execute(
-            "UPDATE kobo_reading_state SET priority_timestamp = ?1 WHERE priority_timestamp IS NULL",
-            [&now_micro],
-        )?;
-        if fixed > 0 {
-            println!(" -> Fixed {} Kobo reading states with missing priority_timestamp", fixed);
-        }
-
-        // Fix kobo_bookmark timestamps
-        let fixed = tx.execute(
-            "UPDATE kobo_bookmark SET last_modified = ?1 WHERE last_modified IS NULL",
-            [&now_micro],
-        )?;
-        if fixed > 0 {
-            println!(" -> Fixed {} Kobo bookmarks with missing last_modified", fixed);
-        }
-
+        repair_null_timestamps(&tx, APPDB_TIMESTAMP_COLUMNS, &now_utc_micro(), repair)?;
         tx.commit()?;
     }
 
     Ok(())
 }
 
+/// File extensions (lowercase, without the dot) that can be added to the library.
+pub(crate) const BOOK_EXTENSIONS: &[&str] = &["epub", "kepub", "pdf", "mobi", "azw3", "txt"];
+
 /// Detect the book format and file extension from a path.
 /// Returns `(format, extension)` e.g. `("KEPUB", ".kepub")` or `("EPUB", ".epub")`.
 pub(crate) fn detect_book_format(path: &Path) -> Result<(&'static str, &'static str)> {
-    let path_str = path.to_string_lossy();
+    // Extensions match case-insensitively, so "Book.EPUB" is an EPUB
+    let path_str = path.to_string_lossy().to_lowercase();
     if path_str.ends_with(".kepub.epub") || path_str.ends_with(".kepub") {
         Ok(("KEPUB", ".kepub"))
     } else if path_str.ends_with(".epub") {
         Ok(("EPUB", ".epub"))
+    } else if path_str.ends_with(".pdf") {
+        Ok(("PDF", ".pdf"))
+    } else if path_str.ends_with(".mobi") {
+        Ok(("MOBI", ".mobi"))
+    } else if path_str.ends_with(".azw3") {
+        Ok(("AZW3", ".azw3"))
+    } else if path_str.ends_with(".txt") {
+        Ok(("TXT", ".txt"))
     } else {
-        anyhow::bail!("Unsupported file extension. File must end in .epub, .kepub, or .kepub.epub")
+        anyhow::bail!("Unsupported file extension. File must end in .epub, .kepub, .kepub.epub, .pdf, .mobi, .azw3, or .txt")
+    }
+}
+
+/// Returns true for formats whose metadata and cover can be read from the file itself.
+pub(crate) fn is_epub_format(format: &str) -> bool {
+    format == "EPUB" || format == "KEPUB"
+}
+
+/// Returns true if the path has one of the supported book extensions (case-insensitive).
+pub(crate) fn has_book_extension(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| BOOK_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Returns true if the error was caused by the disk running out of space (ENOSPC)
+pub(crate) fn is_storage_full(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::StorageFull)
+    })
+}
+
+/// Calculate the hash of a file with the given algorithm, as lowercase hex
+pub(crate) fn calculate_file_hash(file_path: &Path, algorithm: ChecksumAlgorithm) -> Result<String> {
+    match algorithm {
+        ChecksumAlgorithm::Sha1 => hash_file::<Sha1>(file_path),
+        ChecksumAlgorithm::Sha256 => hash_file::<Sha256>(file_path),
     }
 }
 
-/// Calculate SHA1 hash of a file
-pub(crate) fn calculate_file_hash(file_path: &Path) -> Result<String> {
+/// Returns true if both files have the same size and modification time. Much cheaper than hashing
+/// on a network share, but misses edits that keep the size and mtime, and only ever matches a
+/// library copy that kept its source's mtime (`add --preserve-timestamps`)
+pub(crate) fn same_size_and_mtime(a: &Path, b: &Path) -> Result<bool> {
+    let (a_meta, b_meta) = (fs::metadata(a)?, fs::metadata(b)?);
+    Ok(a_meta.len() == b_meta.len() && a_meta.modified()? == b_meta.modified()?)
+}
+
+fn hash_file<D: Digest>(file_path: &Path) -> Result<String> {
     let mut file = File::open(file_path)?;
-    let mut hasher = Sha1::new();
+    let mut hasher = D::new();
     let mut buffer = [0; 8192]; // 8KB buffer for reading chunks
     
     loop {
@@ -400,6 +709,70 @@ pub(crate) fn calculate_file_hash(file_path: &Path) -> Result<String> {
     Ok(hash.iter().map(|b| format!("{:02x}", b)).collect())
 }
 
+/// Escapes a string for embedding in a JSON string literal
+pub(crate) fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reads the (source, target) pairs of a mapping file such as `--lang-map-file`: either a JSON
+/// object (for `.json` files) or lines of `source<TAB>target`, with `#` comments.
+/// `kind` names the file in error messages.
+pub(crate) fn read_mapping_file(path: &Path, kind: &str) -> Result<Vec<(String, String)>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {} {:?}", kind, path))?;
+
+    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json")) {
+        return Ok(MAPPING_ENTRY_RE.captures_iter(&contents)
+            .map(|caps| (json_unescape(&caps[1]), json_unescape(&caps[2])))
+            .collect());
+    }
+    contents.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.split_once('\t')
+            .map(|(source, target)| (source.to_string(), target.to_string()))
+            .with_context(|| format!("Invalid {} line (expected source<TAB>target): {:?}", kind, line)))
+        .collect()
+}
+
+/// Reverses `json_escape`, decoding the escape sequences of a JSON string literal body
+pub(crate) fn json_unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some('u') => {
+                let code: String = chars.by_ref().take(4).collect();
+                if let Some(decoded) = u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+                    unescaped.push(decoded);
+                }
+            }
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
 /// Validates that an ID is positive and within reasonable bounds
 pub(crate) fn validate_id(id: i64, entity_type: &str) -> Result<()> {
     if id <= 0 {
@@ -483,7 +856,7 @@ pub(crate) fn backup_database(db_path: &Path, operation_name: &str) -> Result<Pa
             db_path, backup_path
         ))?;
     
-    println!(" -> Created database backup: {:?}", backup_path);
+    say!(" -> Created database backup: {:?}", backup_path);
     Ok(backup_path)
 }
 
@@ -515,4 +888,168 @@ pub(crate) fn validate_foreign_key(
     }
     
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_series_index() {
+        assert_eq!(parse_series_index("1.5"), Some(1.5));
+        assert_eq!(parse_series_index("1,5"), Some(1.5));
+        assert_eq!(parse_series_index(" 3 "), Some(3.0));
+        assert_eq!(parse_series_index("1,000"), Some(1000.0));
+        assert_eq!(parse_series_index("1,000,000"), Some(1_000_000.0));
+        assert_eq!(parse_series_index("1.000,5"), Some(1000.5));
+        assert_eq!(parse_series_index("1,000.5"), Some(1000.5));
+        assert_eq!(parse_series_index("12,25"), Some(12.25));
+        assert_eq!(parse_series_index("3,"), Some(3.0));
+        assert_eq!(parse_series_index("1,2,3"), None);
+        assert_eq!(parse_series_index("abc"), None);
+    }
+
+    #[test]
+    fn test_parse_db_timestamp() {
+        let expected = |s| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        assert_eq!(parse_db_timestamp("2024-01-02 03:04:05.123456+00:00"), Some(expected("2024-01-02T03:04:05.123456Z")));
+        assert_eq!(parse_db_timestamp("2024-01-02 03:04:05.123456"), Some(expected("2024-01-02T03:04:05.123456Z")));
+        assert_eq!(parse_db_timestamp("2024-01-02 05:04:05+02:00"), Some(expected("2024-01-02T03:04:05Z")));
+        assert_eq!(parse_db_timestamp("2024-01-02T03:04:05Z"), Some(expected("2024-01-02T03:04:05Z")));
+        assert_eq!(parse_db_timestamp("2024-01-02"), Some(expected("2024-01-02T00:00:00Z")));
+        assert_eq!(parse_db_timestamp("January 2nd, 2024"), None);
+    }
+
+    #[test]
+    fn test_series_index_candidates() {
+        assert_eq!(series_index_candidates("Saga #2"), vec![2.0]);
+        assert_eq!(series_index_candidates("The Expanse Book 3"), vec![3.0]);
+        assert_eq!(series_index_candidates("03 - Abaddon's Gate"), vec![3.0]);
+        assert_eq!(series_index_candidates("Dune (Vol. 1.5)"), vec![1.5]);
+        assert_eq!(series_index_candidates("Leviathan Wakes (1) - James S. A. Corey"), vec![1.0]);
+        assert_eq!(series_index_candidates("Book 2 - Part 4"), vec![2.0, 4.0]);
+        assert!(series_index_candidates("Nineteen Eighty-Four (1949)").is_empty());
+        assert!(series_index_candidates("Notebook 7").is_empty());
+    }
+
+    #[test]
+    fn test_isbn10_to_isbn13() {
+        assert_eq!(isbn10_to_isbn13("0306406152").as_deref(), Some("9780306406157"));
+        assert_eq!(isbn10_to_isbn13("080442957X").as_deref(), Some("9780804429573"));
+        assert_eq!(isbn10_to_isbn13("0-306-40615-2").as_deref(), Some("9780306406157"));
+        assert_eq!(isbn10_to_isbn13("0306406153"), None, "wrong check digit");
+        assert_eq!(isbn10_to_isbn13("030640615"), None, "too short");
+        assert_eq!(isbn10_to_isbn13("03064X6152"), None, "X only allowed as the check digit");
+        assert_eq!(isbn10_to_isbn13("9780306406157"), None, "already an ISBN-13");
+    }
+
+    #[test]
+    fn test_corporate_authors_are_not_inverted() {
+        use crate::models::AuthorSortMethod;
+        for name in ["O'Reilly Media", "No Starch Press", "Packt Publishing Ltd.", "Acme, Inc.", "Springer GmbH", "Oxford University Press"] {
+            assert!(is_corporate_author(name), "{} should be an organization", name);
+            assert_eq!(AuthorSortMethod::CalibreWeb.author_sort(name), name);
+            assert_eq!(AuthorSortMethod::Calibre { surname_prefixes: false }.author_sort(name), name);
+        }
+        assert!(!is_corporate_author("Pressley Smith"));
+        // Words that are also common surnames or name parts don't count on their own
+        assert!(!is_corporate_author("Ray Books"));
+        assert_eq!(AuthorSortMethod::CalibreWeb.author_sort("Jane Group"), "Group, Jane");
+        assert_eq!(AuthorSortMethod::CalibreWeb.author_sort("John Doe"), "Doe, John");
+        assert_eq!(AuthorSortMethod::AsIs.author_sort(" John Doe "), "John Doe");
+    }
+
+    #[test]
+    fn test_author_to_author_sort() {
+        let cases = [
+            ("John Doe", "Doe, John"),
+            ("John Ronald Reuel Tolkien", "Tolkien, John Ronald Reuel"),
+            ("Doe, John", "Doe, John"),
+            ("Plato", "Plato"),
+            ("", ""),
+            ("Martin Luther King Jr.", "King, Martin Luther Jr."),
+            ("Dr. John Smith", "Smith, John"),
+            ("Prof Dr. Jane Smith PhD", "Smith, Jane PhD"),
+            ("Henry Ford II", "Ford, Henry II"),
+            ("Mr. Jr.", "Mr. Jr."),
+            ("John Smith (Editor)", "Smith, John"),
+            ("Ludwig van Beethoven", "Beethoven, Ludwig van"),
+            ("Acme Software", "Acme Software"),
+            ("The Jim Henson Company", "The Jim Henson Company"),
+        ];
+        for (author, expected) in cases {
+            assert_eq!(author_to_author_sort(author, false), expected, "author: {:?}", author);
+        }
+
+        let prefix_cases = [
+            ("Ludwig van Beethoven", "van Beethoven, Ludwig"),
+            ("Leonardo da Vinci", "da Vinci, Leonardo"),
+            ("Van Gogh", "Van Gogh"),
+            ("John Doe", "Doe, John"),
+        ];
+        for (author, expected) in prefix_cases {
+            assert_eq!(author_to_author_sort(author, true), expected, "author: {:?}", author);
+        }
+    }
+
+    #[test]
+    fn test_json_escape() {
+        assert_eq!(json_escape("plain"), "plain");
+        assert_eq!(json_escape(r#"a "quoted" \path"#), r#"a \"quoted\" \\path"#);
+        assert_eq!(json_escape("line\nbreak\t"), "line\\nbreak\\t");
+        assert_eq!(json_escape("\u{1}"), "\\u0001");
+    }
+
+    #[test]
+    fn test_json_unescape_round_trip() {
+        for value in ["plain", r#"a "quoted" \path"#, "line\nbreak\t", "\u{1}", "Émile/Zola"] {
+            assert_eq!(json_unescape(&json_escape(value)), value);
+        }
+    }
+
+    #[test]
+    fn test_render_book_path() {
+        let mut metadata = BookMetadata {
+            title: "Dune: Messiah".to_string(),
+            author: "Frank Herbert".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(render_book_path(DEFAULT_PATH_PATTERN, &metadata, "7"), "Frank Herbert/Dune_ Messiah (7)");
+        assert_eq!(render_book_path("{author}/{series} - {title} ({id})", &metadata, "7"), "Frank Herbert/Dune_ Messiah (7)");
+        metadata.series = Some("Dune".to_string());
+        metadata.series_index = Some(2.0);
+        assert_eq!(render_book_path("{author}/{series} {series_index} - {title} ({id})", &metadata, "7"), "Frank Herbert/Dune 2 - Dune_ Messiah (7)");
+        let sharded = format!("{}{}", SHARD_PATH_PREFIX, DEFAULT_PATH_PATTERN);
+        assert_eq!(render_book_path(&sharded, &metadata, "12345"), "345/Frank Herbert/Dune_ Messiah (12345)");
+        assert_eq!(render_book_path(&sharded, &metadata, "7"), "007/Frank Herbert/Dune_ Messiah (7)");
+    }
+
+    #[test]
+    fn test_validate_path_pattern() {
+        assert!(validate_path_pattern(DEFAULT_PATH_PATTERN).is_ok());
+        assert!(validate_path_pattern("{author}/{title}").is_err());
+        assert!(validate_path_pattern("{author}/{isbn} ({id})").is_err());
+        assert!(validate_path_pattern("../{title} ({id})").is_err());
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        assert_eq!(normalize_text("  Padded Title  "), "Padded Title");
+        assert_eq!(normalize_text("Double  Spaced\t Title"), "Double Spaced Title");
+        assert_eq!(normalize_text("   "), "");
+        #[cfg(feature = "nfc")]
+        assert_eq!(normalize_text("Cafe\u{301}"), "Caf\u{E9}");
+    }
+
+    #[test]
+    fn test_series_index_helpers() {
+        assert_eq!(format_series_index(3.0), "3");
+        assert_eq!(format_series_index(1.5), "1.5");
+        assert_eq!(format_series_index(2.25), "2.25");
+        assert_eq!(format_series_index(2.9999999), "3");
+        assert_eq!(format_series_index(1.001), "1");
+        assert_eq!(format_series_index(1.999), "2");
+        assert_eq!(snap_series_index(2.9999999), 3.0);
+        assert_eq!(snap_series_index(1.5), 1.5);
+    }
+}